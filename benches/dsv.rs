@@ -0,0 +1,87 @@
+use std::fs;
+use std::io::BufReader;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+use tabular::dsv::{from_file, read_rows, ByteRecord, StringRecord, CSV};
+
+fn read_medium(c: &mut Criterion) {
+    c.bench_function("read_medium", |b| {
+        b.iter(|| {
+            for _ in from_file(CSV, "data/medium.csv").unwrap() {}
+        })
+    });
+}
+
+fn read_short(c: &mut Criterion) {
+    c.bench_function("read_short", |b| {
+        b.iter(|| {
+            for _ in from_file(CSV, "data/short.csv").unwrap() {}
+        })
+    });
+}
+
+fn read_medium_record(c: &mut Criterion) {
+    c.bench_function("read_medium_record", |b| {
+        b.iter(|| {
+            let mut rows = from_file(CSV, "data/medium.csv").unwrap();
+            let mut record = StringRecord::new();
+            while rows.read_record(&mut record).unwrap() {}
+        })
+    });
+}
+
+/// Throughput of reading records from memory with the DFA, the character reader and one byte at a time
+fn read_medium_throughput(c: &mut Criterion) {
+    let data = fs::read("data/medium.csv").unwrap();
+    let mut group = c.benchmark_group("medium_throughput");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("byte_record", |b| {
+        b.iter(|| {
+            let mut rows = read_rows(CSV, &data[..]);
+            let mut record = ByteRecord::new();
+            while rows.read_byte_record(&mut record).unwrap() {}
+        })
+    });
+    group.bench_function("byte_record_char_reader", |b| {
+        b.iter(|| {
+            let mut rows = read_rows(CSV, &data[..]).without_dfa();
+            let mut record = ByteRecord::new();
+            while rows.read_byte_record(&mut record).unwrap() {}
+        })
+    });
+    group.bench_function("byte_record_unbuffered", |b| {
+        b.iter(|| {
+            let mut rows = read_rows(CSV, BufReader::with_capacity(1, &data[..]));
+            let mut record = ByteRecord::new();
+            while rows.read_byte_record(&mut record).unwrap() {}
+        })
+    });
+    group.finish();
+}
+
+/// Throughput of reading records with long unquoted and quoted text columns with the DFA and the character reader
+fn read_long_columns_throughput(c: &mut Criterion) {
+    let text = "Lorem ipsum dolor sit amet consectetur adipiscing elit sed do eiusmod tempor ".repeat(4);
+    let data = format!("{0},\"{0}\",{0}\r\n", text).repeat(2000).into_bytes();
+    let mut group = c.benchmark_group("long_columns_throughput");
+    group.throughput(Throughput::Bytes(data.len() as u64));
+    group.bench_function("byte_record", |b| {
+        b.iter(|| {
+            let mut rows = read_rows(CSV, &data[..]);
+            let mut record = ByteRecord::new();
+            while rows.read_byte_record(&mut record).unwrap() {}
+        })
+    });
+    group.bench_function("byte_record_char_reader", |b| {
+        b.iter(|| {
+            let mut rows = read_rows(CSV, &data[..]).without_dfa();
+            let mut record = ByteRecord::new();
+            while rows.read_byte_record(&mut record).unwrap() {}
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read_medium, read_short, read_medium_record, read_medium_throughput, read_long_columns_throughput);
+criterion_main!(benches);
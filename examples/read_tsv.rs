@@ -1,11 +1,9 @@
-
 extern crate tabular;
 
 use tabular::dsv;
 
 fn main() {
-    let path = Path::new("data/short.tsv");
-    for row in dsv::from_file(dsv::TSV, &path) {
-        println!("row = {}", row);
+    for row in dsv::from_file(dsv::TSV, "data/short.tsv").unwrap() {
+        println!("row = {:?}", row);
     }
 }
@@ -1,8 +1,10 @@
-use std::io;
-use std::io::IoError;
+use std::io::{self, BufRead, Write};
+use std::str;
+
+pub use self::LineTerminator::*;
 
 /// Line terminator
-#[deriving(Eq, PartialEq, Clone)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum LineTerminator {
     /// Line feed ('\n')
     LF,
@@ -28,24 +30,98 @@ impl LineTerminator {
             LF => "\n",
             CR => "\r",
             CRLF => "\r\n",
-            VT => "\u000B",
-            FF => "\u000C",
-            NEL => "\u0085",
-            LS => "\u2028",
-            PS => "\u2029",
+            VT => "\u{000B}",
+            FF => "\u{000C}",
+            NEL => "\u{0085}",
+            LS => "\u{2028}",
+            PS => "\u{2029}",
         }
     }
 
     pub fn is_beginning(&self, ch: char) -> bool {
-        ch == self.as_str().char_at(0)
+        self.as_str().starts_with(ch)
+    }
+
+    pub fn is_end(&self, ch: char) -> bool {
+        self.as_str().ends_with(ch)
     }
 }
 
 /// One row with columns
 pub type Row = Vec<String>;
 
-pub static INVALID_LINE_ENDING: IoError = IoError {
-    kind: io::InvalidInput,
-    desc: "Invalid line ending",
-    detail: None
-};
+/// One row with columns that can be null
+pub type NullableRow = Vec<Option<String>>;
+
+fn utf8_char_width(b: u8) -> usize {
+    match b {
+        0x00..=0x7F => 1,
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => 0,
+    }
+}
+
+/// Character read as raw bytes, `ch` is `None` for a sequence that is not valid UTF-8
+#[derive(Clone, Copy)]
+pub struct RawChar {
+    pub ch: Option<char>,
+    bytes: [u8; 4],
+    len: usize,
+}
+
+impl RawChar {
+    pub fn from_char(ch: char) -> RawChar {
+        let mut raw = RawChar {ch: Some(ch), bytes: [0; 4], len: ch.len_utf8()};
+        ch.encode_utf8(&mut raw.bytes);
+        raw
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Read a single character without failing on invalid UTF-8, `None` at the end of data
+///
+/// Invalid sequences never include bytes that could start another character, so
+/// ASCII delimiters and line terminators following them are always found.
+pub fn read_raw_char<R: BufRead + ?Sized>(reader: &mut R) -> io::Result<Option<RawChar>> {
+    let buf = reader.fill_buf()?;
+    let first = match buf.first() {
+        Some(&b) => b,
+        None => return Ok(None),
+    };
+    let width = utf8_char_width(first);
+    let mut raw = RawChar {ch: None, bytes: [first, 0, 0, 0], len: 1};
+    if width > 0 && buf.len() >= width {
+        if let Ok(s) = str::from_utf8(&buf[..width]) {
+            raw.bytes[..width].copy_from_slice(&buf[..width]);
+            raw.len = width;
+            raw.ch = s.chars().next();
+            reader.consume(width);
+            return Ok(Some(raw))
+        }
+    }
+    reader.consume(1);
+    while raw.len < width {
+        match reader.fill_buf()?.first() {
+            Some(&b) if b & 0xC0 == 0x80 => {
+                raw.bytes[raw.len] = b;
+                raw.len += 1;
+                reader.consume(1);
+            }
+            _ => break
+        }
+    }
+    if raw.len == width {
+        raw.ch = str::from_utf8(raw.as_bytes()).ok().and_then(|s| s.chars().next());
+    }
+    Ok(Some(raw))
+}
+
+pub fn write_char<W: Write + ?Sized>(writer: &mut W, ch: char) -> io::Result<()> {
+    let mut buf = [0u8; 4];
+    writer.write_all(ch.encode_utf8(&mut buf).as_bytes())
+}
@@ -0,0 +1,391 @@
+//! Deserialization of rows into user types with serde
+use std::error;
+use std::fmt;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, SeqAccess, Visitor};
+
+use crate::dsv::{Headers, Null, NoNull, StringRecord};
+use crate::error::{Error, Position, Result};
+
+#[derive(Debug)]
+struct DeError(String);
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T: fmt::Display>(msg: T) -> DeError {
+        DeError(msg.to_string())
+    }
+}
+
+type DeResult<T> = ::std::result::Result<T, DeError>;
+
+/// Deserialize a row, structs are matched by header name when headers are given, by position otherwise
+///
+/// Null columns are `None`, empty columns too when the data has no null representation.
+pub fn deserialize_row<D: DeserializeOwned>(row: &StringRecord, headers: Option<&Headers>, null: Null, position: Position) -> Result<D> {
+    let mut de = RowDeserializer {
+        row,
+        headers,
+        null,
+        next: 0,
+        in_struct: false
+    };
+    D::deserialize(&mut de).map_err(|DeError(msg)| {
+        let field = de.next.saturating_sub(1);
+        let msg = match headers.and_then(|headers| headers.get(field)) {
+            Some(name) => format!("column {:?}: {}", name, msg),
+            None => msg
+        };
+        Error::Deserialize(msg, Position {field: field as u64, ..position})
+    })
+}
+
+struct RowDeserializer<'r> {
+    row: &'r StringRecord,
+    headers: Option<&'r Headers>,
+    null: Null,
+    next: usize,
+    in_struct: bool,
+}
+
+impl<'r> RowDeserializer<'r> {
+    fn has_next(&self) -> bool {
+        self.next < self.row.len()
+    }
+
+    fn peek_field(&self) -> Option<&'r str> {
+        self.row.get(self.next)
+    }
+
+    fn next_field(&mut self) -> DeResult<&'r str> {
+        match self.peek_field() {
+            Some(col) => {
+                self.next += 1;
+                Ok(col)
+            }
+            None => {
+                self.next += 1;
+                Err(de::Error::custom("expected a column, found end of row"))
+            }
+        }
+    }
+
+    fn parse<T>(&mut self, expected: &str) -> DeResult<T>
+        where T: ::std::str::FromStr, T::Err: fmt::Display {
+        let col = self.next_field()?;
+        col.parse().map_err(|err| de::Error::custom(format!("cannot parse {:?} as {}: {}", col, expected, err)))
+    }
+}
+
+macro_rules! deserialize_parse {
+    ($($method:ident => $visit:ident $ty:ty,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+                visitor.$visit(self.parse::<$ty>(stringify!($ty))?)
+            }
+        )*
+    }
+}
+
+impl<'a, 'de, 'r> de::Deserializer<'de> for &'a mut RowDeserializer<'r> {
+    type Error = DeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_str(self.next_field()?)
+    }
+
+    deserialize_parse! {
+        deserialize_bool => visit_bool bool,
+        deserialize_i8 => visit_i8 i8,
+        deserialize_i16 => visit_i16 i16,
+        deserialize_i32 => visit_i32 i32,
+        deserialize_i64 => visit_i64 i64,
+        deserialize_i128 => visit_i128 i128,
+        deserialize_u8 => visit_u8 u8,
+        deserialize_u16 => visit_u16 u16,
+        deserialize_u32 => visit_u32 u32,
+        deserialize_u64 => visit_u64 u64,
+        deserialize_u128 => visit_u128 u128,
+        deserialize_f32 => visit_f32 f32,
+        deserialize_f64 => visit_f64 f64,
+        deserialize_char => visit_char char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_str(self.next_field()?)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_str(self.next_field()?)
+    }
+
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_bytes(self.next_field()?.as_bytes())
+    }
+
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_bytes(self.next_field()?.as_bytes())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        let null = match self.peek_field() {
+            None => return visitor.visit_none(),
+            // Empty and null columns are told apart only with a null representation
+            Some(col) => self.row.is_null(self.next) || self.null == NoNull && col.is_empty()
+        };
+        if null {
+            self.next += 1;
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        match self.next_field()? {
+            "" => visitor.visit_unit(),
+            col => Err(de::Error::custom(format!("expected an empty column, found {:?}", col)))
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_seq(Columns {de: self, len: None})
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> DeResult<V::Value> {
+        visitor.visit_seq(Columns {de: self, len: Some(len)})
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        match self.headers {
+            Some(headers) if !self.in_struct => {
+                self.in_struct = true;
+                visitor.visit_map(Named {de: self, headers})
+            }
+            _ => Err(de::Error::custom("deserializing a map requires headers"))
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V) -> DeResult<V::Value> {
+        match self.headers {
+            Some(headers) if !self.in_struct => {
+                self.in_struct = true;
+                visitor.visit_map(Named {de: self, headers})
+            }
+            _ => {
+                self.in_struct = true;
+                visitor.visit_seq(Columns {de: self, len: Some(fields.len())})
+            }
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> DeResult<V::Value> {
+        visitor.visit_enum(self.next_field()?.into_deserializer())
+    }
+
+    fn deserialize_identifier<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> DeResult<V::Value> {
+        self.next_field()?;
+        visitor.visit_unit()
+    }
+}
+
+/// Columns accessed by position
+struct Columns<'a, 'r: 'a> {
+    de: &'a mut RowDeserializer<'r>,
+    len: Option<usize>,
+}
+
+impl<'a, 'de, 'r> SeqAccess<'de> for Columns<'a, 'r> {
+    type Error = DeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> DeResult<Option<T::Value>> {
+        match self.len {
+            Some(0) => return Ok(None),
+            Some(ref mut len) => *len -= 1,
+            None if !self.de.has_next() => return Ok(None),
+            None => ()
+        }
+        seed.deserialize(&mut *self.de).map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        self.len
+    }
+}
+
+/// Columns accessed by header name
+struct Named<'a, 'r: 'a> {
+    de: &'a mut RowDeserializer<'r>,
+    headers: &'r Headers,
+}
+
+impl<'a, 'de, 'r> MapAccess<'de> for Named<'a, 'r> {
+    type Error = DeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> DeResult<Option<K::Value>> {
+        if !self.de.has_next() {
+            return Ok(None)
+        }
+        match self.headers.get(self.de.next) {
+            Some(name) => seed.deserialize(name.into_deserializer()).map(Some),
+            None => Ok(None)
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> DeResult<V::Value> {
+        seed.deserialize(&mut *self.de)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use serde::Deserialize;
+
+    use crate::dsv::{from_str, Config, ConfigBuilder, NullMarker, RejectWriter, UnquotedEmpty, CSV};
+    use crate::error::{Error, Position, Result};
+
+    static HEADERS: Config = Config {has_headers: true, ..CSV};
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    enum Team {
+        #[serde(rename = "DAL")]
+        Dallas,
+        #[serde(rename = "NYG")]
+        Giants,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Play {
+        qtr: u8,
+        off: Team,
+        down: Option<u8>,
+        yards: f64,
+        touchdown: bool,
+    }
+
+    fn deserialize_all<T: serde::de::DeserializeOwned>(config: Config, s: &str) -> Result<Vec<T>> {
+        from_str(config, s).deserialize().collect()
+    }
+
+    #[test]
+    fn struct_by_header_name() {
+        let plays: Vec<Play> = deserialize_all(HEADERS, "touchdown,off,qtr,yards,down\r\nfalse,DAL,1,20.5,\r\ntrue,NYG,4,-3,2").unwrap();
+        assert_eq!(plays, vec!(Play {qtr: 1, off: Team::Dallas, down: None, yards: 20.5, touchdown: false},
+                               Play {qtr: 4, off: Team::Giants, down: Some(2), yards: -3.0, touchdown: true}));
+    }
+
+    #[test]
+    fn struct_by_position() {
+        let plays: Vec<Play> = deserialize_all(CSV, "1,DAL,,20.5,false").unwrap();
+        assert_eq!(plays, vec!(Play {qtr: 1, off: Team::Dallas, down: None, yards: 20.5, touchdown: false}));
+    }
+
+    #[test]
+    fn missing_option_column_by_name() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Row {
+            a: String,
+            b: Option<i32>,
+        }
+        let rows: Vec<Row> = deserialize_all(HEADERS, "a,c\r\nfoo,bar").unwrap();
+        assert_eq!(rows, vec!(Row {a: "foo".to_string(), b: None}));
+    }
+
+    #[test]
+    fn nested_tuples() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct Game {
+            id: String,
+            score: (u32, u32),
+            season: u16,
+        }
+        let games: Vec<Game> = deserialize_all(CSV, "20120905_DAL@NYG,24,17,2012").unwrap();
+        assert_eq!(games, vec!(Game {id: "20120905_DAL@NYG".to_string(), score: (24, 17), season: 2012}));
+        type Nested = (String, (i8, Option<char>), Vec<u8>);
+        let rows: Vec<Nested> = deserialize_all(CSV, "a,-1,,1,2,3").unwrap();
+        assert_eq!(rows, vec!(("a".to_string(), (-1, None), vec!(1, 2, 3))));
+    }
+
+    #[test]
+    fn map_by_header_name() {
+        let rows: Vec<HashMap<String, String>> = deserialize_all(HEADERS, "a,b\r\n1,2").unwrap();
+        assert_eq!(rows[0].get("b").map(|s| &s[..]), Some("2"));
+    }
+
+    #[test]
+    fn null_columns_are_none() {
+        let config = ConfigBuilder::new(CSV).null(UnquotedEmpty).build();
+        let rows: Vec<(Option<String>, Option<String>)> = deserialize_all(config, ",\"\"").unwrap();
+        assert_eq!(rows, vec!((None, Some("".to_string()))));
+        let config = ConfigBuilder::new(CSV).null(NullMarker("NULL")).build();
+        let rows: Vec<(Option<String>, Option<String>)> = deserialize_all(config, ",NULL").unwrap();
+        assert_eq!(rows, vec!((Some("".to_string()), None)));
+        let rows: Vec<(Option<String>, Option<String>)> = deserialize_all(CSV, ",\"\"").unwrap();
+        assert_eq!(rows, vec!((None, None)));
+    }
+
+    #[test]
+    fn error_identifies_column() {
+        let res: Result<Vec<Play>> = deserialize_all(HEADERS, "qtr,off,down,yards,touchdown\r\n1,DAL,,20.5,false\r\n1,DAL,x,20.5,false");
+        match res {
+            Err(Error::Deserialize(msg, position)) => {
+                assert_eq!(position, Position {record: 2, field: 2, line: 3, byte: 49});
+                assert!(msg.starts_with("column \"down\""), "{}", msg);
+            }
+            res => panic!("Unexpected result: {:?}", res)
+        }
+    }
+
+    #[test]
+    fn error_on_missing_columns() {
+        let res: Result<Vec<(u8, u8)>> = deserialize_all(CSV, "1");
+        match res {
+            Err(Error::Deserialize(_, position)) => assert_eq!(position.field, 1),
+            res => panic!("Unexpected result: {:?}", res)
+        }
+    }
+
+    #[test]
+    fn position_of_row_after_skipped_lines() {
+        let res: Result<Vec<(u8, u8)>> = deserialize_all(CSV, "1,2\r\n\r\n\r\n3,x");
+        match res {
+            Err(Error::Deserialize(_, position)) => assert_eq!(position, Position {record: 1, field: 1, line: 4, byte: 9}),
+            res => panic!("Unexpected result: {:?}", res)
+        }
+    }
+
+    #[test]
+    fn rows_that_can_not_be_deserialized_are_quarantined() {
+        let mut rows = from_str(HEADERS, "a,b\r\n1,2\r\n3,x\r\n\"4\"5,6\r\n7,8").deserialize::<(u8, u8)>()
+            .quarantine(RejectWriter::new(CSV, Vec::new()));
+        assert_eq!(rows.by_ref().collect::<Result<Vec<_>>>().unwrap(), vec!((1, 2), (7, 8)));
+        let rejects: Vec<_> = from_str(CSV, &String::from_utf8(rows.into_rejects().into_inner()).unwrap()).map(|row| row.unwrap()).collect();
+        assert_eq!(rejects.iter().map(|row| &row[0][..]).collect::<Vec<_>>(), vec!("3,x", "\"4\"5,6"));
+        assert!(rejects[0][1].starts_with("Cannot deserialize row, column \"b\""), "{}", rejects[0][1]);
+    }
+}
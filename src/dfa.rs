@@ -0,0 +1,607 @@
+//! Table-driven byte-level DFA reading DSV records directly from the buffer of the reader
+//!
+//! States follow `fsm.dot`. A record is consumed only when it is complete within the buffer and
+//! valid, anything else (errors, records crossing the end of the buffer or the data, comments,
+//! invalid UTF-8) is left to the character reader of `dsv`, which reports errors with exact positions.
+//!
+//! Runs of column data are scanned with `memchr` for the up to three bytes that can end them, which
+//! uses the SIMD instructions available on the CPU at runtime and falls back to the `runs` table.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::str;
+
+use memchr::{memchr, memchr2, memchr3};
+
+use crate::common::{CR, CRLF, LF};
+use crate::dsv::{Config, Utf8, Double, Char, Disallowed, Exact, Universal, Never, PerColumn, NoTrim, Null, NoNull, NullMarker, UnquotedEmpty};
+use crate::error::{Position, Result};
+use crate::record::{BorrowedRecord, ByteRecord};
+
+/// Byte classes
+const OTHER: usize = 0;
+const DELIMITER: usize = 1;
+const QUOTE: usize = 2;
+const ESCAPE: usize = 3;
+const NEWLINE: usize = 4;
+const RETURN: usize = 5;
+const CLASSES: usize = 6;
+
+/// State between two bytes of data
+pub type State = u8;
+
+/// States
+const READ_COLUMN: u8 = 0;
+const UNQUOTED: u8 = 1;
+const QUOTED: u8 = 2;
+const ESCAPED: u8 = 3;
+const QUOTED_END: u8 = 4;
+const END_OR_ESCAPED: u8 = 5;
+/// Carriage return ended the column, the row ends after an optional line feed
+const RETURNED: u8 = 6;
+pub const STATES: usize = 7;
+
+/// State at the start of a row
+pub const ROW_START: State = READ_COLUMN;
+
+/// Action taken on the byte of a transition
+#[derive(Clone, Copy, PartialEq)]
+enum Action {
+    /// Byte is part of the column
+    Push,
+    /// Byte is consumed without output
+    Skip,
+    /// Byte opens a quoted column
+    Open,
+    /// Byte ends the column
+    EndColumn,
+    /// Byte ends the column and the row
+    EndRow,
+    /// Byte ends the column, the row ends after the next byte if it is a line feed
+    EndReturn,
+    /// Byte ends the row after a carriage return
+    Done,
+    /// Row ended with the carriage return before this byte, which is not consumed
+    DoneBefore,
+    /// Data is not valid, left for the character reader
+    Fail,
+}
+
+use self::Action::*;
+
+#[derive(Clone, Copy)]
+struct Transition {
+    next: u8,
+    action: Action,
+}
+
+const FAIL: Transition = Transition {next: READ_COLUMN, action: Fail};
+
+fn to(next: u8, action: Action) -> Transition {
+    Transition {next, action}
+}
+
+/// DFA for one configuration
+#[derive(Clone)]
+pub struct Dfa {
+    classes: [u8; 256],
+    table: [[Transition; CLASSES]; STATES],
+    /// Bytes pushed without leaving each state
+    runs: [[bool; 256]; STATES],
+    /// Bytes ending the runs of each state
+    stops: [Stops; STATES],
+    comment: Option<u8>,
+    /// Character preceding escaped quotes in quoted columns
+    marker: char,
+    null: Null,
+    lines: LineCount,
+}
+
+/// Bytes counted as line ends for positions
+#[derive(Clone, Copy)]
+enum LineCount {
+    Newlines,
+    Returns,
+    Both,
+}
+
+/// Length of runs scanned with the table before searching for their end
+const SHORT_RUN: usize = 16;
+
+/// Bytes ending a run, searched for with `memchr` when there are at most three
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Stops {
+    One(u8),
+    Two(u8, u8),
+    Three(u8, u8, u8),
+    Table,
+}
+
+impl Stops {
+    fn new(run: &[bool; 256]) -> Stops {
+        let mut stops = (0..=255u8).filter(|&b| !run[b as usize]);
+        match (stops.next(), stops.next(), stops.next(), stops.next()) {
+            (Some(a), None, _, _) => Stops::One(a),
+            (Some(a), Some(b), None, _) => Stops::Two(a, b),
+            (Some(a), Some(b), Some(c), None) => Stops::Three(a, b, c),
+            _ => Stops::Table
+        }
+    }
+}
+
+fn ascii(ch: char) -> Option<u8> {
+    if ch.is_ascii() { Some(ch as u8) } else { None }
+}
+
+impl Dfa {
+    /// DFA for the configuration, `None` if it uses features only the character reader supports
+    pub fn new(config: &Config) -> Option<Dfa> {
+        if config.trim != NoTrim || config.skip_initial_space || matches!(config.quote, PerColumn(_)) {
+            return None
+        }
+        let delimiter = ascii(config.delimiter)?;
+        let quote = if config.quote == Never { None } else { Some(ascii(config.quote_char)?) };
+        let escape = match config.escape {
+            Char(ch) if ch != config.quote_char => Some(ascii(ch)?),
+            Double | Char(_) | Disallowed => None,
+            _ => return None
+        };
+        let comment = match config.comment {
+            Some(ch) => Some(ascii(ch)?),
+            None => None
+        };
+        let special = [Some(delimiter), quote, escape];
+        if special.iter().flatten().any(|&b| b == b'\n' || b == b'\r')
+            || Some(delimiter) == quote || Some(delimiter) == escape {
+            return None
+        }
+        let lines = match (config.newlines, config.line_terminator) {
+            (Exact, LF) | (Exact, CRLF) => LineCount::Newlines,
+            (Exact, CR) => LineCount::Returns,
+            (Universal, _) => LineCount::Both,
+            _ => return None
+        };
+
+        let mut classes = [OTHER as u8; 256];
+        classes[b'\n' as usize] = NEWLINE as u8;
+        classes[b'\r' as usize] = RETURN as u8;
+        classes[delimiter as usize] = DELIMITER as u8;
+        if let Some(quote) = quote {
+            classes[quote as usize] = QUOTE as u8;
+        }
+        if let Some(escape) = escape {
+            classes[escape as usize] = ESCAPE as u8;
+        }
+
+        // Transitions on line feed and carriage return where a column can end, `None` if the byte is data
+        let (newline, ret) = match (config.newlines, config.line_terminator) {
+            (Exact, LF) => (Some(to(READ_COLUMN, EndRow)), None),
+            (Exact, CR) => (None, Some(to(READ_COLUMN, EndRow))),
+            (Exact, _) => (None, Some(to(RETURNED, EndReturn))),
+            _ => (Some(to(READ_COLUMN, EndRow)), Some(to(RETURNED, EndReturn)))
+        };
+        let push = to(UNQUOTED, Push);
+        let end_column = to(READ_COLUMN, EndColumn);
+
+        let mut table = [[FAIL; CLASSES]; STATES];
+        table[READ_COLUMN as usize] = [push, end_column, to(QUOTED, Open), push, newline.unwrap_or(push), ret.unwrap_or(push)];
+        table[UNQUOTED as usize] = [push, end_column, push, push, newline.unwrap_or(push), ret.unwrap_or(push)];
+        let quoted = to(QUOTED, Push);
+        let closing = if escape.is_none() && config.escape != Disallowed { to(END_OR_ESCAPED, Skip) } else { to(QUOTED_END, Skip) };
+        table[QUOTED as usize] = [quoted, quoted, closing, to(ESCAPED, Skip), quoted, quoted];
+        table[ESCAPED as usize][QUOTE] = quoted;
+        let quoted_end = [FAIL, end_column, FAIL, FAIL, newline.unwrap_or(FAIL), ret.unwrap_or(FAIL)];
+        table[QUOTED_END as usize] = quoted_end;
+        table[END_OR_ESCAPED as usize] = quoted_end;
+        table[END_OR_ESCAPED as usize][QUOTE] = quoted;
+        let after_return = if config.newlines == Exact { FAIL } else { to(READ_COLUMN, DoneBefore) };
+        table[RETURNED as usize] = [after_return; CLASSES];
+        table[RETURNED as usize][NEWLINE] = to(READ_COLUMN, Done);
+
+        let mut runs = [[false; 256]; STATES];
+        for (state, run) in runs.iter_mut().enumerate() {
+            for (b, pushed) in run.iter_mut().enumerate() {
+                let transition = table[state][classes[b] as usize];
+                *pushed = transition.action == Push && transition.next as usize == state;
+            }
+        }
+        let stops = runs.map(|run| Stops::new(&run));
+
+        let marker = escape.or(quote).map_or(config.quote_char, char::from);
+
+        Some(Dfa {classes, table, runs, stops, comment, marker, null: config.null, lines})
+    }
+
+    /// Read the next row into the empty record if it is complete in the buffer of reader
+    ///
+    /// Returns false without consuming anything when the row has to be read by the character reader.
+    /// A blank line is read as a record without columns.
+    pub fn read_record<R: BufRead + ?Sized>(&self, reader: &mut R, record: &mut ByteRecord, utf8: Option<Utf8>, position: &mut Position) -> Result<bool> {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() || self.comment.is_some() && buf.first() == self.comment.as_ref() {
+            return Ok(false)
+        }
+        let mut state = READ_COLUMN;
+        let mut quoted = false;
+        let mut end = None;
+        let mut i = 0;
+        while i < buf.len() {
+            let transition = self.table[state as usize][self.classes[buf[i] as usize] as usize];
+            match transition.action {
+                Push => {
+                    // Bytes that stay in the same state are pushed at once
+                    let start = i;
+                    i = self.run_end(transition.next, buf, i + 1);
+                    record.push_bytes(&buf[start..i]);
+                    state = transition.next;
+                    continue
+                }
+                Skip => (),
+                Open => quoted = true,
+                EndColumn => {
+                    self.end_column(record, quoted);
+                    quoted = false;
+                }
+                EndRow | EndReturn => {
+                    if !record.is_empty() || state != READ_COLUMN || quoted {
+                        self.end_column(record, quoted);
+                    }
+                    quoted = false;
+                    if transition.action == EndRow {
+                        end = Some(i + 1);
+                        break
+                    }
+                }
+                Done => {
+                    end = Some(i + 1);
+                    break
+                }
+                DoneBefore => {
+                    end = Some(i);
+                    break
+                }
+                Fail => break
+            }
+            state = transition.next;
+            i += 1;
+        }
+        let valid = match end {
+            Some(end) => utf8.is_none() || str::from_utf8(&buf[..end]).is_ok(),
+            None => false
+        };
+        let end = match end {
+            Some(end) if valid => end,
+            _ => {
+                record.clear();
+                return Ok(false)
+            }
+        };
+        let lines = self.count_lines(&buf[..end]);
+        reader.consume(end);
+        position.byte += end as u64;
+        position.line += lines;
+        position.field = 0;
+        Ok(true)
+    }
+
+    /// Read the next row from data into the empty record if it is complete and valid UTF-8
+    ///
+    /// Columns are borrowed from data, except quoted columns with escaped quotes which are decoded.
+    /// Returns the length of the row, `None` if it has to be read by the character reader.
+    pub fn read_borrowed<'a>(&self, data: &'a [u8], record: &mut BorrowedRecord<'a>, position: &mut Position) -> Option<usize> {
+        let end = match self.split_borrowed(data, record) {
+            Some(end) => end,
+            None => {
+                record.clear();
+                return None
+            }
+        };
+        position.byte += end as u64;
+        position.line += self.count_lines(&data[..end]);
+        position.field = 0;
+        Some(end)
+    }
+
+    fn split_borrowed<'a>(&self, data: &'a [u8], record: &mut BorrowedRecord<'a>) -> Option<usize> {
+        if data.is_empty() || self.comment.is_some() && data.first() == self.comment.as_ref() {
+            return None
+        }
+        let mut state = READ_COLUMN;
+        let (mut start, mut quoted, mut escaped) = (0, false, false);
+        let mut i = 0;
+        while i < data.len() {
+            let transition = self.table[state as usize][self.classes[data[i] as usize] as usize];
+            match transition.action {
+                Push => {
+                    escaped |= state == ESCAPED || state == END_OR_ESCAPED;
+                    state = transition.next;
+                    i = self.run_end(state, data, i + 1);
+                    continue
+                }
+                Skip => (),
+                Open => {
+                    quoted = true;
+                    start = i + 1;
+                }
+                EndColumn | EndRow | EndReturn => {
+                    if transition.action == EndColumn || !record.is_empty() || state != READ_COLUMN || quoted {
+                        // Closing quote precedes the end of a quoted column
+                        let end = if quoted { i - 1 } else { i };
+                        self.push_borrowed(record, &data[start..end], quoted, escaped)?;
+                    }
+                    (start, quoted, escaped) = (i + 1, false, false);
+                    if transition.action == EndRow {
+                        return Some(i + 1)
+                    }
+                }
+                Done => return Some(i + 1),
+                DoneBefore => return Some(i),
+                Fail => return None
+            }
+            state = transition.next;
+            i += 1;
+        }
+        None
+    }
+
+    fn push_borrowed<'a>(&self, record: &mut BorrowedRecord<'a>, raw: &'a [u8], quoted: bool, escaped: bool) -> Option<()> {
+        let col = str::from_utf8(raw).ok()?;
+        let null = !quoted && match self.null {
+            NullMarker(marker) => col == marker,
+            UnquotedEmpty => col.is_empty(),
+            NoNull => false
+        };
+        if null {
+            record.push_null();
+        } else if escaped {
+            let mut decoded = String::with_capacity(col.len());
+            let mut chars = col.chars();
+            while let Some(ch) = chars.next() {
+                match ch {
+                    ch if ch == self.marker => decoded.extend(chars.next()),
+                    ch => decoded.push(ch)
+                }
+            }
+            record.push_field(Cow::Owned(decoded));
+        } else {
+            record.push_field(Cow::Borrowed(col));
+        }
+        Some(())
+    }
+
+    /// Index of the first byte from `start` on that ends a run in state, the length of buf if there is none
+    #[inline(always)]
+    fn run_end(&self, state: u8, buf: &[u8], start: usize) -> usize {
+        // Most columns are short, the search only pays off for long runs
+        let run = &self.runs[state as usize];
+        let short = buf.len().min(start + SHORT_RUN);
+        if let Some(end) = buf[start..short].iter().position(|&b| !run[b as usize]) {
+            return start + end
+        }
+        let rest = &buf[short..];
+        let found = match self.stops[state as usize] {
+            Stops::One(a) => memchr(a, rest),
+            Stops::Two(a, b) => memchr2(a, b, rest),
+            Stops::Three(a, b, c) => memchr3(a, b, c, rest),
+            Stops::Table => rest.iter().position(|&b| !run[b as usize])
+        };
+        short + found.unwrap_or(rest.len())
+    }
+
+    #[inline(always)]
+    fn end_column(&self, record: &mut ByteRecord, quoted: bool) {
+        let null = !quoted && match self.null {
+            NullMarker(marker) => record.field() == marker.as_bytes(),
+            UnquotedEmpty => record.field_len() == 0,
+            NoNull => false
+        };
+        if null {
+            record.end_null_field();
+        } else {
+            record.end_field();
+        }
+    }
+
+    /// State after byte in state, `None` if the byte is not valid there
+    #[inline(always)]
+    fn next_state(&self, state: State, byte: u8) -> Option<State> {
+        let transition = self.table[state as usize][self.classes[byte as usize] as usize];
+        match transition.action {
+            Fail => None,
+            // Byte after the row ended is read again at the start of the next row
+            DoneBefore => self.next_state(READ_COLUMN, byte),
+            _ => Some(transition.next)
+        }
+    }
+
+    /// States reached after data from each of the states, `None` where the data is not valid
+    ///
+    /// The tuples of states of the runs from all states are numbered as they are reached and their
+    /// transitions cached, so that data is scanned with one lookup per byte. Few tuples are reachable
+    /// since most runs merge within a row.
+    pub fn run_all(&self, data: &[u8]) -> [Option<State>; STATES] {
+        const UNKNOWN: usize = usize::MAX;
+        let mut tuples = vec!(std::array::from_fn(|state| Some(state as State)));
+        let mut ids = HashMap::from([(tuples[0], 0)]);
+        let mut next = vec!([UNKNOWN; CLASSES]);
+        let mut current = 0;
+        for &byte in data {
+            let class = self.classes[byte as usize] as usize;
+            if next[current][class] == UNKNOWN {
+                let tuple: [Option<State>; STATES] = tuples[current].map(|state| state.and_then(|state| self.next_state(state, byte)));
+                let id = *ids.entry(tuple).or_insert_with(|| {
+                    tuples.push(tuple);
+                    next.push([UNKNOWN; CLASSES]);
+                    tuples.len() - 1
+                });
+                next[current][class] = id;
+            }
+            current = next[current][class];
+        }
+        tuples[current]
+    }
+
+    /// Offset of the first row start in data from state
+    ///
+    /// State is updated to the state at the end of data if no row starts in it, `None` if the data is not valid.
+    pub fn row_start(&self, state: &mut Option<State>, data: &[u8]) -> Option<usize> {
+        for (i, &byte) in data.iter().enumerate() {
+            let transition = match *state {
+                Some(current) => self.table[current as usize][self.classes[byte as usize] as usize],
+                None => return None
+            };
+            match transition.action {
+                EndRow | Done => return Some(i + 1),
+                DoneBefore => return Some(i),
+                Fail => *state = None,
+                _ => *state = Some(transition.next)
+            }
+        }
+        None
+    }
+
+    /// Number of line ends in raw data
+    pub fn count_lines(&self, raw: &[u8]) -> u64 {
+        let count = |byte| raw.iter().filter(|&&b| b == byte).count() as u64;
+        match self.lines {
+            LineCount::Newlines => count(b'\n'),
+            LineCount::Returns => count(b'\r'),
+            LineCount::Both => count(b'\n') + count(b'\r') - raw.windows(2).filter(|w| w == b"\r\n").count() as u64
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsv::{Config, ConfigBuilder, CSV, TSV, Char, Universal, Lossy, Strict, TrimHeaders, TrimFields, TrimAll, Backslash, UnquotedEmpty};
+    use crate::dsv::{Quote, Always, Never, PerColumn};
+    use crate::common::{CR, LF, VT, FF, NEL, LS, PS};
+    use crate::error::Position;
+    use crate::record::ByteRecord;
+
+    use super::{Dfa, Stops, STATES, UNQUOTED, QUOTED};
+
+    /// Rows read by the DFA and the unread rest of data
+    fn read_all(config: Config, data: &str) -> (Vec<Vec<String>>, String, Position) {
+        read_with(&Dfa::new(&config).unwrap(), data)
+    }
+
+    fn read_with(dfa: &Dfa, data: &str) -> (Vec<Vec<String>>, String, Position) {
+        let mut reader = data.as_bytes();
+        let mut record = ByteRecord::new();
+        let mut position = Position::new();
+        let mut rows = Vec::new();
+        while dfa.read_record(&mut reader, &mut record, Some(Strict), &mut position).unwrap() {
+            rows.push(record.iter().map(|col| String::from_utf8(col.to_vec()).unwrap()).collect());
+            record.clear();
+        }
+        (rows, String::from_utf8(reader.to_vec()).unwrap(), position)
+    }
+
+    fn row(cols: &[&str]) -> Vec<String> {
+        cols.iter().map(|col| col.to_string()).collect()
+    }
+
+    #[test]
+    fn complete_rows_are_read() {
+        let (rows, rest, position) = read_all(CSV, "a,\"b,\"\"c\"\"\r\n\"\r\n\r\n,\"\"\r\nd,e");
+        assert_eq!(rows, vec!(row(&["a", "b,\"c\"\r\n"]), row(&[]), row(&["", ""])));
+        assert_eq!(rest, "d,e");
+        assert_eq!(position, Position {record: 0, field: 0, line: 5, byte: 22});
+    }
+
+    #[test]
+    fn invalid_rows_are_left() {
+        assert_eq!(read_all(CSV, "a,b\r\n\"c\"d\r\n").1, "\"c\"d\r\n");
+        assert_eq!(read_all(CSV, "a,b\r\nc\nd\re\r\r\n").1, "c\nd\re\r\r\n");
+        assert_eq!(read_all(CSV, "a,b\r\n\"c\r\n").1, "\"c\r\n");
+        let dfa = Dfa::new(&CSV).unwrap();
+        let mut reader = &b"a\xff\r\n"[..];
+        assert!(!dfa.read_record(&mut reader, &mut ByteRecord::new(), Some(Strict), &mut Position::new()).unwrap());
+        assert!(dfa.read_record(&mut reader, &mut ByteRecord::new(), None, &mut Position::new()).unwrap());
+        let config = ConfigBuilder::new(CSV).comment(Some('#')).build();
+        assert_eq!(read_all(config, "a\r\n#b\r\n").1, "#b\r\n");
+    }
+
+    #[test]
+    fn line_terminators_are_configured() {
+        let config = ConfigBuilder::new(CSV).newlines(Universal).build();
+        let (rows, rest, position) = read_all(config, "a\rb\nc\r\nd\"\r\"e\r");
+        assert_eq!(rows, vec!(row(&["a"]), row(&["b"]), row(&["c"]), row(&["d\""])));
+        assert_eq!((rest, position.line), ("\"e\r".to_string(), 5));
+        let config = ConfigBuilder::new(TSV).line_terminator(CR).build();
+        assert_eq!(read_all(config, "a\n\"\tb\rc").0, vec!(row(&["a\n\"", "b"])));
+        let config = ConfigBuilder::new(CSV).line_terminator(LF).escape(Char('\\')).build();
+        assert_eq!(read_all(config, "\"a\\\"\r\"\n").0, vec!(row(&["a\"\r"])));
+    }
+
+    #[test]
+    fn unquoted_columns_are_null() {
+        let config = ConfigBuilder::new(CSV).null(UnquotedEmpty).build();
+        let dfa = Dfa::new(&config).unwrap();
+        let mut record = ByteRecord::new();
+        assert!(dfa.read_record(&mut &b",\"\"\r\n"[..], &mut record, None, &mut Position::new()).unwrap());
+        assert!(record.is_null(0) && !record.is_null(1));
+    }
+
+    #[test]
+    fn runs_end_at_structural_bytes() {
+        let dfa = Dfa::new(&CSV).unwrap();
+        assert_eq!(dfa.stops[UNQUOTED as usize], Stops::Two(b'\r', b','));
+        assert_eq!(dfa.stops[QUOTED as usize], Stops::One(b'"'));
+        let dfa = Dfa::new(&ConfigBuilder::new(CSV).newlines(Universal).escape(Char('\\')).build()).unwrap();
+        assert_eq!(dfa.stops[UNQUOTED as usize], Stops::Three(b'\n', b'\r', b','));
+        assert_eq!(dfa.stops[QUOTED as usize], Stops::Two(b'"', b'\\'));
+    }
+
+    #[test]
+    fn searched_runs_match_table_runs() {
+        let long = "abcdefghijklmnopqrstuvwxyz0123456789".repeat(3);
+        let data = [
+            format!("{0},{0}\r\n\"{0}\"\"{0}\",{0}\n{0}\r\n", long),
+            format!("{0}\\\"{0},\"{0}\\\"\r\n{0}\",é{0}\r{0}\n", long),
+            format!("{0}\"\r\n{0}", long),
+        ];
+        for config in [CSV, ConfigBuilder::new(CSV).newlines(Universal).escape(Char('\\')).build()] {
+            let dfa = Dfa::new(&config).unwrap();
+            let mut table = dfa.clone();
+            table.stops = [Stops::Table; STATES];
+            for data in data.iter() {
+                assert_eq!(read_with(&dfa, data), read_with(&table, data));
+            }
+        }
+    }
+
+    /// Every configuration read only by the character reader, extending the DFA has to update this list
+    #[test]
+    fn unsupported_configurations_have_no_dfa() {
+        static QUOTES: [Quote; 1] = [Always];
+        let unsupported = [
+            ConfigBuilder::new(CSV).trim(TrimHeaders).build(),
+            ConfigBuilder::new(CSV).trim(TrimFields).build(),
+            ConfigBuilder::new(CSV).trim(TrimAll).build(),
+            ConfigBuilder::new(CSV).skip_initial_space(true).build(),
+            ConfigBuilder::new(CSV).quote(PerColumn(&QUOTES)).build(),
+            ConfigBuilder::new(CSV).escape(Backslash).build(),
+            ConfigBuilder::new(CSV).escape(Char('\u{e9}')).build(),
+            ConfigBuilder::new(CSV).delimiter('\u{e9}').build(),
+            ConfigBuilder::new(CSV).quote_char('\u{e9}').build(),
+            ConfigBuilder::new(CSV).comment(Some('\u{e9}')).build(),
+            ConfigBuilder::new(CSV).delimiter('\n').build(),
+            ConfigBuilder::new(CSV).quote_char('\r').build(),
+            ConfigBuilder::new(CSV).escape(Char('\n')).build(),
+            ConfigBuilder::new(CSV).quote_char(',').build(),
+            ConfigBuilder::new(CSV).escape(Char(',')).build(),
+        ];
+        for config in unsupported.iter() {
+            assert!(Dfa::new(config).is_none(), "{:?}", config);
+        }
+        for lt in [VT, FF, NEL, LS, PS] {
+            assert!(Dfa::new(&ConfigBuilder::new(CSV).line_terminator(lt).build()).is_none(), "{:?}", lt);
+            assert!(Dfa::new(&ConfigBuilder::new(CSV).line_terminator(lt).newlines(Universal).build()).is_some(), "{:?}", lt);
+        }
+        assert!(Dfa::new(&ConfigBuilder::new(CSV).utf8(Lossy).build()).is_some());
+        assert!(Dfa::new(&ConfigBuilder::new(CSV).quote(Never).quote_char('\u{e9}').build()).is_some());
+        assert!(Dfa::new(&ConfigBuilder::new(CSV).escape(Char('"')).build()).is_some());
+    }
+}
@@ -1,13 +1,43 @@
 //! Reading and writing of DSV (Delimiter-separated values) data
-use std::io;
-use std::io::{IoResult, IoError};
-use std::string::String;
-
-pub use common::{LineTerminator, Row, LF, CR, CRLF, VT, FF, NEL, LS, PS};
-use common::INVALID_LINE_ENDING;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::path::Path;
+use std::slice;
+use std::str;
+use std::sync::Arc;
+
+use encoding_rs::{Encoding, UTF_8};
+use serde::de::DeserializeOwned;
+use serde::ser::Serialize;
+
+pub use crate::common::{LineTerminator, Row, NullableRow, LF, CR, CRLF, VT, FF, NEL, LS, PS};
+pub use crate::mmap::MappedFile;
+pub use crate::record::{StringRecord, StringRecordIter, NullableIter, ByteRecord, ByteRecordIter, BorrowedRecord};
+pub use crate::recovery::{Recovery, Rejected, RejectWriter, Quarantine, Stop, Skip, Collect};
+pub use crate::sniff::{sniff, Sniffed};
+pub use crate::par::{par_read_file, ParRows};
+use crate::common::{read_raw_char, write_char, RawChar};
+use crate::de::deserialize_row;
+use crate::dfa::Dfa;
+use crate::ser::serialize_row;
+use crate::error::{Error, Position, Result};
+use crate::recovery::{strip_terminator, Recorder};
+use crate::transcode::{self, DecodeReader, EncodeWriter};
+
+pub use self::Escape::*;
+pub use self::Quote::*;
+pub use self::DuplicateHeaders::*;
+pub use self::EmptyHeaders::*;
+pub use self::Utf8::*;
+pub use self::Newlines::*;
+pub use self::Trim::*;
+pub use self::Null::*;
 
 /// Quote character inside of quoted column escape rule
-#[deriving(Eq, PartialEq, Show)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum Escape {
     /// Quote character is doubled
     Double,
@@ -15,17 +45,101 @@ pub enum Escape {
     Char(char),
     /// No escaping is allowed, error is characters that require escaping are in quoted column
     Disallowed,
+    /// Backslash escape sequences in any column as in MySQL and PostgreSQL text dumps
+    ///
+    /// `\n`, `\r`, `\t`, `\0`, `\b`, `\f` and `\v` are control characters, a backslash before any
+    /// other character stands for that character, like `\\` or a backslash before the delimiter.
+    /// An unquoted column of only `\N` is null. When writing, the delimiter, quote character,
+    /// line terminators and control characters are escaped instead of quoted.
+    Backslash,
 }
 
 /// Column quoting rule, only Never affects data reading
-#[deriving(Eq, PartialEq, Show)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum Quote {
     /// Column is never quoted, error when writing if it contains characters that should be quoted
     Never,
     /// Column is always quoted
     Always,
-    /// Column is quoted if it contains characters that require quoting (delimiter or line terminator)
+    /// Column is quoted if it contains characters that require quoting (delimiter, line terminator
+    /// or quote character) or begins or ends with whitespace
     Minimal,
+    /// Column is quoted if it is not a decimal number or Minimal quotes it
+    NonNumeric,
+    /// Rule for each column by index, columns without a rule and nested `PerColumn` rules use Minimal
+    PerColumn(&'static [Quote]),
+}
+
+/// Handling of header names that occur more than once
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum DuplicateHeaders {
+    /// Duplicates are kept, lookup by name returns the first column with that name
+    KeepFirst,
+    /// Duplicates are renamed by appending `_2`, `_3`, ... to the name
+    Rename,
+    /// Error when reading headers with duplicate names
+    RejectDuplicates,
+}
+
+/// Handling of empty header names
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum EmptyHeaders {
+    /// Empty names are kept as they are
+    KeepEmpty,
+    /// Empty names are replaced by `column_N`, where N is the 1-based column number
+    Generate,
+    /// Error when reading headers with empty names
+    RejectEmpty,
+}
+
+/// Handling of data that is not valid UTF-8 when reading string rows
+///
+/// Reading byte records with `Rows::read_byte_record` never validates the data.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Utf8 {
+    /// Error when reading invalid data
+    Strict,
+    /// Invalid sequences are replaced with the replacement character U+FFFD
+    Lossy,
+}
+
+/// Line terminators accepted when reading, writing always uses the configured line terminator
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Newlines {
+    /// Only the configured line terminator
+    Exact,
+    /// Any of LF, CR and CRLF
+    Universal,
+    /// Any of LF, CR, CRLF, NEL, LS and PS
+    UniversalUnicode,
+}
+
+/// Trimming of spaces and tabs around columns when reading
+///
+/// Quoted column values are never trimmed, but blanks around the quotes are allowed.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Trim {
+    /// Columns are not trimmed
+    NoTrim,
+    /// Only header names are trimmed
+    TrimHeaders,
+    /// Only columns of rows after the header are trimmed
+    TrimFields,
+    /// Header names and columns are trimmed
+    TrimAll,
+}
+
+/// Representation of null columns, read as empty columns that are marked as null in records
+///
+/// Quoted columns are never null.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Null {
+    /// No column is null, except `\N` with `Backslash` escapes, null is written as an empty column
+    NoNull,
+    /// Unquoted column with exactly this text is null, like `\N` or `NULL`
+    NullMarker(&'static str),
+    /// Unquoted empty column is null, quoted empty column is an empty string
+    UnquotedEmpty,
 }
 
 /// Configuration for RFC 4180 standard CSV parsing
@@ -34,7 +148,19 @@ pub static CSV: Config = Config {
     quote_char: '"',
     escape: Double,
     line_terminator: CRLF,
-    quote: Minimal
+    newlines: Exact,
+    quote: Minimal,
+    comment: None,
+    null: NoNull,
+    trim: NoTrim,
+    skip_initial_space: false,
+    flexible: true,
+    has_headers: false,
+    duplicate_headers: KeepFirst,
+    empty_headers: KeepEmpty,
+    utf8: Strict,
+    encoding: UTF_8,
+    bom: false
 };
 
 ///Configuration for IANA TSV (text/tab-separated-values) parsing
@@ -43,10 +169,23 @@ pub static TSV: Config = Config {
     quote_char: '\0',
     escape: Disallowed,
     line_terminator: CRLF,
-    quote: Never
+    newlines: Exact,
+    quote: Never,
+    comment: None,
+    null: NoNull,
+    trim: NoTrim,
+    skip_initial_space: false,
+    flexible: true,
+    has_headers: false,
+    duplicate_headers: KeepFirst,
+    empty_headers: KeepEmpty,
+    utf8: Strict,
+    encoding: UTF_8,
+    bom: false
 };
 
 /// Contains configuration parameters for reading and writing
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct Config {
     /// Column delimiter
     pub delimiter: char,
@@ -56,8 +195,36 @@ pub struct Config {
     pub escape: Escape,
     /// Rows are separated by line terminator
     pub line_terminator: LineTerminator,
+    /// Line terminators accepted when reading
+    pub newlines: Newlines,
     /// Quoting of columns
     pub quote: Quote,
+    /// Lines beginning with this character are skipped when reading
+    pub comment: Option<char>,
+    /// Representation of null columns
+    pub null: Null,
+    /// Trimming of columns when reading
+    pub trim: Trim,
+    /// Spaces and tabs at the beginning of columns are skipped when reading, allowing blanks before quotes
+    pub skip_initial_space: bool,
+    /// Rows may have different numbers of columns when reading, otherwise every row
+    /// must have as many columns as the header or the first row
+    pub flexible: bool,
+    /// First row is a header with column names
+    pub has_headers: bool,
+    /// Handling of duplicate header names
+    pub duplicate_headers: DuplicateHeaders,
+    /// Handling of empty header names
+    pub empty_headers: EmptyHeaders,
+    /// Handling of invalid UTF-8
+    pub utf8: Utf8,
+    /// Character encoding of the data, a byte order mark found when reading overrides it
+    ///
+    /// Data in other encodings than UTF-8 is transcoded, byte offsets in positions count
+    /// the data as UTF-8.
+    pub encoding: &'static Encoding,
+    /// Write a byte order mark at the start of data
+    pub bom: bool,
 }
 
 impl Config {
@@ -65,100 +232,370 @@ impl Config {
         match self.escape {
             Double => Some(self.quote_char),
             Char(ch) => Some(ch),
+            Backslash => Some('\\'),
             Disallowed => None
         }
     }
+
+    /// Quoting rule of column at index
+    fn column_quote(&self, column: usize) -> Quote {
+        match self.quote {
+            PerColumn(quotes) => match quotes.get(column) {
+                Some(&PerColumn(_)) | None => Minimal,
+                Some(&quote) => quote
+            },
+            quote => quote
+        }
+    }
+
+    fn trims_headers(&self) -> bool {
+        self.trim == TrimHeaders || self.trim == TrimAll
+    }
+
+    fn trims_fields(&self) -> bool {
+        self.trim == TrimFields || self.trim == TrimAll
+    }
+
+    fn is_blank(&self, ch: char) -> bool {
+        (ch == ' ' || ch == '\t') && ch != self.delimiter
+    }
+
+    fn is_line_start(&self, ch: char) -> bool {
+        match self.newlines {
+            Exact => self.line_terminator.is_beginning(ch),
+            Universal => ch == '\n' || ch == '\r',
+            UniversalUnicode => matches!(ch, '\n' | '\r' | '\u{85}' | '\u{2028}' | '\u{2029}')
+        }
+    }
+
+    fn strip_line_terminator(&self, raw: &mut Vec<u8>) {
+        match self.newlines {
+            Exact => strip_terminator(raw, &[self.line_terminator.as_str()]),
+            Universal => strip_terminator(raw, &["\r\n", "\n", "\r"]),
+            UniversalUnicode => strip_terminator(raw, &["\r\n", "\n", "\r", "\u{85}", "\u{2028}", "\u{2029}"])
+        }
+    }
+}
+
+/// Builder for `Config`, starting from an existing configuration
+///
+/// ```rust
+/// # use tabular::dsv::{ConfigBuilder, CSV, Rename};
+/// let config = ConfigBuilder::new(CSV)
+///     .delimiter(';')
+///     .has_headers(true)
+///     .duplicate_headers(Rename)
+///     .build();
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigBuilder {
+    config: Config,
+}
+
+impl ConfigBuilder {
+    /// Create a builder with all settings copied from config
+    pub fn new(config: Config) -> ConfigBuilder {
+        ConfigBuilder { config }
+    }
+
+    /// Set column delimiter
+    pub fn delimiter(mut self, delimiter: char) -> ConfigBuilder {
+        self.config.delimiter = delimiter;
+        self
+    }
+
+    /// Set character used for column quoting
+    pub fn quote_char(mut self, quote_char: char) -> ConfigBuilder {
+        self.config.quote_char = quote_char;
+        self
+    }
+
+    /// Set quote escape rule
+    pub fn escape(mut self, escape: Escape) -> ConfigBuilder {
+        self.config.escape = escape;
+        self
+    }
+
+    /// Set line terminator
+    pub fn line_terminator(mut self, line_terminator: LineTerminator) -> ConfigBuilder {
+        self.config.line_terminator = line_terminator;
+        self
+    }
+
+    /// Set line terminators accepted when reading
+    pub fn newlines(mut self, newlines: Newlines) -> ConfigBuilder {
+        self.config.newlines = newlines;
+        self
+    }
+
+    /// Set quoting of columns
+    pub fn quote(mut self, quote: Quote) -> ConfigBuilder {
+        self.config.quote = quote;
+        self
+    }
+
+    /// Set character beginning comment lines
+    pub fn comment(mut self, comment: Option<char>) -> ConfigBuilder {
+        self.config.comment = comment;
+        self
+    }
+
+    /// Set representation of null columns
+    pub fn null(mut self, null: Null) -> ConfigBuilder {
+        self.config.null = null;
+        self
+    }
+
+    /// Set trimming of columns
+    pub fn trim(mut self, trim: Trim) -> ConfigBuilder {
+        self.config.trim = trim;
+        self
+    }
+
+    /// Set whether blanks at the beginning of columns are skipped
+    pub fn skip_initial_space(mut self, skip_initial_space: bool) -> ConfigBuilder {
+        self.config.skip_initial_space = skip_initial_space;
+        self
+    }
+
+    /// Set whether rows may have different numbers of columns
+    pub fn flexible(mut self, flexible: bool) -> ConfigBuilder {
+        self.config.flexible = flexible;
+        self
+    }
+
+    /// Set whether the first row is a header
+    pub fn has_headers(mut self, has_headers: bool) -> ConfigBuilder {
+        self.config.has_headers = has_headers;
+        self
+    }
+
+    /// Set handling of duplicate header names
+    pub fn duplicate_headers(mut self, duplicate_headers: DuplicateHeaders) -> ConfigBuilder {
+        self.config.duplicate_headers = duplicate_headers;
+        self
+    }
+
+    /// Set handling of empty header names
+    pub fn empty_headers(mut self, empty_headers: EmptyHeaders) -> ConfigBuilder {
+        self.config.empty_headers = empty_headers;
+        self
+    }
+
+    /// Set handling of invalid UTF-8
+    pub fn utf8(mut self, utf8: Utf8) -> ConfigBuilder {
+        self.config.utf8 = utf8;
+        self
+    }
+
+    /// Set character encoding of the data
+    pub fn encoding(mut self, encoding: &'static Encoding) -> ConfigBuilder {
+        self.config.encoding = encoding;
+        self
+    }
+
+    /// Set whether a byte order mark is written at the start of data
+    pub fn bom(mut self, bom: bool) -> ConfigBuilder {
+        self.config.bom = bom;
+        self
+    }
+
+    /// Create the configuration
+    pub fn build(self) -> Config {
+        self.config
+    }
 }
 
 struct Columns<'a, R: 'a> {
     reader: &'a mut R,
     config: Config,
+    record: &'a mut ByteRecord,
+    utf8: Option<Utf8>,
+    trim: bool,
+    raw: RawChar,
     row_done: bool,
     done: bool,
     allow_empty: bool,
-    column: uint,
-    pos: uint
+    null: bool,
+    escaped: bool,
+    column: usize,
+    pos: usize,
+    terminator_len: usize,
+    position: Position,
+    last: Position,
+    column_start: Position
+}
+
+fn is_eof(err: &Error) -> bool {
+    matches!(*err, Error::UnexpectedEof(_))
 }
 
-impl<'a, R: Buffer> Columns<'a, R> {
+impl<'a, R: BufRead> Columns<'a, R> {
+    fn new(reader: &'a mut R, config: Config, record: &'a mut ByteRecord, utf8: Option<Utf8>, trim: bool, position: Position) -> Columns<'a, R> {
+        Columns {
+            reader,
+            config,
+            record,
+            utf8,
+            trim,
+            raw: RawChar::from_char('\0'),
+            row_done: false,
+            done: false,
+            allow_empty: false,
+            null: false,
+            escaped: false,
+            column: 0,
+            pos: 0,
+            terminator_len: 0,
+            position,
+            last: position,
+            column_start: position
+        }
+    }
+
     #[inline(always)]
-    fn read_char(&mut self) -> IoResult<char> {
-        let res = self.reader.read_char();
-        if res.is_ok() { self.pos += 1; }
-        res
+    fn read_char(&mut self) -> Result<char> {
+        self.last = self.position;
+        let raw = match read_raw_char(self.reader)? {
+            Some(raw) => raw,
+            None => return Err(Error::UnexpectedEof(self.position))
+        };
+        self.pos += 1;
+        self.position.byte += raw.as_bytes().len() as u64;
+        self.raw = raw;
+        let ch = match (raw.ch, self.utf8) {
+            (Some(ch), _) => ch,
+            (None, Some(Strict)) => return Err(Error::InvalidUtf8(self.last)),
+            (None, Some(Lossy)) => {
+                self.raw = RawChar::from_char(char::REPLACEMENT_CHARACTER);
+                char::REPLACEMENT_CHARACTER
+            }
+            (None, None) => char::REPLACEMENT_CHARACTER
+        };
+        if self.is_line_end(ch)? {
+            self.position.line += 1;
+        }
+        Ok(ch)
+    }
+
+    #[inline(always)]
+    fn is_line_end(&mut self, ch: char) -> Result<bool> {
+        match self.config.newlines {
+            Exact => Ok(self.config.line_terminator.is_end(ch)),
+            _ if ch == '\r' => Ok(!self.is_next_lf()?),
+            _ => Ok(self.config.is_line_start(ch))
+        }
+    }
+
+    fn is_next_lf(&mut self) -> Result<bool> {
+        Ok(self.reader.fill_buf()?.first() == Some(&b'\n'))
     }
 
-    fn quoted_end(&mut self, next: IoResult<char>, res: String) -> IoResult<String> {
+    /// Append the last character read to the column, as it was in the data
+    #[inline(always)]
+    fn push_char(&mut self) {
+        self.record.push_bytes(self.raw.as_bytes());
+    }
+
+    fn quoted_end(&mut self, mut next: Result<char>) -> Result<()> {
+        if self.trim {
+            while matches!(next, Ok(ch) if self.config.is_blank(ch)) {
+                next = self.read_char();
+            }
+        }
         match next {
             Ok(ch) => {
                 if ch == self.config.delimiter {
-                    Ok(res)
-                } else if self.config.line_terminator.is_beginning(ch) {
-                    match self.read_line_terminator() {
-                        Ok(()) => Ok(res),
-                        Err(err) => Err(err)
-                    }
+                    Ok(())
+                } else if self.config.is_line_start(ch) {
+                    self.read_line_terminator(ch)
                 } else {
-                    Err(IoError {
-                        kind: io::InvalidInput,
-                        desc: "Expecting line terminator or delimiter",
-                        detail: None
-                    })
+                    Err(Error::UnexpectedChar(ch, self.last))
                 }
             }
-            Err(ref err) if err.kind == io::EndOfFile => {
+            Err(ref err) if is_eof(err) => {
                 self.row_done = true;
                 self.done = true;
-                Ok(res)
+                Ok(())
             }
             Err(err) => Err(err)
         }
     }
 
-    fn read_quoted_column(&mut self) -> IoResult<String> {
+    fn read_quoted_char(&mut self) -> Result<char> {
+        match self.read_char() {
+            Err(ref err) if is_eof(err) => Err(Error::UnterminatedQuote(self.column_start)),
+            res => res
+        }
+    }
+
+    /// Append the character escaped by a backslash to the column
+    fn push_escaped(&mut self, ch: char) {
+        self.escaped = true;
+        let decoded = match ch {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'v' => '\u{b}',
+            _ => {
+                self.null = ch == 'N' && self.record.field_len() == 0;
+                return self.push_char()
+            }
+        };
+        self.record.push_bytes(decoded.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+
+    fn read_quoted_column(&mut self) -> Result<()> {
         self.allow_empty = true;
-        let mut col = String::new();
         loop {
-            match self.read_char() {
-                Ok(ch) => {
-                    if self.config.escape_char() != Some(self.config.quote_char) && Some(ch) == self.config.escape_char() {
-                        match self.read_char() {
-                            Ok(quote) if quote == self.config.quote_char => col.push_char(quote),
-                            _ => return Err(IoError {
-                                kind: io::InvalidInput,
-                                desc: "Expecting quote char",
-                                detail: None
-                            })
-                        }
-
-                    } else if self.config.escape_char() != Some(self.config.quote_char) && ch == self.config.quote_char {
-                        let next = self.read_char();
-                        return self.quoted_end(next, col.into_string())
-                    } else if ch == self.config.quote_char {
-                        let next = self.read_char();
-                        match next {
-                            Ok(next) if next == self.config.quote_char => {
-                                col.push_char(next);
-                                continue
-                            }
-                            _ => ()
-                        };
-                        return self.quoted_end(next, col.into_string())
-                    } else {
-                        col.push_char(ch);
-                    }
+            let ch = self.read_quoted_char()?;
+            if self.config.escape == Backslash && ch == '\\' {
+                let next = self.read_quoted_char()?;
+                self.push_escaped(next);
+            } else if self.config.escape_char() != Some(self.config.quote_char) && Some(ch) == self.config.escape_char() {
+                match self.read_quoted_char()? {
+                    quote if quote == self.config.quote_char => self.push_char(),
+                    _ => return Err(Error::InvalidEscape(self.last))
                 }
-                Err(err) => return Err(err)
+            } else if self.config.escape_char() != Some(self.config.quote_char) && ch == self.config.quote_char {
+                let next = self.read_char();
+                return self.quoted_end(next)
+            } else if ch == self.config.quote_char {
+                let next = self.read_char();
+                match next {
+                    Ok(next) if next == self.config.quote_char => {
+                        self.push_char();
+                        continue
+                    }
+                    _ => ()
+                };
+                return self.quoted_end(next)
+            } else {
+                self.push_char();
             }
         }
     }
 
-    fn read_line_terminator(&mut self) -> IoResult<()> {
-        let mut lt = self.config.line_terminator.as_str().chars().skip(1);
+    fn read_line_terminator(&mut self, first: char) -> Result<()> {
+        let start = self.last;
+        self.terminator_len = 1;
+        if self.config.newlines != Exact {
+            if first == '\r' && self.is_next_lf()? {
+                self.read_char()?;
+                self.terminator_len = 2;
+            }
+            self.row_done = true;
+            return Ok(())
+        }
+        let lt = self.config.line_terminator.as_str().chars().skip(1);
         for c in lt {
             match self.read_char() {
-                Ok(ch) if ch == c => (),
-                Ok(_) => return Err(INVALID_LINE_ENDING.clone()),
+                Ok(ch) if ch == c => self.terminator_len += 1,
+                Ok(_) => return Err(Error::InvalidLineEnding(start)),
+                Err(ref err) if is_eof(err) => return Err(Error::InvalidLineEnding(start)),
                 Err(err) => return Err(err)
             }
         }
@@ -166,489 +603,1611 @@ impl<'a, R: Buffer> Columns<'a, R> {
         Ok(())
     }
 
-    fn check_eof(&mut self, err: IoError, allow_empty: bool, res: String) -> IoResult<String> {
-        if !self.row_done && err.kind == io::EndOfFile && (res.len() > 0 || allow_empty) {
+    /// Returns true if the next line is a comment
+    fn is_comment(&mut self) -> Result<bool> {
+        match self.config.comment {
+            Some(comment) => {
+                let mut buf = [0; 4];
+                let comment = comment.encode_utf8(&mut buf).as_bytes();
+                Ok(self.reader.fill_buf()?.starts_with(comment))
+            }
+            None => Ok(false)
+        }
+    }
+
+    /// Read a comment line, returns the text after the comment character
+    fn read_comment(&mut self) -> Result<String> {
+        let mut text = String::new();
+        self.read_char()?;
+        loop {
+            match self.read_char() {
+                Ok(ch) if self.config.is_line_start(ch) => {
+                    self.read_line_terminator(ch)?;
+                    break
+                }
+                Ok(ch) => text.push(ch),
+                Err(ref err) if is_eof(err) => {
+                    self.done = true;
+                    break
+                }
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(text)
+    }
+
+    /// Skip the rest of the line after an error
+    fn skip_line(&mut self) -> Result<()> {
+        loop {
+            match self.read_char() {
+                Ok(ch) if self.config.is_line_start(ch) => match self.read_line_terminator(ch) {
+                    Ok(()) => return Ok(()),
+                    Err(Error::Io(err)) => return Err(Error::Io(err)),
+                    Err(_) => ()
+                },
+                Ok(_) => (),
+                Err(ref err) if is_eof(err) => return Ok(()),
+                Err(err) => return Err(err)
+            }
+        }
+    }
+
+    fn check_eof(&mut self, err: Error, allow_empty: bool) -> Result<()> {
+        if !self.row_done && is_eof(&err) && (self.pos > 0 || allow_empty) {
             self.row_done = true;
             self.done = true;
-            Ok(res)
+            Ok(())
         } else {
             Err(err)
         }
     }
 
+    /// Returns true if the column that was read is null
+    fn is_null(&self) -> bool {
+        // Quoted columns allow empty rows
+        if self.allow_empty {
+            return false
+        }
+        match self.config.null {
+            _ if self.null && self.record.field_len() == 1 => true,
+            NullMarker(marker) => !self.escaped && self.record.field() == marker.as_bytes(),
+            UnquotedEmpty => self.record.field_len() == 0,
+            NoNull => false
+        }
+    }
+
     #[inline(always)]
-    fn read_unquoted_column(&mut self, mut curr: IoResult<char>) -> IoResult<String> {
+    fn read_unquoted_column(&mut self, curr: Result<char>) -> Result<()> {
         self.allow_empty = false;
-        let mut col = String::new();
+        let res = self.read_unquoted(curr);
+        if self.trim {
+            let config = self.config;
+            self.record.trim_field_end(|b| config.is_blank(b as char));
+        }
+        res
+    }
+
+    #[inline(always)]
+    fn read_unquoted(&mut self, mut curr: Result<char>) -> Result<()> {
         loop {
             match curr {
                 Ok(ch) => {
-                    if self.config.line_terminator.is_beginning(ch) {
-                        match self.read_line_terminator() {
-                            Ok(()) => break,
+                    if self.config.is_line_start(ch) {
+                        return self.read_line_terminator(ch)
+                    } else if ch == self.config.delimiter {
+                        return Ok(())
+                    } else if ch == '\\' && self.config.escape == Backslash {
+                        match self.read_char() {
+                            Ok(next) => self.push_escaped(next),
+                            Err(ref err) if is_eof(err) => return Err(Error::UnexpectedEof(self.position)),
                             Err(err) => return Err(err)
                         }
-                    } else if ch != self.config.delimiter {
-                        col.push_char(ch);
                     } else {
-                        break
+                        self.push_char();
                     }
                     curr = self.read_char();
                 }
                 Err(err) => {
                     let allow_empty = self.column > 0;
-                    return self.check_eof(err, allow_empty, col.into_string())
-                }            }
+                    return self.check_eof(err, allow_empty)
+                }
+            }
         }
-        Ok(col.into_string())
     }
 
     #[inline(always)]
-    fn read_column(&mut self) -> IoResult<String> {
-        let res = match self.read_char() {
-            Ok(ch) if self.config.quote == Never => self.read_unquoted_column(Ok(ch)),
+    fn read_column(&mut self) -> Result<()> {
+        self.column_start = self.position;
+        self.null = false;
+        self.escaped = false;
+        let mut first = self.read_char();
+        if self.trim || self.config.skip_initial_space {
+            while matches!(first, Ok(ch) if self.config.is_blank(ch)) {
+                first = self.read_char();
+            }
+        }
+        let res = match first {
+            Ok(ch) if self.config.column_quote(self.column) == Never => self.read_unquoted_column(Ok(ch)),
             Ok(ch) if self.config.quote_char == ch => self.read_quoted_column(),
             res => self.read_unquoted_column(res)
         };
         if res.is_ok() {
             self.column += 1;
+            self.position.field = self.column as u64;
         }
         res
     }
 }
 
-impl<'a, R: Buffer> Iterator<IoResult<String>> for Columns<'a, R> {
-    fn next(&mut self) -> Option<IoResult<String>> {
+impl<'a, R: BufRead> Iterator for Columns<'a, R> {
+    type Item = Result<()>;
+
+    /// Read the next column into the record
+    fn next(&mut self) -> Option<Result<()>> {
         if self.row_done {
             return None
         }
         match self.read_column() {
             Err(err) => {
                 self.row_done = true;
-                if self.pos == 0 && err.kind == io::EndOfFile {
+                if self.pos == 0 && is_eof(&err) {
                     self.done = true;
                     None
                 } else {
                     Some(Err(err))
                 }
             }
-            Ok(res) => {
+            Ok(()) => {
                 if self.row_done && !self.allow_empty
-                    && self.pos == self.config.line_terminator.as_str().len() {
+                    && self.pos == self.terminator_len {
+                    self.record.discard_field();
                     self.next()
                 } else {
-                    Some(Ok(res))
+                    if self.is_null() {
+                        self.record.end_null_field();
+                    } else {
+                        self.record.end_field();
+                    }
+                    Some(Ok(()))
                 }
             }
         }
     }
 }
 
-/// Read a single row
-pub fn read_row<R: Buffer>(config: Config, reader: &mut R) -> IoResult<Row> {
-    let mut res = Vec::new();
-    let done = {
-        let mut cols = Columns {
-            reader: reader,
-            config: config,
-            row_done: false,
-            done: false,
-            allow_empty: false,
-            column: 0,
-            pos: 0
-        };
-        for col in cols {
-            match col {
-                Ok(s) => res.push(s),
-                Err(err) => return Err(err)
+/// Read the next non-empty row into record, returns false at the end of data
+///
+/// Invalid UTF-8 is handled according to `utf8`, `None` keeps the data as it is.
+/// Comment lines are skipped, their text is passed to `on_comment`.
+fn read_record_at<R: BufRead>(config: Config, reader: &mut R, record: &mut ByteRecord, utf8: Option<Utf8>, trim: bool,
+                              position: &mut Position, on_comment: &mut Option<CommentHandler>) -> Result<bool> {
+    loop {
+        record.clear();
+        record.set_position(*position);
+        let mut cols = Columns::new(&mut *reader, config, &mut *record, utf8, trim, *position);
+        if cols.is_comment()? {
+            let comment = match cols.read_comment() {
+                Ok(comment) => comment,
+                Err(err) => {
+                    *position = cols.position;
+                    return Err(err)
+                }
+            };
+            if let Some(ref mut on_comment) = *on_comment {
+                on_comment(&comment, position);
+            }
+            *position = cols.position;
+            if cols.done {
+                return Ok(false)
             }
+            continue
+        }
+        for col in &mut cols {
+            if let Err(err) = col {
+                *position = cols.position;
+                return Err(err)
+            }
+        }
+        *position = cols.position;
+        let done = cols.done;
+        position.field = 0;
+        if !record.is_empty() || done {
+            return Ok(!record.is_empty())
         }
-        cols.done
-    };
-    if res.len() == 0 && !done {
-        read_row(config, reader)
-    } else {
-        Ok(res)
     }
 }
 
+/// Skip the rest of the line after an error in a record
+fn skip_line<R: BufRead>(config: Config, reader: &mut R, position: &mut Position) -> Result<()> {
+    let mut record = ByteRecord::new();
+    let mut cols = Columns::new(reader, config, &mut record, None, false, *position);
+    let res = cols.skip_line();
+    *position = cols.position;
+    position.field = 0;
+    res
+}
+
+fn read_row_at<R: BufRead>(config: Config, reader: &mut R, trim: bool, position: &mut Position, on_comment: &mut Option<CommentHandler>) -> Result<Row> {
+    let mut record = ByteRecord::new();
+    read_record_at(config, reader, &mut record, Some(config.utf8), trim, position, on_comment)?;
+    Ok(record.into_valid_string_record()?.to_row())
+}
+
+/// Read a single row, data is read as UTF-8 regardless of the configured encoding
+pub fn read_row<R: BufRead>(config: Config, reader: &mut R) -> Result<Row> {
+    read_row_at(config, reader, config.trims_fields(), &mut Position::new(), &mut None)
+}
+
+/// Column names of the header row
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Headers {
+    names: Row,
+    index: HashMap<String, usize>,
+}
+
+impl Headers {
+    fn new(config: &Config, row: Row, position: Position) -> Result<Headers> {
+        let mut names = Vec::with_capacity(row.len());
+        let mut index = HashMap::new();
+        for (i, mut name) in row.into_iter().enumerate() {
+            let position = Position {field: i as u64, ..position};
+            if name.is_empty() {
+                match config.empty_headers {
+                    KeepEmpty => (),
+                    Generate => name = format!("column_{}", i + 1),
+                    RejectEmpty => return Err(Error::EmptyHeader(position))
+                }
+            }
+            if index.contains_key(&name) {
+                match config.duplicate_headers {
+                    KeepFirst => (),
+                    Rename => {
+                        name = (2..).map(|n| format!("{}_{}", name, n))
+                                    .find(|renamed| !index.contains_key(renamed))
+                                    .unwrap();
+                    }
+                    RejectDuplicates => return Err(Error::DuplicateHeader(name, position))
+                }
+            }
+            index.entry(name.clone()).or_insert(i);
+            names.push(name);
+        }
+        Ok(Headers {names, index})
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns true if there are no columns
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Name of the column at index
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.names.get(i).map(|name| &name[..])
+    }
+
+    /// Index of the column with name
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.index.get(name).cloned()
+    }
+
+    /// Iterator over column names
+    pub fn iter(&self) -> slice::Iter<'_, String> {
+        self.names.iter()
+    }
+
+    /// Column names as a row
+    pub fn as_row(&self) -> &Row {
+        &self.names
+    }
+}
+
+/// Row with access to columns by header name
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    row: Row,
+    headers: Option<Arc<Headers>>,
+}
+
+impl Record {
+    /// Column at index
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.row.get(i).map(|col| &col[..])
+    }
+
+    /// Column with header name, always `None` when there are no headers
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{from_str, ConfigBuilder, CSV};
+    /// let config = ConfigBuilder::new(CSV).has_headers(true).build();
+    /// let mut records = from_str(config, "gameid,qtr\r\n20120905_DAL@NYG,1").records();
+    /// let record = records.next().unwrap().unwrap();
+    /// assert_eq!(record.get_by_name("qtr"), Some("1"));
+    /// ```
+    pub fn get_by_name(&self, name: &str) -> Option<&str> {
+        self.headers.as_ref()
+            .and_then(|headers| headers.index_of(name))
+            .and_then(|i| self.get(i))
+    }
+
+    /// Headers of the data this record was read from
+    pub fn headers(&self) -> Option<&Headers> {
+        self.headers.as_deref()
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.row.len()
+    }
+
+    /// Returns true if there are no columns
+    pub fn is_empty(&self) -> bool {
+        self.row.is_empty()
+    }
+
+    /// Iterator over columns
+    pub fn iter(&self) -> slice::Iter<'_, String> {
+        self.row.iter()
+    }
+
+    /// Columns of the record as a row
+    pub fn into_row(self) -> Row {
+        self.row
+    }
+}
+
+type CommentHandler = Box<dyn FnMut(&str, &Position) + Send>;
+
 ///Iterator over rows
 pub struct Rows<R> {
-    reader: R,
+    reader: Recorder<DecodeReader<R>>,
     config: Config,
+    position: Position,
+    headers: Option<Arc<Headers>>,
+    headers_done: bool,
     done: bool,
+    columns: Option<usize>,
+    record: StringRecord,
+    on_comment: Option<CommentHandler>,
+    recovery: Recovery,
+    rejected: Vec<Rejected>,
+    dfa: Option<Dfa>,
 }
 
-impl<R: Buffer> Iterator<IoResult<Row>> for Rows<R> {
-    fn next(&mut self) -> Option<IoResult<Row>> {
-        if self.done {
-            return None
+impl<R> Rows<R> {
+    /// Position of the next row to be read
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Number of columns rows are expected to have, known after the header or the first row is read
+    pub(crate) fn columns(&self) -> Option<usize> {
+        self.columns
+    }
+
+    /// Continue reading data at a row start after the header, rows have to have `columns` columns
+    pub(crate) fn resume_at(mut self, position: Position, columns: Option<usize>) -> Rows<R> {
+        self.position = position;
+        self.columns = columns;
+        self.headers_done = true;
+        self
+    }
+}
+
+impl<R: BufRead> Rows<R> {
+    fn read_headers(&mut self) -> Result<()> {
+        if self.headers_done {
+            return Ok(())
         }
-        match read_row(self.config, &mut self.reader) {
-            Ok(row) => {
-                self.done = row.len() == 0;
-                if self.done {
-                    None
-                } else {
-                    Some(Ok(row))
-                }
+        self.headers_done = true;
+        if !self.config.has_headers {
+            return Ok(())
+        }
+        let position = self.position;
+        let trim = self.config.trims_headers();
+        let row = match read_row_at(self.config, &mut self.reader, trim, &mut self.position, &mut self.on_comment) {
+            Ok(row) => row,
+            Err(err) => {
+                self.done = true;
+                return Err(err)
+            }
+        };
+        self.position.record += 1;
+        self.done = row.is_empty();
+        self.columns = Some(row.len());
+        match Headers::new(&self.config, row, position) {
+            Ok(headers) => {
+                self.headers = Some(Arc::new(headers));
+                Ok(())
             }
             Err(err) => {
                 self.done = true;
-                Some(Err(err))
+                Err(err)
+            }
+        }
+    }
+
+    /// Header of the data, reads the first row if it was not read yet
+    ///
+    /// Returns `None` if the configuration has no headers.
+    pub fn headers(&mut self) -> Result<Option<&Headers>> {
+        self.read_headers()?;
+        Ok(self.headers.as_deref())
+    }
+
+    fn read_next(&mut self, record: &mut ByteRecord, utf8: Option<Utf8>) -> Result<bool> {
+        self.read_headers()?;
+        loop {
+            if self.done {
+                record.clear();
+                return Ok(false)
+            }
+            self.reader.clear(self.position.byte);
+            let err = match self.read_checked(record, utf8) {
+                Ok(more) => return Ok(more),
+                Err(err) => err
+            };
+            if matches!(err, Error::Io(_)) || (self.recovery == Stop && !matches!(err, Error::UnequalLengths(..))) {
+                self.done = true;
+                return Err(err)
+            }
+            if self.recovery != Collect {
+                return Err(err)
+            }
+            self.reject(err, *record.position());
+        }
+    }
+
+    /// Keep the data read from position on as rejected
+    fn reject(&mut self, error: Error, position: Position) {
+        let mut raw = self.reader.recorded(position.byte).to_vec();
+        self.config.strip_line_terminator(&mut raw);
+        self.rejected.push(Rejected {error, raw});
+    }
+
+    /// Read the next row, skipping the rest of its line on errors if recovering
+    fn read_checked(&mut self, record: &mut ByteRecord, utf8: Option<Utf8>) -> Result<bool> {
+        let trim = self.config.trims_fields();
+        let read = self.read_buffered(record, utf8).and_then(|read| match read {
+            true => Ok(true),
+            false => read_record_at(self.config, &mut self.reader, record, utf8, trim, &mut self.position, &mut self.on_comment)
+        });
+        match read {
+            Ok(true) => {
+                self.position.record += 1;
+                self.check_columns(record.len(), *record.position())?;
+                Ok(true)
+            }
+            Ok(false) => {
+                self.done = true;
+                Ok(false)
+            }
+            Err(err) => {
+                if self.recovery != Stop {
+                    skip_line(self.config, &mut self.reader, &mut self.position)?;
+                    self.position.record += 1;
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Read the next non-empty row with the DFA, returns false if it has to be read by `read_record_at`
+    fn read_buffered(&mut self, record: &mut ByteRecord, utf8: Option<Utf8>) -> Result<bool> {
+        let dfa = match self.dfa {
+            Some(ref dfa) => dfa,
+            None => return Ok(false)
+        };
+        loop {
+            record.clear();
+            record.set_position(self.position);
+            if !dfa.read_record(&mut self.reader, record, utf8, &mut self.position)? {
+                return Ok(false)
+            }
+            if !record.is_empty() {
+                return Ok(true)
+            }
+        }
+    }
+
+    /// Check the number of columns of a row that was read unless the configuration is flexible
+    ///
+    /// The row was read completely, so reading continues after the error even with `Stop`.
+    fn check_columns(&mut self, len: usize, position: Position) -> Result<()> {
+        if self.config.flexible {
+            return Ok(())
+        }
+        match self.columns {
+            Some(expected) if expected != len => Err(Error::UnequalLengths(expected, len, position)),
+            Some(_) => Ok(()),
+            None => {
+                self.columns = Some(len);
+                Ok(())
+            }
+        }
+    }
+
+    /// Read the next row into record, reusing its allocations
+    ///
+    /// Returns `false` when there are no more rows. The header row is not returned.
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{from_str, StringRecord, CSV};
+    /// let mut rows = from_str(CSV, "aa,bb\r\ncc,dd");
+    /// let mut record = StringRecord::new();
+    /// while rows.read_record(&mut record).unwrap() {
+    ///     println!("{}", &record[1]);
+    /// }
+    /// ```
+    pub fn read_record(&mut self, record: &mut StringRecord) -> Result<bool> {
+        self.read_record_at(record).map(|(more, _)| more)
+    }
+
+    /// Read the next row into record, also returning the position of its start
+    fn read_record_at(&mut self, record: &mut StringRecord) -> Result<(bool, Position)> {
+        let mut bytes = mem::take(record).into_byte_record();
+        let res = self.read_next(&mut bytes, Some(self.config.utf8));
+        let position = *bytes.position();
+        *record = bytes.into_valid_string_record()?;
+        res.map(|more| (more, position))
+    }
+
+    /// Read the next row into a byte record without UTF-8 validation
+    ///
+    /// Returns `false` when there are no more rows. The header row is not returned.
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{read_rows, ByteRecord, Lossy, CSV};
+    /// let mut rows = read_rows(CSV, &b"caf\xe9,ok"[..]);
+    /// let mut record = ByteRecord::new();
+    /// assert!(rows.read_byte_record(&mut record).unwrap());
+    /// assert_eq!(record.get(0), Some(&b"caf\xe9"[..]));
+    /// assert_eq!(record.into_string_record(Lossy).unwrap().get(0), Some("caf\u{FFFD}"));
+    /// ```
+    pub fn read_byte_record(&mut self, record: &mut ByteRecord) -> Result<bool> {
+        self.read_next(record, None)
+    }
+
+    /// Call `on_comment` with the text and position of each skipped comment line
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{from_str, ConfigBuilder, CSV};
+    /// let config = ConfigBuilder::new(CSV).comment(Some('#')).build();
+    /// let mut rows = from_str(config, "#source: export\r\na,b").on_comment(|text, _| println!("{}", text));
+    /// assert_eq!(rows.next().unwrap().unwrap(), vec!("a".to_string(), "b".to_string()));
+    /// ```
+    pub fn on_comment<F: FnMut(&str, &Position) + Send + 'static>(mut self, on_comment: F) -> Rows<R> {
+        self.on_comment = Some(Box::new(on_comment));
+        self
+    }
+
+    /// Read every row with the character reader instead of the byte-level DFA
+    ///
+    /// Rows and errors are the same, this is for benchmarks and tests comparing the two readers.
+    #[doc(hidden)]
+    pub fn without_dfa(mut self) -> Rows<R> {
+        self.dfa = None;
+        self
+    }
+
+    /// Set the handling of records that can not be read, `Stop` by default
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{from_str, Collect, CSV};
+    /// let mut rows = from_str(CSV, "a,b\r\n\"c\"d,e\r\nf,g").recovery(Collect);
+    /// assert_eq!(rows.by_ref().filter_map(|row| row.ok()).count(), 2);
+    /// let rejected = rows.take_rejected();
+    /// assert_eq!(rejected[0].raw, b"\"c\"d,e");
+    /// assert_eq!(rejected[0].error.position().unwrap().line, 2);
+    /// ```
+    pub fn recovery(mut self, recovery: Recovery) -> Rows<R> {
+        self.recovery = recovery;
+        self.reader.set_enabled(recovery == Collect);
+        self
+    }
+
+    /// Records rejected so far by the `Collect` recovery
+    pub fn rejected(&self) -> &[Rejected] {
+        &self.rejected
+    }
+
+    /// Remove and return the records rejected so far by the `Collect` recovery
+    pub fn take_rejected(&mut self) -> Vec<Rejected> {
+        mem::take(&mut self.rejected)
+    }
+
+    /// Convert into an iterator over rows that can be read, records that can not be read
+    /// are written to rejects and reading continues
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{from_str, read_rows, ConfigBuilder, RejectWriter, CSV};
+    /// let config = ConfigBuilder::new(CSV).flexible(false).build();
+    /// let mut rows = from_str(config, "a,b\r\nc\r\n\"d\"e,f\r\ng,h")
+    ///     .quarantine(RejectWriter::new(CSV, Vec::new()));
+    /// assert_eq!(rows.by_ref().count(), 2);
+    /// let rejects = rows.into_rejects().into_inner();
+    /// let rejects: Vec<_> = read_rows(CSV, &rejects[..]).map(|row| row.unwrap()).collect();
+    /// assert_eq!(rejects[1][0], "\"d\"e,f");
+    /// assert_eq!(rejects[1][1], "Expecting line terminator or delimiter, found 'e' at record 2, field 0 (line 3, byte 11)");
+    /// ```
+    pub fn quarantine<W: Write>(self, rejects: RejectWriter<W>) -> Quarantine<Rows<R>, W> {
+        Quarantine::new(self.recovery(Collect), rejects)
+    }
+
+    /// Convert into an iterator over records with access to columns by header name
+    pub fn records(self) -> Records<R> {
+        Records {rows: self}
+    }
+
+    /// Convert into an iterator deserializing each row into `D`
+    ///
+    /// Struct fields are matched to columns by header name if the configuration has headers,
+    /// otherwise by position. Null columns deserialize to `None` for `Option` fields, without a
+    /// `null` representation empty columns do too.
+    ///
+    /// ```rust
+    /// # use serde::Deserialize;
+    /// # use tabular::dsv::{from_str, CSV};
+    /// #[derive(Deserialize)]
+    /// struct Score {
+    ///     off: String,
+    ///     offscore: u32,
+    /// }
+    ///
+    /// let mut scores = from_str(CSV, "DAL,17\r\nNYG,24").deserialize::<Score>();
+    /// assert_eq!(scores.next().unwrap().unwrap().offscore, 17);
+    /// ```
+    pub fn deserialize<D: DeserializeOwned>(self) -> DeserializeRows<R, D> {
+        DeserializeRows {rows: self, record: StringRecord::new(), _type: PhantomData}
+    }
+}
+
+impl<'a> Rows<&'a [u8]> {
+    /// Read the next row into a record with columns borrowed from the data
+    ///
+    /// Columns are borrowed unless they have to be decoded, like quoted columns with escaped quotes.
+    /// Rows only the character reader can read, data in other encodings than UTF-8 and all rows
+    /// with the `Collect` recovery are read into owned columns.
+    ///
+    /// ```rust
+    /// # use std::borrow::Cow;
+    /// # use tabular::dsv::{read_rows, BorrowedRecord, CSV};
+    /// let data = b"a,\"b\"\"c\"\r\n";
+    /// let mut rows = read_rows(CSV, &data[..]);
+    /// let mut record = BorrowedRecord::new();
+    /// assert!(rows.read_borrowed_record(&mut record).unwrap());
+    /// let cols = record.into_columns();
+    /// assert_eq!(cols[0], Cow::Borrowed("a"));
+    /// assert_eq!(cols[1], Cow::<str>::Owned("b\"c".to_string()));
+    /// ```
+    pub fn read_borrowed_record(&mut self, record: &mut BorrowedRecord<'a>) -> Result<bool> {
+        record.clear();
+        self.read_headers()?;
+        if self.done {
+            return Ok(false)
+        }
+        if self.recovery != Collect && self.read_borrowed(record)? {
+            self.position.record += 1;
+            return self.check_columns(record.len(), *record.position()).map(|_| true)
+        }
+        let mut owned = mem::take(&mut self.record);
+        let res = self.read_record_at(&mut owned);
+        if let Ok((true, position)) = res {
+            record.extend_owned(&owned);
+            record.set_position(position);
+        }
+        self.record = owned;
+        res.map(|(more, _)| more)
+    }
+
+    /// Read the next non-empty row with the DFA, returns false if it has to be read by `read_record_at`
+    fn read_borrowed(&mut self, record: &mut BorrowedRecord<'a>) -> Result<bool> {
+        let dfa = match self.dfa {
+            Some(ref dfa) => dfa,
+            None => return Ok(false)
+        };
+        // Byte order mark is removed by filling the buffer
+        self.reader.fill_buf()?;
+        loop {
+            let data = match self.reader.get_ref().passthrough() {
+                Some(&data) => data,
+                None => return Ok(false)
+            };
+            record.clear();
+            record.set_position(self.position);
+            match dfa.read_borrowed(data, record, &mut self.position) {
+                Some(len) => self.reader.consume(len),
+                None => return Ok(false)
+            }
+            if !record.is_empty() {
+                return Ok(true)
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Rows<R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        let mut record = mem::take(&mut self.record);
+        let res = match self.read_record(&mut record) {
+            Ok(true) => Some(Ok(record.to_row())),
+            Ok(false) => None,
+            Err(err) => Some(Err(err))
+        };
+        self.record = record;
+        res
+    }
+}
+
+/// Iterator over records
+pub struct Records<R> {
+    rows: Rows<R>,
+}
+
+impl<R: BufRead> Records<R> {
+    /// Header of the data, reads the first row if it was not read yet
+    pub fn headers(&mut self) -> Result<Option<&Headers>> {
+        self.rows.headers()
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Result<Record>> {
+        self.rows.next().map(|res| res.map(|row| Record {
+            row,
+            headers: self.rows.headers.clone()
+        }))
+    }
+}
+
+/// Iterator deserializing rows
+pub struct DeserializeRows<R, D> {
+    rows: Rows<R>,
+    record: StringRecord,
+    _type: PhantomData<fn() -> D>,
+}
+
+impl<R: BufRead, D> DeserializeRows<R, D> {
+    /// Header of the data, reads the first row if it was not read yet
+    pub fn headers(&mut self) -> Result<Option<&Headers>> {
+        self.rows.headers()
+    }
+}
+
+impl<R: BufRead, D> DeserializeRows<R, D> {
+    /// Convert into an iterator over deserialized rows, records that can not be read or
+    /// deserialized are written to rejects and reading continues
+    pub fn quarantine<W: Write>(mut self, rejects: RejectWriter<W>) -> Quarantine<DeserializeRows<R, D>, W> {
+        self.rows = self.rows.recovery(Collect);
+        Quarantine::new(self, rejects)
+    }
+}
+
+impl<R: BufRead, D: DeserializeOwned> Iterator for DeserializeRows<R, D> {
+    type Item = Result<D>;
+
+    /// Deserialization errors do not end reading, with the `Collect` recovery the rows are rejected
+    fn next(&mut self) -> Option<Result<D>> {
+        if let Err(err) = self.rows.read_headers() {
+            return Some(Err(err))
+        }
+        loop {
+            let position = match self.rows.read_record_at(&mut self.record) {
+                Ok((true, position)) => position,
+                Ok((false, _)) => return None,
+                Err(err) => return Some(Err(err))
+            };
+            match deserialize_row(&self.record, self.rows.headers.as_deref(), self.rows.config.null, position) {
+                Err(err) if self.rows.recovery == Collect => self.rows.reject(err, position),
+                res => return Some(res)
+            }
+        }
+    }
+}
+
+impl<R: BufRead, W: Write> Iterator for Quarantine<Rows<R>, W> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        let next = self.rows.next();
+        let rejected = self.rows.take_rejected();
+        match self.write_rejected(rejected) {
+            Ok(()) => next,
+            Err(err) => Some(Err(err))
+        }
+    }
+}
+
+impl<R: BufRead, D: DeserializeOwned, W: Write> Iterator for Quarantine<DeserializeRows<R, D>, W> {
+    type Item = Result<D>;
+
+    fn next(&mut self) -> Option<Result<D>> {
+        let next = self.rows.next();
+        let rejected = self.rows.rows.take_rejected();
+        match self.write_rejected(rejected) {
+            Ok(()) => next,
+            Err(err) => Some(Err(err))
+        }
+    }
+}
+
+/// Create an iterator that reads a line on each iteration until EOF
+///
+/// ```rust,no_run
+/// # use std::io::BufReader;
+/// # use std::fs::File;
+/// # use tabular::dsv::{read_rows, CSV};
+/// let file = BufReader::new(File::open("file.csv").unwrap());
+///
+/// let rows = read_rows(CSV, file);
+/// ```
+pub fn read_rows<R: BufRead>(config: Config, reader: R) -> Rows<R> {
+    Rows {
+        reader: Recorder::new(DecodeReader::new(reader, config.encoding)),
+        config,
+        position: Position::new(),
+        headers: None,
+        headers_done: false,
+        done: false,
+        columns: None,
+        record: StringRecord::new(),
+        on_comment: None,
+        recovery: Stop,
+        rejected: Vec::new(),
+        dfa: Dfa::new(&config)
+    }
+}
+
+pub type RowsMem = Rows<io::Cursor<Vec<u8>>>;
+
+/// Helper method for reading rows from a string, the configured encoding is ignored
+///
+/// ```rust
+/// # use tabular::dsv::{from_str, CSV};
+/// let rows = from_str(CSV, "aa,bb\r\ncc,dd");
+/// ```
+pub fn from_str(config: Config, s: &str) -> RowsMem {
+    let buf = io::Cursor::new(s.as_bytes().to_vec());
+    read_rows(Config {encoding: UTF_8, ..config}, buf)
+}
+
+pub type RowsFile = Rows<BufReader<File>>;
+
+/// Files are read in large blocks, so that few rows cross the end of the buffer
+pub(crate) const FILE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Helper method for reading rows from a file
+///
+/// ```rust,no_run
+/// # use tabular::dsv::{from_file, CSV};
+/// let rows = from_file(CSV, "path/file.csv").unwrap();
+/// ```
+pub fn from_file<P: AsRef<Path>>(config: Config, path: P) -> Result<RowsFile> {
+    let file = BufReader::with_capacity(FILE_BUFFER_SIZE, File::open(path)?);
+    Ok(read_rows(config, file))
+}
+
+pub type RowsMapped<'a> = Rows<&'a [u8]>;
+
+/// Helper method for reading rows from a file mapped into memory
+///
+/// ```rust,no_run
+/// # use tabular::dsv::{from_mapped, BorrowedRecord, MappedFile, CSV};
+/// let file = MappedFile::open("path/file.csv").unwrap();
+/// let mut rows = from_mapped(CSV, &file);
+/// let mut record = BorrowedRecord::new();
+/// while rows.read_borrowed_record(&mut record).unwrap() {
+///     println!("{}", &record[0]);
+/// }
+/// ```
+pub fn from_mapped(config: Config, file: &MappedFile) -> RowsMapped<'_> {
+    read_rows(config, file.as_bytes())
+}
+
+/// Returns true if column is a decimal number with optional sign, fraction and exponent
+fn is_numeric(col: &str) -> bool {
+    let digits = |s: &str| s.bytes().take_while(u8::is_ascii_digit).count();
+    let mut rest = col.strip_prefix(['+', '-']).unwrap_or(col);
+    let int = digits(rest);
+    rest = &rest[int..];
+    let mut frac = 0;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        frac = digits(fraction);
+        rest = &fraction[frac..];
+    }
+    if int + frac == 0 {
+        return false
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let exp = digits(exponent);
+        if exp == 0 {
+            return false
+        }
+        rest = &exponent[exp..];
+    }
+    rest.is_empty()
+}
+
+/// Returns true if column must be quoted to be read back unchanged, `alone` if it is the only column of its row
+fn is_quote_required(config: Config, quote: Quote, col: &str, alone: bool) -> bool {
+    let escaped = config.escape == Backslash;
+    let null = match config.null {
+        // Backslash in the column is escaped, so it is not read as the marker
+        NullMarker(marker) => col == marker && !(escaped && col.contains('\\')),
+        UnquotedEmpty => col.is_empty(),
+        NoNull => false
+    };
+    // Unquoted empty column alone in a row would be read as a blank line and skipped
+    let required = (alone && col.is_empty()) || null || !escaped && (
+        config.comment.is_some_and(|comment| col.starts_with(comment))
+        || col.chars().any(|ch| ch == config.delimiter || config.line_terminator.is_beginning(ch) || config.is_line_start(ch)));
+    // Byte order mark at the start of data is removed when reading
+    let minimal = || {
+        required || !escaped && (col.contains(config.quote_char) || col.starts_with('\u{feff}')
+            || col.starts_with(char::is_whitespace) || col.ends_with(char::is_whitespace))
+    };
+    match quote {
+        Always => true,
+        Never => required,
+        NonNumeric => !is_numeric(col) || minimal(),
+        _ => minimal()
+    }
+}
+
+fn write_column<W: Write + ?Sized>(config: Config, writer: &mut W, col: &str, alone: bool, position: &mut Position) -> Result<()> {
+    write_column_parts(config, writer, col, [(col, &b""[..])], alone, position)
+}
+
+/// Write column of raw data, invalid UTF-8 sequences are written unchanged
+fn write_byte_column<W: Write + ?Sized>(config: Config, writer: &mut W, col: &[u8], alone: bool, position: &mut Position) -> Result<()> {
+    match str::from_utf8(col) {
+        Ok(col) => write_column(config, writer, col, alone, position),
+        // Replacement characters never require quoting, so the decision is made for the valid data
+        Err(_) => write_column_parts(config, writer, &String::from_utf8_lossy(col), utf8_parts(col), alone, position)
+    }
+}
+
+/// Split data into valid UTF-8 text each followed by an invalid sequence, empty at the end
+fn utf8_parts(mut data: &[u8]) -> Vec<(&str, &[u8])> {
+    let mut parts = Vec::new();
+    while !data.is_empty() {
+        let (valid, len) = match str::from_utf8(data) {
+            Ok(text) => (text, 0),
+            Err(err) => {
+                let (valid, rest) = data.split_at(err.valid_up_to());
+                // Valid prefix was checked by from_utf8
+                let valid = str::from_utf8(valid).unwrap_or_default();
+                (valid, err.error_len().unwrap_or(rest.len()))
             }
+        };
+        let end = valid.len() + len;
+        parts.push((valid, &data[valid.len()..end]));
+        data = &data[end..];
+    }
+    parts
+}
+
+/// Write column text, each part escaped and followed by its invalid bytes written unchanged
+fn write_column_parts<'c, W, P>(config: Config, writer: &mut W, col: &str, parts: P, alone: bool, position: &mut Position) -> Result<()>
+    where W: Write + ?Sized, P: IntoIterator<Item = (&'c str, &'c [u8])> {
+    let quote = config.column_quote(position.field as usize);
+    let quoted = is_quote_required(config, quote, col, alone);
+    if quoted && quote == Never {
+        return Err(Error::MustQuote(*position))
+    }
+    // Errors are at the start of the column, position is moved after it is written
+    let (mut written, mut lines) = (0, 0);
+    if quoted {
+        write_char(writer, config.quote_char)?;
+        written += config.quote_char.len_utf8();
+    }
+    let mut start = true;
+    for (text, invalid) in parts {
+        let (bytes, ends) = if config.escape == Backslash {
+            write_escaped_text(config, writer, text, start)?
+        } else if quoted {
+            write_quoted_text(config, writer, text, position)?
+        } else {
+            writer.write_all(text.as_bytes())?;
+            (text.len(), text.chars().filter(|&ch| config.line_terminator.is_end(ch)).count())
+        };
+        writer.write_all(invalid)?;
+        written += bytes + invalid.len();
+        lines += ends;
+        start = start && text.is_empty() && invalid.is_empty();
+    }
+    if quoted {
+        write_char(writer, config.quote_char)?;
+        written += config.quote_char.len_utf8();
+    }
+    position.byte += written as u64;
+    position.line += lines as u64;
+    Ok(())
+}
+
+/// Write text inside quotes with quote characters escaped, returns the bytes and lines written
+fn write_quoted_text<W: Write + ?Sized>(config: Config, writer: &mut W, text: &str, position: &Position) -> Result<(usize, usize)> {
+    let (mut written, mut lines) = (0, 0);
+    for ch in text.chars() {
+        if ch == config.quote_char {
+            match config.escape_char() {
+                Some(esc) => {
+                    write_char(writer, esc)?;
+                    written += esc.len_utf8();
+                }
+                None => return Err(Error::EscapeDisallowed(*position))
+            }
+        } else if Some(ch) == config.escape_char() {
+            return Err(Error::EscapeCharInQuote(*position))
+        }
+        write_char(writer, ch)?;
+        written += ch.len_utf8();
+        if config.line_terminator.is_end(ch) {
+            lines += 1;
+        }
+    }
+    Ok((written, lines))
+}
+
+/// Write text with backslash escapes for all characters that would otherwise require quoting,
+/// `start` if it is at the start of the column, returns the bytes and lines written
+fn write_escaped_text<W: Write + ?Sized>(config: Config, writer: &mut W, text: &str, start: bool) -> Result<(usize, usize)> {
+    let (mut written, mut lines) = (0, 0);
+    for (i, ch) in text.chars().enumerate() {
+        let escape = match ch {
+            '\\' => Some('\\'),
+            '\n' => Some('n'),
+            '\r' => Some('r'),
+            '\t' => Some('t'),
+            '\0' => Some('0'),
+            _ if ch == config.delimiter || ch == config.quote_char
+                || config.line_terminator.is_beginning(ch) || config.is_line_start(ch)
+                || start && i == 0 && (Some(ch) == config.comment || ch == '\u{feff}') => Some(ch),
+            _ => None
+        };
+        if escape.is_some() {
+            write_char(writer, '\\')?;
+            written += 1;
+        }
+        let out = escape.unwrap_or(ch);
+        write_char(writer, out)?;
+        written += out.len_utf8();
+        if config.line_terminator.is_end(out) {
+            lines += 1;
+        }
+    }
+    Ok((written, lines))
+}
+
+/// Write null column as the configured marker, `\N` with `Backslash` escapes or an empty column
+fn write_null<W: Write + ?Sized>(config: Config, writer: &mut W, alone: bool, position: &mut Position) -> Result<()> {
+    let marker = match config.null {
+        NullMarker(marker) => marker,
+        UnquotedEmpty => "",
+        NoNull if config.escape == Backslash => "\\N",
+        NoNull => return write_column(config, writer, "", alone, position)
+    };
+    // Empty null alone in a row would be read as a blank line and skipped, it can not be quoted
+    if alone && marker.is_empty() {
+        return Err(Error::MustQuote(*position))
+    }
+    writer.write_all(marker.as_bytes())?;
+    position.byte += marker.len() as u64;
+    Ok(())
+}
+
+pub(crate) fn write_row_at<W: Write + ?Sized>(config: Config, writer: &mut W, row: Row, position: &mut Position) -> Result<()> {
+    write_nullable_row_at(config, writer, row.iter().map(|col| Some(&col[..])), position)
+}
+
+fn write_nullable_row_at<'c, W, I>(config: Config, writer: &mut W, row: I, position: &mut Position) -> Result<()>
+    where W: Write + ?Sized, I: ExactSizeIterator<Item = Option<&'c str>> {
+    if config.encoding == UTF_8 {
+        return write_utf8_row(config, writer, row, position)
+    }
+    let mut encoder = EncodeWriter::new(writer, config.encoding);
+    write_utf8_row(config, &mut encoder, row, position).map_err(|err| encoder.map_error(err, *position))
+}
+
+/// Write a row of raw data, invalid UTF-8 sequences are written unchanged
+///
+/// They can not be represented in other encodings, raw data that is not valid UTF-8 is then an
+/// `InvalidUtf8` error at its column.
+pub(crate) fn write_byte_row_at<W: Write + ?Sized>(config: Config, writer: &mut W, row: &[&[u8]], position: &mut Position) -> Result<()> {
+    if config.encoding != UTF_8 {
+        let row = row.iter().enumerate()
+            .map(|(field, col)| str::from_utf8(col).map_err(|_| Error::InvalidUtf8(Position {field: field as u64, ..*position})))
+            .collect::<Result<Vec<_>>>()?;
+        return write_nullable_row_at(config, writer, row.into_iter().map(Some), position)
+    }
+    write_utf8_row(config, writer, row.iter().copied(), position)
+}
+
+/// Column of a row written by `write_utf8_row`
+trait WriteColumn {
+    fn write<W: Write + ?Sized>(self, config: Config, writer: &mut W, alone: bool, position: &mut Position) -> Result<()>;
+}
+
+impl WriteColumn for Option<&str> {
+    fn write<W: Write + ?Sized>(self, config: Config, writer: &mut W, alone: bool, position: &mut Position) -> Result<()> {
+        match self {
+            Some(col) => write_column(config, writer, col, alone, position),
+            None => write_null(config, writer, alone, position)
         }
     }
 }
 
-/// Create an iterator that reads a line on each iteration until EOF
+impl WriteColumn for &[u8] {
+    fn write<W: Write + ?Sized>(self, config: Config, writer: &mut W, alone: bool, position: &mut Position) -> Result<()> {
+        write_byte_column(config, writer, self, alone, position)
+    }
+}
+
+fn write_utf8_row<W, I>(config: Config, writer: &mut W, row: I, position: &mut Position) -> Result<()>
+    where W: Write + ?Sized, I: ExactSizeIterator, I::Item: WriteColumn {
+    let alone = row.len() == 1;
+    let mut first = true;
+    for col in row {
+        if !first {
+            write_char(writer, config.delimiter)?;
+            position.byte += config.delimiter.len_utf8() as u64;
+            position.field += 1;
+        }
+        col.write(config, writer, alone, position)?;
+        first = false;
+    }
+    let lt = config.line_terminator.as_str();
+    writer.write_all(lt.as_bytes())?;
+    position.byte += lt.len() as u64;
+    position.line += 1;
+    position.record += 1;
+    position.field = 0;
+    Ok(())
+}
+
+pub(crate) fn write_bom<W: Write + ?Sized>(config: Config, writer: &mut W) -> Result<()> {
+    if config.bom {
+        writer.write_all(transcode::bom(config.encoding))?;
+    }
+    Ok(())
+}
+
+/// Write a single row, a byte order mark is never written
+pub fn write_row<W: Write + ?Sized>(config: Config, writer: &mut W, row: Row) -> Result<()> {
+    write_row_at(config, writer, row, &mut Position::new())
+}
+
+/// Write a single row with `None` for null columns, a byte order mark is never written
+pub fn write_nullable_row<W: Write + ?Sized>(config: Config, writer: &mut W, row: NullableRow) -> Result<()> {
+    write_nullable_row_at(config, writer, row.iter().map(|col| col.as_deref()), &mut Position::new())
+}
+
+/// Write rows with `None` for null columns from iterator into writer with settings from config
+///
+/// Null columns are written as configured by `null`, columns that would be read as null are quoted.
+/// A row of a single null column is a `MustQuote` error with `UnquotedEmpty`, it would be written
+/// as a blank line and skipped when read.
+///
+/// ```rust
+/// # use tabular::dsv::{write_nullable_rows, ConfigBuilder, CSV, NullMarker};
+/// let config = ConfigBuilder::new(CSV).null(NullMarker("NULL")).build();
+/// let mut buf = Vec::new();
+/// let rows = vec!(vec!(Some("a".to_string()), None, Some("NULL".to_string())));
+/// write_nullable_rows(config, &mut buf, rows.into_iter()).unwrap();
+/// assert_eq!(&buf[..], b"a,NULL,\"NULL\"\r\n");
+/// ```
+pub fn write_nullable_rows<W, R>(config: Config, writer: &mut W, rows: R) -> Result<()>
+    where W: Write + ?Sized, R: Iterator<Item = NullableRow> {
+    let mut position = Position::new();
+    write_bom(config, writer)?;
+    for row in rows {
+        write_nullable_row_at(config, writer, row.iter().map(|col| col.as_deref()), &mut position)?;
+    }
+    Ok(())
+}
+
+/// Write rows from iterator into writer with settings from config
 ///
-/// ```rust
-/// # use std::io::BufferedReader;
-/// # use std::io::File;
-/// # use tabular::dsv::{read_rows, CSV};
-/// let path = Path::new("file.csv");
-/// let mut file = BufferedReader::new(File::open(&path));
+/// Rows that are written without error are read back unchanged by `read_rows` with the same
+/// config, except rows without columns, which are written as blank lines and skipped when read.
+/// Columns are quoted as needed for that, a row of a single empty column is written as a quoted
+/// empty column.
 ///
-/// let rows = read_rows(CSV, file);
+/// ```rust,no_run
+/// # use std::io::BufWriter;
+/// # use std::fs::File;
+/// # use tabular::dsv::{write_rows, CSV};
+/// let mut file = BufWriter::new(File::create("path/file.csv").unwrap());
+///
+/// let rows = vec!(vec!("a".to_string(), "bb".to_string()), vec!("ccc".to_string(), "dddd".to_string()));
+/// write_rows(CSV, &mut file, rows.into_iter()).unwrap();
 /// ```
-pub fn read_rows<R: Buffer>(config: Config, reader: R) -> Rows<R> {
-    Rows {
-        reader: reader,
-        config: config,
-        done: false
+pub fn write_rows<W, R>(config: Config, writer: &mut W, rows: R) -> Result<()>
+    where W: Write + ?Sized, R: Iterator<Item = Row> {
+    let mut position = Position::new();
+    write_bom(config, writer)?;
+    for row in rows {
+        write_row_at(config, writer, row, &mut position)?;
     }
+    Ok(())
 }
 
-pub type RowsMem = Rows<io::MemReader>;
-
-/// Helper method for reading rows from a string
+/// Serialize values from iterator into writer with settings from config
+///
+/// Structs, tuples and sequences are flattened into columns, `None` is written as null like by
+/// `write_nullable_rows` and unit enum variants as their name. When the configuration has headers,
+/// a header row with the field names of the first value is written before it. Formatting of
+/// individual fields can be customized with serde's `serialize_with` attribute.
 ///
 /// ```rust
-/// # use tabular::dsv::{from_str, CSV};
-/// let rows = from_str(CSV, "aa,bb\r\ncc,dd");
+/// # use serde::Serialize;
+/// # use tabular::dsv::{serialize_rows, ConfigBuilder, CSV};
+/// #[derive(Serialize)]
+/// struct Score {
+///     off: &'static str,
+///     offscore: u32,
+/// }
+///
+/// let config = ConfigBuilder::new(CSV).has_headers(true).build();
+/// let mut buf = Vec::new();
+/// serialize_rows(config, &mut buf, vec!(Score {off: "DAL", offscore: 17})).unwrap();
+/// assert_eq!(&buf[..], b"off,offscore\r\nDAL,17\r\n");
 /// ```
-pub fn from_str(config: Config, s: &str) -> RowsMem {
-    let buf = io::MemReader::new(Vec::from_slice(s.as_bytes()));
-    read_rows(config, buf)
+pub fn serialize_rows<W, S, I>(config: Config, writer: &mut W, values: I) -> Result<()>
+    where W: Write + ?Sized, S: Serialize, I: IntoIterator<Item = S> {
+    let mut position = Position::new();
+    write_bom(config, writer)?;
+    for value in values {
+        let serialized = serialize_row(&value, position)?;
+        if config.has_headers && position.record == 0 {
+            if let Some(names) = serialized.names {
+                write_row_at(config, writer, names, &mut position)?;
+            }
+        }
+        write_nullable_row_at(config, writer, serialized.row.iter().map(|col| col.as_deref()), &mut position)?;
+    }
+    Ok(())
 }
 
-pub type RowsFile = Rows<io::BufferedReader<IoResult<io::File>>>;
+/// Helper method for writing rows to a file
+///
+/// ```rust,no_run
+/// # use tabular::dsv::{write_file, CSV};
+/// let rows = vec!(vec!("a".to_string(), "bb".to_string()), vec!("ccc".to_string(), "dddd".to_string()));
+/// write_file(CSV, "path/file.csv", rows.into_iter()).unwrap();
+/// ```
+pub fn write_file<P: AsRef<Path>, R: Iterator<Item = Row>>(config: Config, path: P, rows: R) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_rows(config, &mut file, rows)?;
+    file.flush()?;
+    Ok(())
+}
 
-/// Helper method for reading rows from a file
+/// Writer of rows one at a time with buffered output
+///
+/// A byte order mark is written before the first row when the configuration has one. Each row
+/// is buffered until it is complete, a row that fails to be written leaves no partial output.
+/// Data reaches the underlying writer when the buffer is full, on `flush` and by `into_inner`.
 ///
 /// ```rust
-/// # use tabular::dsv::{from_file, CSV};
-/// let path = Path::new("path/file.csv");
-/// let rows = from_file(CSV, &path);
+/// # use tabular::dsv::{Writer, CSV, LF};
+/// let mut writer = Writer::new(CSV, Vec::new()).line_terminator(LF);
+/// writer.write_header(&["off", "offscore"]).unwrap();
+/// writer.write_record(&["DAL", "17"]).unwrap();
+/// writer.write_nullable_record(&[Some("NYG"), None]).unwrap();
+/// assert_eq!(writer.into_inner().unwrap(), b"off,offscore\nDAL,17\nNYG,\n");
 /// ```
-pub fn from_file(config: Config, path: &Path) -> RowsFile {
-    let file = io::BufferedReader::new(io::File::open(path));
-    read_rows(config, file)
+pub struct Writer<W: Write> {
+    writer: BufWriter<W>,
+    config: Config,
+    position: Position,
+    row: Vec<u8>,
 }
 
-fn is_quote_required(config: Config, col: &str) -> bool {
-    if config.quote == Always {
-        return true
+impl<W: Write> Writer<W> {
+    /// Create a writer in the format of config
+    pub fn new(config: Config, writer: W) -> Writer<W> {
+        Writer {
+            writer: BufWriter::new(writer),
+            config,
+            position: Position::new(),
+            row: Vec::new()
+        }
     }
-    col.chars().any(|ch| {
-        ch == config.delimiter || config.line_terminator.is_beginning(ch)
-    })
-}
 
-static MUST_QUOTE: IoError = IoError {
-    kind: io::InvalidInput,
-    desc: "Value should be quoted",
-    detail: None
-};
+    /// Terminate rows with line terminator instead of the configured one
+    pub fn line_terminator(mut self, line_terminator: LineTerminator) -> Writer<W> {
+        self.config.line_terminator = line_terminator;
+        self
+    }
 
-static ESCAPE_DISALLOWED: IoError = IoError {
-    kind: io::InvalidInput,
-    desc: "Escaping disallowed",
-    detail: None
-};
+    /// Position of the next row to be written
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
 
-static ESCAPE_CHAR_IN_QUOTE: IoError = IoError {
-    kind: io::InvalidInput,
-    desc: "Escape characted not allowed in quote",
-    detail: None
-};
+    /// Write a header row of column names, error if other rows were written before
+    pub fn write_header<S: AsRef<str>>(&mut self, names: &[S]) -> Result<()> {
+        if self.position.record > 0 {
+            return Err(Error::HeaderNotFirst(self.position))
+        }
+        self.write_record(names)
+    }
 
-fn write_column(config: Config, writer: &mut Writer, col: &str) -> IoResult<()> {
-    if is_quote_required(config, col.as_slice()) {
-        if config.quote == Never {
-            Err(MUST_QUOTE.clone())
-        } else {
-            try!(writer.write_char(config.quote_char));
-            for ch in col.chars() {
-                if ch == config.quote_char {
-                    if config.escape_char().is_some() {
-                        try!(writer.write_char(config.escape_char().unwrap()));
-                    } else {
-                        return Err(ESCAPE_DISALLOWED.clone())
-                    }
-                } else if Some(ch) == config.escape_char() {
-                    return Err(ESCAPE_CHAR_IN_QUOTE.clone())
-                }
-                try!(writer.write_char(ch));
+    /// Write a row
+    pub fn write_record<S: AsRef<str>>(&mut self, record: &[S]) -> Result<()> {
+        self.write_columns(record.iter().map(|col| Some(col.as_ref())))
+    }
+
+    /// Write a row with `None` for null columns
+    pub fn write_nullable_record<S: AsRef<str>>(&mut self, record: &[Option<S>]) -> Result<()> {
+        self.write_columns(record.iter().map(|col| col.as_ref().map(|col| col.as_ref())))
+    }
+
+    /// Write a record, null columns are written as null
+    pub fn write_string_record(&mut self, record: &StringRecord) -> Result<()> {
+        self.write_columns(record.iter_nullable())
+    }
+
+    /// Serialize value into a row like `serialize_rows`
+    ///
+    /// When the configuration has headers, the field names of the value are written as the header
+    /// row before it if it is the first row.
+    pub fn serialize<S: Serialize + ?Sized>(&mut self, value: &S) -> Result<()> {
+        let serialized = serialize_row(value, self.position)?;
+        if self.config.has_headers && self.position.record == 0 {
+            if let Some(names) = serialized.names {
+                self.write_record(&names)?;
             }
-            writer.write_char(config.quote_char)
         }
-    } else {
-        writer.write(col.as_bytes())
+        self.write_nullable_record(&serialized.row)
     }
-}
 
-/// Write a single row
-pub fn write_row(config: Config, writer: &mut Writer, row: Row) -> IoResult<()> {
-    let mut first = true;
-    for col in row.iter() {
-        if !first {
-            try!(writer.write_char(config.delimiter));
+    fn write_columns<'c, I: ExactSizeIterator<Item = Option<&'c str>>>(&mut self, row: I) -> Result<()> {
+        self.row.clear();
+        if self.position.record == 0 && self.position.byte == 0 {
+            write_bom(self.config, &mut self.row)?;
         }
-        try!(write_column(config, writer, col.as_slice()));
-        first = false;
+        let mut position = self.position;
+        write_nullable_row_at(self.config, &mut self.row, row, &mut position)?;
+        self.writer.write_all(&self.row)?;
+        self.position = position;
+        Ok(())
     }
-    try!(writer.write_str(config.line_terminator.as_str()));
-    Ok(())
-}
 
-/// Write rows from iterator into writer with settings from config
-///
-/// ```rust
-/// # #![allow(unused_must_use)]
-/// # use std::io::BufferedWriter;
-/// # use std::io::File;
-/// # use tabular::dsv::{write_rows, CSV};
-/// let path = Path::new("path/file.csv");
-/// let mut file = BufferedWriter::new(File::open(&path));
-///
-/// let rows = vec!(vec!("a".to_string(), "bb".to_string()), vec!("ccc".to_string(), "dddd".to_string()));
-/// write_rows(CSV, &mut file, rows.into_iter());
-/// ```
-pub fn write_rows<R: Iterator<Row>>(config: Config, writer: &mut Writer, mut rows: R) -> IoResult<()> {
-    for row in rows {
-        try!(write_row(config, writer, row));
+    /// Write buffered data to the underlying writer and flush it
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
     }
-    Ok(())
-}
 
-/// Helper method for writing rows to a file
-///
-/// ```rust
-/// # #![allow(unused_must_use)]
-/// # use tabular::dsv::{write_file, CSV};
-/// let rows = vec!(vec!("a".to_string(), "bb".to_string()), vec!("ccc".to_string(), "dddd".to_string()));
-/// let path = Path::new("path/file.csv");
-/// write_file(CSV, &path, rows.into_iter());
-/// ```
-pub fn write_file<R: Iterator<Row>>(config: Config, path: &Path, rows: R) -> IoResult<()> {
-    let mut file = io::BufferedWriter::new(io::File::open_mode(path, io::Open, io::Write));
-    write_rows(config, &mut file, rows)
+    /// Flush buffered data and return the underlying writer
+    pub fn into_inner(self) -> Result<W> {
+        self.writer.into_inner().map_err(|err| Error::Io(err.into_error()))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io;
-    use std::io::{IoResult, IoError};
-    use std::vec::Vec;
+    use std::borrow::Cow;
+    use std::sync::{Arc, Mutex};
+
+    use crate::error::{Error, Position, Result};
+
+    use super::{Columns, Config, ConfigBuilder, Char, CSV, read_rows, from_str, Row, StringRecord, ByteRecord, LF, TSV, NEL, PS};
+    use super::{Lossy, Universal, UniversalUnicode, read_row, TrimHeaders, TrimFields, TrimAll, Skip, Collect, Backslash};
+    use crate::encoding::{UTF_16BE, WINDOWS_1252};
+    use super::{Rename, RejectDuplicates, Generate, RejectEmpty};
+    use super::{write_column, write_rows, Never, Always, Disallowed, write_row, Quote, NonNumeric, PerColumn};
+    use super::{write_nullable_row, NullMarker, UnquotedEmpty, Writer, BorrowedRecord};
 
-    use common::INVALID_LINE_ENDING;
+    type Flat<T> = ::std::result::Result<T, String>;
 
-    use super::{Columns, Config, Char, CSV, read_rows, Row, LF, TSV, NEL, PS};
-    use super::{write_column, write_rows, Never, Always, Disallowed, write_row};
-    use super::{ESCAPE_DISALLOWED, MUST_QUOTE, ESCAPE_CHAR_IN_QUOTE};
+    fn flatten<T: Clone>(res: &Result<T>) -> Flat<T> {
+        match *res {
+            Ok(ref v) => Ok(v.clone()),
+            Err(ref err) => Err(format!("{:?}", err))
+        }
+    }
+
+    fn flatten_all<T: Clone>(res: &[Result<T>]) -> Vec<Flat<T>> {
+        res.iter().map(flatten).collect()
+    }
 
-    fn assert_colmatch(cfg: Config, row: &str, cols: &[IoResult<String>]) {
-        let mut reader = io::BufReader::new(row.as_bytes());
-        let mut columns = Columns {reader: &mut reader, config: cfg, row_done: false, done: false,
-                                    allow_empty: false, column: 0, pos: 0};
-        let result: Vec<IoResult<String>> = columns.collect();
-        assert_eq!(cols, result.as_slice())
+    fn pos(field: u64, byte: u64) -> Position {
+        Position {field, byte, ..Position::new()}
+    }
+
+    fn assert_colmatch(cfg: Config, row: &str, cols: &[Result<String>]) {
+        let mut reader = row.as_bytes();
+        let mut record = ByteRecord::new();
+        let mut columns = Columns::new(&mut reader, cfg, &mut record, Some(cfg.utf8), cfg.trims_fields(), Position::new());
+        let mut result = Vec::new();
+        while let Some(res) = columns.next() {
+            result.push(res.map(|()| {
+                let col = &columns.record[columns.record.len() - 1];
+                String::from_utf8(col.to_vec()).unwrap()
+            }));
+        }
+        assert_eq!(flatten_all(cols), flatten_all(&result))
     }
 
     static DELIM_PIPE: Config = Config {delimiter: '|', ..CSV};
 
     static QUOTE_TILDE: Config = Config {quote_char: '~', ..CSV};
 
-    static EOF_ERROR: IoError = IoError {
-        kind: io::EndOfFile,
-        desc: "end of file",
-        detail: None
-    };
-
     #[test]
     fn multi_column_quoting_dsabled() {
-        assert_colmatch(Config{quote: Never, ..CSV}, "\"foo,bar\"", [Ok("\"foo".to_string()), Ok("bar\"".to_string())]);
+        assert_colmatch(Config{quote: Never, ..CSV}, "\"foo,bar\"", &[Ok("\"foo".to_string()), Ok("bar\"".to_string())]);
     }
 
     #[test]
     fn empty_column() {
-        assert_colmatch(CSV, "", []);
+        assert_colmatch(CSV, "", &[]);
     }
 
     #[test]
     fn empty_column_line_end() {
-        assert_colmatch(CSV, "\r\n", []);
-        assert_colmatch(Config {line_terminator: LF, ..CSV}, "\n", []);
+        assert_colmatch(CSV, "\r\n", &[]);
+        assert_colmatch(Config {line_terminator: LF, ..CSV}, "\n", &[]);
     }
 
     #[test]
     fn single_column() {
-        assert_colmatch(CSV, "abc", [Ok("abc".to_string())]);
-        assert_colmatch(DELIM_PIPE, "abc", [Ok("abc".to_string())]);
+        assert_colmatch(CSV, "abc", &[Ok("abc".to_string())]);
+        assert_colmatch(DELIM_PIPE, "abc", &[Ok("abc".to_string())]);
     }
 
     #[test]
     fn single_column_line_end() {
-        assert_colmatch(CSV, "foo\r\n", [Ok("foo".to_string())]);
-        assert_colmatch(Config {line_terminator: LF, ..CSV}, "foo\n", [Ok("foo".to_string())]);
+        assert_colmatch(CSV, "foo\r\n", &[Ok("foo".to_string())]);
+        assert_colmatch(Config {line_terminator: LF, ..CSV}, "foo\n", &[Ok("foo".to_string())]);
     }
 
     #[test]
     fn single_column_invalid_line_end() {
-        assert_colmatch(CSV, "foo\r\r", [Err(INVALID_LINE_ENDING.clone())]);
+        assert_colmatch(CSV, "foo\r\r", &[Err(Error::InvalidLineEnding(pos(0, 3)))]);
     }
 
     #[test]
     fn multi_column() {
-        assert_colmatch(CSV, "foo,bar", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(DELIM_PIPE, "foo|bar", [Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(CSV, "foo,bar", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(DELIM_PIPE, "foo|bar", &[Ok("foo".to_string()), Ok("bar".to_string())]);
     }
 
     #[test]
     fn multi_column_line_end() {
-        assert_colmatch(CSV, "foo,bar\r\n", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(DELIM_PIPE, "foo|bar\r\n", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(Config {line_terminator: LF, ..CSV}, "foo,bar\n", [Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(CSV, "foo,bar\r\n", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(DELIM_PIPE, "foo|bar\r\n", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(Config {line_terminator: LF, ..CSV}, "foo,bar\n", &[Ok("foo".to_string()), Ok("bar".to_string())]);
     }
 
     #[test]
     fn empty_column_quoted() {
-        assert_colmatch(CSV, r#""""#, [Ok("".to_string())]);
-        assert_colmatch(Config {quote_char: '\'', ..CSV}, "''", [Ok("".to_string())]);
+        assert_colmatch(CSV, r#""""#, &[Ok("".to_string())]);
+        assert_colmatch(Config {quote_char: '\'', ..CSV}, "''", &[Ok("".to_string())]);
     }
 
     #[test]
     fn empty_column_quoted_line_end() {
-        assert_colmatch(CSV, "\"\"\r\n", [Ok("".to_string())]);
-        assert_colmatch(Config {quote_char: '\'', ..CSV}, "''\r\n", [Ok("".to_string())]);
-        assert_colmatch(Config {line_terminator: LF, ..CSV}, "\"\"\n", [Ok("".to_string())]);
-        assert_colmatch(Config {line_terminator: LF, quote_char: '\'', ..CSV}, "''\n", [Ok("".to_string())]);
+        assert_colmatch(CSV, "\"\"\r\n", &[Ok("".to_string())]);
+        assert_colmatch(Config {quote_char: '\'', ..CSV}, "''\r\n", &[Ok("".to_string())]);
+        assert_colmatch(Config {line_terminator: LF, ..CSV}, "\"\"\n", &[Ok("".to_string())]);
+        assert_colmatch(Config {line_terminator: LF, quote_char: '\'', ..CSV}, "''\n", &[Ok("".to_string())]);
     }
 
     #[test]
     fn single_column_quoted() {
-        assert_colmatch(CSV, r#""abc""#, [Ok("abc".to_string())]);
-        assert_colmatch(QUOTE_TILDE, r#"~abc~"#, [Ok("abc".to_string())]);
+        assert_colmatch(CSV, r#""abc""#, &[Ok("abc".to_string())]);
+        assert_colmatch(QUOTE_TILDE, r#"~abc~"#, &[Ok("abc".to_string())]);
     }
 
     #[test]
     fn single_column_quoted_with_delim() {
-        assert_colmatch(CSV, r#""a,b,c""#, [Ok("a,b,c".to_string())]);
-        assert_colmatch(Config {delimiter: '-', ..QUOTE_TILDE}, r#"~a-b-c~"#, [Ok("a-b-c".to_string())]);
+        assert_colmatch(CSV, r#""a,b,c""#, &[Ok("a,b,c".to_string())]);
+        assert_colmatch(Config {delimiter: '-', ..QUOTE_TILDE}, r#"~a-b-c~"#, &[Ok("a-b-c".to_string())]);
     }
 
     #[test]
     fn single_column_quoted_line_end() {
-        assert_colmatch(CSV, "\"abc\"\r\n", [Ok("abc".to_string())]);
-        assert_colmatch(QUOTE_TILDE, "~abc~\r\n", [Ok("abc".to_string())]);
-        assert_colmatch(Config {line_terminator: LF, ..QUOTE_TILDE}, "~abc~\n", [Ok("abc".to_string())]);
+        assert_colmatch(CSV, "\"abc\"\r\n", &[Ok("abc".to_string())]);
+        assert_colmatch(QUOTE_TILDE, "~abc~\r\n", &[Ok("abc".to_string())]);
+        assert_colmatch(Config {line_terminator: LF, ..QUOTE_TILDE}, "~abc~\n", &[Ok("abc".to_string())]);
     }
 
     #[test]
     fn single_column_quoted_invalid_line_end() {
-        assert_colmatch(CSV, "\"abc\"\r\r", [Err(INVALID_LINE_ENDING.clone())]);
+        assert_colmatch(CSV, "\"abc\"\r\r", &[Err(Error::InvalidLineEnding(pos(0, 5)))]);
     }
 
     #[test]
     fn single_column_quoted_allow_line_ending_inside() {
-        assert_colmatch(CSV, "\"Hello\r\nworld\"", [Ok("Hello\r\nworld".to_string())]);
+        assert_colmatch(CSV, "\"Hello\r\nworld\"", &[Ok("Hello\r\nworld".to_string())]);
     }
 
     #[test]
     fn single_column_quoted_escaped() {
-        assert_colmatch(CSV, r#""Hello, ""rust"" world""#, [Ok("Hello, \"rust\" world".to_string())]);
-        assert_colmatch(Config {escape: Char('$'), ..CSV}, r#""Hello, $"rust$" world""#, [Ok("Hello, \"rust\" world".to_string())]);
+        assert_colmatch(CSV, r#""Hello, ""rust"" world""#, &[Ok("Hello, \"rust\" world".to_string())]);
+        assert_colmatch(Config {escape: Char('$'), ..CSV}, r#""Hello, $"rust$" world""#, &[Ok("Hello, \"rust\" world".to_string())]);
     }
 
     #[test]
     fn single_column_quoted_escape_char_does_not_end_value() {
-        assert_colmatch(Config {escape: Char('~'), ..CSV}, "\"Hello~\r\nworld\"", [Err(IoError {
-            kind: io::InvalidInput,
-            desc: "Expecting quote char",
-            detail: None})]);
+        assert_colmatch(Config {escape: Char('~'), ..CSV}, "\"Hello~\r\nworld\"", &[Err(Error::InvalidEscape(pos(0, 7)))]);
     }
 
     #[test]
     fn single_column_quoted_unexpected_delimiter() {
-        assert_colmatch(CSV, r#""ab"c""#, [Err(IoError {
-            kind: io::InvalidInput,
-            desc: "Expecting line terminator or delimiter",
-            detail: None})]);
+        assert_colmatch(CSV, r#""ab"c""#, &[Err(Error::UnexpectedChar('c', pos(0, 4)))]);
     }
 
     #[test]
     fn single_column_quoted_unmatched_quotechar() {
-        assert_colmatch(CSV, r#""abc"#, [Err(EOF_ERROR.clone())]);
+        assert_colmatch(CSV, r#""abc"#, &[Err(Error::UnterminatedQuote(pos(0, 0)))]);
     }
 
     #[test]
     fn multi_column_quoted() {
-        assert_colmatch(CSV, "\"foo\",\"bar\"", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(QUOTE_TILDE, "~foo~,~bar~", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(Config {delimiter: ';', ..QUOTE_TILDE}, "~foo~;~bar~", [Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(CSV, "\"foo\",\"bar\"", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(QUOTE_TILDE, "~foo~,~bar~", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(Config {delimiter: ';', ..QUOTE_TILDE}, "~foo~;~bar~", &[Ok("foo".to_string()), Ok("bar".to_string())]);
     }
 
     #[test]
     fn multi_column_quoted_line_end() {
-        assert_colmatch(CSV, "\"foo\",\"bar\"\r\n", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(QUOTE_TILDE, "~foo~,~bar~\r\n", [Ok("foo".to_string()), Ok("bar".to_string())]);
-        assert_colmatch(Config {delimiter: ';', line_terminator: LF, ..QUOTE_TILDE}, "~foo~;~bar~", [Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(CSV, "\"foo\",\"bar\"\r\n", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(QUOTE_TILDE, "~foo~,~bar~\r\n", &[Ok("foo".to_string()), Ok("bar".to_string())]);
+        assert_colmatch(Config {delimiter: ';', line_terminator: LF, ..QUOTE_TILDE}, "~foo~;~bar~", &[Ok("foo".to_string()), Ok("bar".to_string())]);
     }
 
     #[test]
     fn columns_unquoted_trailing_delim() {
-        assert_colmatch(CSV, r#"a,1,c2,"#, [Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
-        assert_colmatch(DELIM_PIPE, r#"a|1|c2|"#, [Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
+        assert_colmatch(CSV, r#"a,1,c2,"#, &[Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
+        assert_colmatch(DELIM_PIPE, r#"a|1|c2|"#, &[Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
     }
 
     #[test]
     fn columns_unquoted_leading_delim() {
-        assert_colmatch(CSV, r#",1,c2"#, [Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
-        assert_colmatch(DELIM_PIPE, r#"|1|c2"#, [Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
+        assert_colmatch(CSV, r#",1,c2"#, &[Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
+        assert_colmatch(DELIM_PIPE, r#"|1|c2"#, &[Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
     }
 
     #[test]
     fn columns_quoted_trailing_delim() {
-        assert_colmatch(CSV, r#""a","1","c2","#, [Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
-        assert_colmatch(Config {quote_char: '\'', ..DELIM_PIPE}, r#"'a'|'1'|'c2'|"#, [Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
+        assert_colmatch(CSV, r#""a","1","c2","#, &[Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
+        assert_colmatch(Config {quote_char: '\'', ..DELIM_PIPE}, r#"'a'|'1'|'c2'|"#, &[Ok("a".to_string()), Ok("1".to_string()), Ok("c2".to_string()), Ok("".to_string())]);
     }
 
     #[test]
     fn columns_quoted_leading_delim() {
-        assert_colmatch(CSV, r#","1","c2""#, [Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
-        assert_colmatch(Config {quote_char: '\'', ..DELIM_PIPE}, r#"|'1'|'c2'"#, [Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
+        assert_colmatch(CSV, r#","1","c2""#, &[Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
+        assert_colmatch(Config {quote_char: '\'', ..DELIM_PIPE}, r#"|'1'|'c2'"#, &[Ok("".to_string()), Ok("1".to_string()), Ok("c2".to_string())]);
     }
 
     #[test]
     fn columns_quoted_escape_before_delimiter_error() {
-        assert_colmatch(CSV, r#""foo"","bar""#, [Err(IoError {
-            kind: io::InvalidInput,
-            desc: "Expecting line terminator or delimiter",
-            detail: None})]);
+        assert_colmatch(CSV, r#""foo"","bar""#, &[Err(Error::UnexpectedChar('b', pos(0, 8)))]);
     }
 
-    fn assert_rowmatch(config: Config, s: &str, ex: Vec<IoResult<Row>>) {
-        let reader = io::BufReader::new(s.as_bytes());
-        let rows: Vec<IoResult<Row>> = read_rows(config, reader).collect();
+    fn assert_rowmatch(config: Config, s: &str, ex: Vec<Result<Row>>) {
+        let reader = s.as_bytes();
+        let rows = flatten_all(&read_rows(config, reader).collect::<Vec<_>>());
+        let ex = flatten_all(&ex);
         for (row, exrow) in rows.iter().zip(ex.iter()) {
             assert_eq!(row, exrow);
         }
         if rows.len() < ex.len() {
-            fail!("Missing rows: {}", ex.slice_from(rows.len()))
+            panic!("Missing rows: {:?}", &ex[rows.len()..])
         } else if rows.len() > ex.len() {
-            fail!("Unexpected rows: {}", rows.slice_from(ex.len()))
+            panic!("Unexpected rows: {:?}", &rows[ex.len()..])
         }
     }
 
@@ -675,28 +2234,486 @@ mod test {
 
     #[test]
     fn read_nextline_line_terminated() {
-        assert_rowmatch(Config {line_terminator: NEL, ..CSV}, "foo,bar\x85baz,qux", vec!(Ok(vec!("foo".to_string(), "bar".to_string())), Ok(vec!("baz".to_string(), "qux".to_string()))));
+        assert_rowmatch(Config {line_terminator: NEL, ..CSV}, "foo,bar\u{85}baz,qux", vec!(Ok(vec!("foo".to_string(), "bar".to_string())), Ok(vec!("baz".to_string(), "qux".to_string()))));
     }
 
     #[test]
     fn read_paragraph_separator_line_terminated() {
-        assert_rowmatch(Config {line_terminator: PS, ..CSV}, "foo,bar\u2029baz,qux", vec!(Ok(vec!("foo".to_string(), "bar".to_string())), Ok(vec!("baz".to_string(), "qux".to_string()))));
+        assert_rowmatch(Config {line_terminator: PS, ..CSV}, "foo,bar\u{2029}baz,qux", vec!(Ok(vec!("foo".to_string(), "bar".to_string())), Ok(vec!("baz".to_string(), "qux".to_string()))));
     }
 
     #[test]
     fn multiple_rows_unclosed_quote() {
-        assert_rowmatch(CSV, "foo,\"bar\r\nbaz,qux", vec!(Err(IoError {kind: io::EndOfFile,
-                    desc: "end of file",
-                    detail: None})));
+        assert_rowmatch(CSV, "foo,\"bar\r\nbaz,qux", vec!(Err(Error::UnterminatedQuote(pos(1, 4)))));
+    }
+
+    #[test]
+    fn error_position_in_later_row() {
+        let position = Position {record: 2, field: 1, line: 4, byte: 20};
+        assert_rowmatch(CSV, "a,b\r\n\"c\r\nd\",e\r\nf,\"g\"h", vec!(Ok(vec!("a".to_string(), "b".to_string())), Ok(vec!("c\r\nd".to_string(), "e".to_string())), Err(Error::UnexpectedChar('h', position))));
+    }
+
+    #[test]
+    fn read_record_reuses_record() {
+        let mut rows = from_str(CSV, "foo,\"b,ar\"\r\n\r\nbaz\r\n");
+        let mut record = StringRecord::new();
+        assert!(rows.read_record(&mut record).unwrap());
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!("foo", "b,ar"));
+        assert!(rows.read_record(&mut record).unwrap());
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!("baz"));
+        assert!(!rows.read_record(&mut record).unwrap());
+        assert!(record.is_empty());
+        assert_eq!(rows.position().record, 2);
+    }
+
+    #[test]
+    fn read_record_skips_headers() {
+        let mut rows = from_str(HEADERS, "a,b\r\nfoo,bar");
+        let mut record = StringRecord::new();
+        assert!(rows.read_record(&mut record).unwrap());
+        assert_eq!(record.get(1), Some("bar"));
+        assert_eq!(rows.headers().unwrap().unwrap().get(1), Some("b"));
+    }
+
+    #[test]
+    fn read_byte_record_keeps_invalid_utf8() {
+        let mut rows = read_rows(CSV, &b"\xe9t\xe9,\"\xff\"\"\"\r\nok,\xe2\x82"[..]);
+        let mut record = ByteRecord::new();
+        assert!(rows.read_byte_record(&mut record).unwrap());
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!(&b"\xe9t\xe9"[..], &b"\xff\""[..]));
+        assert!(rows.read_byte_record(&mut record).unwrap());
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!(&b"ok"[..], &b"\xe2\x82"[..]));
+        assert_eq!(record.position(), &Position {record: 1, field: 0, line: 2, byte: 11});
+        assert!(!rows.read_byte_record(&mut record).unwrap());
+    }
+
+    #[test]
+    fn invalid_utf8_is_rejected() {
+        let position = Position {record: 1, field: 1, line: 2, byte: 8};
+        let rows: Vec<Result<Row>> = read_rows(CSV, &b"a,b\r\nc,d\xe9"[..]).collect();
+        assert_eq!(flatten_all(&rows), flatten_all(&[Ok(vec!("a".to_string(), "b".to_string())), Err(Error::InvalidUtf8(position))]));
+    }
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        let config = ConfigBuilder::new(CSV).utf8(Lossy).build();
+        let rows: Vec<Result<Row>> = read_rows(config, &b"caf\xe9,\xf0\x9f\x92,x"[..]).collect();
+        assert_eq!(flatten_all(&rows), flatten_all(&[Ok(vec!("caf\u{FFFD}".to_string(), "\u{FFFD}".to_string(), "x".to_string()))]));
+    }
+
+    #[test]
+    fn read_encoded_data() {
+        let config = ConfigBuilder::new(CSV).encoding(WINDOWS_1252).build();
+        let rows: Vec<Result<Row>> = read_rows(config, &b"caf\xe9,\x80"[..]).collect();
+        assert_eq!(flatten_all(&rows), flatten_all(&[Ok(vec!("caf\u{e9}".to_string(), "\u{20ac}".to_string()))]));
+        let rows: Vec<Result<Row>> = read_rows(config, &b"\xff\xfea\x00,\x00\xe9\x00\r\x00\n\x00"[..]).collect();
+        assert_eq!(flatten_all(&rows), flatten_all(&[Ok(vec!("a".to_string(), "\u{e9}".to_string()))]));
+    }
+
+    #[test]
+    fn utf8_bom_is_skipped() {
+        let rows: Vec<Result<Row>> = read_rows(CSV, &b"\xef\xbb\xbfa,b"[..]).collect();
+        assert_eq!(flatten_all(&rows), flatten_all(&[Ok(vec!("a".to_string(), "b".to_string()))]));
+    }
+
+    #[test]
+    fn write_encoded_data() {
+        let mut writer = Vec::new();
+        let config = ConfigBuilder::new(CSV).encoding(UTF_16BE).bom(true).build();
+        write_rows(config, &mut writer, vec!(vec!("\u{e9}".to_string(), "b".to_string())).into_iter()).unwrap();
+        assert_eq!(&writer[..], b"\xfe\xff\x00\xe9\x00,\x00b\x00\r\x00\n");
+    }
+
+    #[test]
+    fn error_on_unencodable_char() {
+        let mut writer = Vec::new();
+        let config = ConfigBuilder::new(CSV).encoding(WINDOWS_1252).build();
+        let rows = vec!(vec!("a".to_string(), "b".to_string()), vec!("c".to_string(), "\u{3b1}".to_string()));
+        let res = write_rows(config, &mut writer, rows.into_iter());
+        let position = Position {record: 1, field: 1, line: 2, byte: 7};
+        assert_eq!(flatten(&res), flatten(&Err(Error::Unencodable('\u{3b1}', position))));
+        assert_eq!(&writer[..], b"a,b\r\nc,");
+    }
+
+    #[test]
+    fn universal_newlines_are_accepted() {
+        let config = ConfigBuilder::new(CSV).newlines(Universal).build();
+        assert_rowmatch(config, "a,b\nc,d\r\n\r\n\ne,\"f\r\n\"\rg,h\u{85}", vec!(Ok(vec!("a".to_string(), "b".to_string())), Ok(vec!("c".to_string(), "d".to_string())), Ok(vec!("e".to_string(), "f\r\n".to_string())), Ok(vec!("g".to_string(), "h\u{85}".to_string()))));
+        let mut rows = from_str(config, "a\r\nb\rc\n\"\r\n\"\nd,\"");
+        assert_eq!(rows.by_ref().take(4).count(), 4);
+        let position = Position {record: 4, field: 1, line: 6, byte: 14};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnterminatedQuote(position))));
+    }
+
+    #[test]
+    fn universal_unicode_newlines_are_accepted() {
+        let config = ConfigBuilder::new(CSV).newlines(UniversalUnicode).build();
+        assert_rowmatch(config, "a\u{85}b\u{2028}c\u{2029}d\r", vec!(Ok(vec!("a".to_string())), Ok(vec!("b".to_string())), Ok(vec!("c".to_string())), Ok(vec!("d".to_string()))));
+    }
+
+    #[test]
+    fn universal_newlines_write_configured_terminator() {
+        let mut writer = Vec::new();
+        let config = ConfigBuilder::new(CSV).newlines(Universal).build();
+        write_rows(config, &mut writer, vec!(vec!("a\nb".to_string(), "c".to_string())).into_iter()).unwrap();
+        assert_eq!(&writer[..], b"\"a\nb\",c\r\n");
     }
 
-    fn assert_column_written(config: Config, col: String, exp: &[u8], exp_res: IoResult<()>) {
-        let mut writer = io::MemWriter::new();
+    #[test]
+    fn comment_lines_are_skipped() {
+        let config = ConfigBuilder::new(HEADERS).comment(Some('#')).build();
+        assert_rowmatch(config, "#a\r\nh1,h2\r\n# b \r\nfoo,\"bar\r\n#baz\"\r\n#", vec!(Ok(vec!("foo".to_string(), "bar\r\n#baz".to_string()))));
+        let mut reader = "#a\r\n\r\n#b\r\nc,#d".as_bytes();
+        assert_eq!(read_row(config, &mut reader).unwrap(), vec!("c".to_string(), "#d".to_string()));
+    }
+
+    #[test]
+    fn comment_text_is_passed_to_callback() {
+        let comments = Arc::new(Mutex::new(Vec::new()));
+        let collected = comments.clone();
+        let config = ConfigBuilder::new(CSV).comment(Some('\u{2116}')).build();
+        let rows = from_str(config, "\u{2116}one\r\na\r\n\u{2116}two")
+            .on_comment(move |text, position| collected.lock().unwrap().push((text.to_string(), *position)));
+        assert_eq!(rows.count(), 1);
+        let position = Position {record: 1, field: 0, line: 3, byte: 11};
+        assert_eq!(*comments.lock().unwrap(), vec!(("one".to_string(), Position::new()), ("two".to_string(), position)));
+    }
+
+    #[test]
+    fn column_starting_with_comment_char_is_quoted() {
+        let mut writer = Vec::new();
+        let config = ConfigBuilder::new(CSV).comment(Some('#')).build();
+        write_row(config, &mut writer, vec!("#a".to_string(), "b#".to_string())).unwrap();
+        assert_eq!(&writer[..], b"\"#a\",b#\r\n");
+    }
+
+    #[test]
+    fn initial_space_before_quote_is_skipped() {
+        let config = ConfigBuilder::new(CSV).skip_initial_space(true).build();
+        assert_rowmatch(config, "a, \"b\",\t c \r\n", vec!(Ok(vec!("a".to_string(), "b".to_string(), "c ".to_string()))));
+    }
+
+    #[test]
+    fn fields_are_trimmed() {
+        let config = ConfigBuilder::new(HEADERS).trim(TrimFields).build();
+        assert_rowmatch(config, " h1 , h2 \r\n a ,\t\"  b \" \r\n , ", vec!(Ok(vec!("a".to_string(), "  b ".to_string())), Ok(vec!("".to_string(), "".to_string()))));
+        let mut rows = from_str(config, " h1 , h2 \r\n\"a\" b,c");
+        assert_eq!(rows.headers().unwrap().unwrap().get(0), Some(" h1 "));
+        let position = Position {record: 1, field: 0, line: 2, byte: 15};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnexpectedChar('b', position))));
+    }
+
+    #[test]
+    fn headers_are_trimmed() {
+        let config = ConfigBuilder::new(HEADERS).trim(TrimHeaders).build();
+        let mut rows = from_str(config, " h1 ,\t\"h2\" \r\n a , b ");
+        let headers = rows.headers().unwrap().unwrap().clone();
+        assert_eq!((headers.get(0), headers.get(1)), (Some("h1"), Some("h2")));
+        assert_eq!(rows.next().unwrap().unwrap(), vec!(" a ".to_string(), " b ".to_string()));
+        let config = ConfigBuilder::new(CSV).delimiter('\t').trim(TrimAll).build();
+        assert_rowmatch(config, " a\t\tb ", vec!(Ok(vec!("a".to_string(), "".to_string(), "b".to_string()))));
+    }
+
+    #[test]
+    fn error_on_unequal_lengths_when_not_flexible() {
+        let config = ConfigBuilder::new(CSV).flexible(false).build();
+        let mut rows = from_str(config, "a,b\r\nc\r\nd,e\r\nf,g,h");
+        assert_eq!(rows.next().unwrap().unwrap(), vec!("a".to_string(), "b".to_string()));
+        let position = Position {record: 1, field: 0, line: 2, byte: 5};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnequalLengths(2, 1, position))));
+        assert_eq!(rows.next().unwrap().unwrap(), vec!("d".to_string(), "e".to_string()));
+        let position = Position {record: 3, field: 0, line: 4, byte: 13};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnequalLengths(2, 3, position))));
+        assert!(rows.next().is_none());
+        assert_rowmatch(CSV, "a,b\r\nc", vec!(Ok(vec!("a".to_string(), "b".to_string())), Ok(vec!("c".to_string()))));
+    }
+
+    #[test]
+    fn unequal_lengths_are_recovered() {
+        let config = ConfigBuilder::new(CSV).flexible(false).build();
+        let mut rows = from_str(config, "a,b\r\nc\r\n\"d\"e\r\nf,g").recovery(Skip);
+        rows.next();
+        let position = Position {record: 1, field: 0, line: 2, byte: 5};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnequalLengths(2, 1, position))));
+        assert!(rows.next().unwrap().is_err());
+        assert_eq!(rows.next().unwrap().unwrap(), vec!("f".to_string(), "g".to_string()));
+        let mut rows = from_str(config, "a,b\r\nc\r\nd,e").recovery(Collect);
+        assert_eq!(rows.by_ref().count(), 2);
+        let rejected = rows.take_rejected();
+        assert_eq!(rejected[0].raw, b"c");
+        assert_eq!(format!("{:?}", rejected[0].error), format!("{:?}", Error::UnequalLengths(2, 1, position)));
+    }
+
+    #[test]
+    fn row_length_is_compared_with_header() {
+        let config = ConfigBuilder::new(HEADERS).flexible(false).build();
+        let mut rows = from_str(config, "h1,h2,h3\r\na,b");
+        let position = Position {record: 1, field: 0, line: 2, byte: 10};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnequalLengths(3, 2, position))));
+    }
+
+    #[test]
+    fn reading_stops_at_first_error() {
+        let mut rows = from_str(CSV, "a\r\n\"b\"c\r\nd");
+        assert!(rows.next().unwrap().is_ok());
+        assert!(rows.next().unwrap().is_err());
+        assert!(rows.next().is_none());
+        assert_eq!(*rows.position(), Position {record: 1, field: 0, line: 2, byte: 7});
+    }
+
+    #[test]
+    fn bad_records_are_skipped() {
+        let config = ConfigBuilder::new(CSV).comment(Some('#')).build();
+        let mut rows = from_str(config, "a,\"b\"x,y\r\n#c\r\n\"d\r\ne\r\n\"\"f");
+        let position = Position {record: 0, field: 1, line: 1, byte: 5};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnexpectedChar('x', position))));
+        assert!(rows.next().is_none());
+        let mut rows = from_str(config, "a,\"b\"x,y\r\n#c\r\n\"d\r\ne\r\n\"\"f").recovery(Skip);
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnexpectedChar('x', position))));
+        assert_eq!(rows.position().line, 2);
+        let position = Position {record: 1, field: 0, line: 3, byte: 14};
+        assert_eq!(flatten(&rows.next().unwrap()), flatten(&Err(Error::UnterminatedQuote(position))));
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn bad_records_are_collected() {
+        let config = ConfigBuilder::new(HEADERS).flexible(false).build();
+        let data = "h1,h2\r\na,\"b\"x,y\r\n\r\nc,d\r\ne\r\n\"f\u{e9}\"g,h\r\ni,j";
+        let mut rows = from_str(config, data).recovery(Collect);
+        assert_eq!(rows.by_ref().collect::<Result<Vec<_>>>().unwrap(), vec!(vec!("c".to_string(), "d".to_string()), vec!("i".to_string(), "j".to_string())));
+        let rejected = rows.take_rejected();
+        assert_eq!(rejected.iter().map(|r| &r.raw[..]).collect::<Vec<_>>(), vec!(&b"a,\"b\"x,y"[..], b"e", "\"f\u{e9}\"g,h".as_bytes()));
+        let position = Position {record: 4, field: 0, line: 6, byte: 32};
+        assert_eq!(format!("{:?}", rejected[2].error), format!("{:?}", Error::UnexpectedChar('g', position)));
+        assert!(rows.rejected().is_empty());
+        assert_eq!(rows.position().record, 6);
+    }
+
+    static DUMP: Config = Config {escape: Backslash, line_terminator: LF, quote: Never, ..TSV};
+
+    #[test]
+    fn backslash_escapes_are_decoded() {
+        let data = "a\\tb\\\\\tc\\\nd\t\\N\tN\\N\t\\x\\0\n\"e\\\"\\n\"\tf\\\t\n";
+        assert_rowmatch(DUMP, data, vec!(
+            Ok(vec!("a\tb\\".to_string(), "c\nd".to_string(), "".to_string(), "NN".to_string(), "x\0".to_string())),
+            Ok(vec!("\"e\"\n\"".to_string(), "f\t".to_string()))));
+        let config = ConfigBuilder::new(CSV).escape(Backslash).build();
+        assert_rowmatch(config, "\"a\\\"b\",c\\,d\\\r\n\r\n", vec!(Ok(vec!("a\"b".to_string(), "c,d\r\n".to_string()))));
+        let position = Position {record: 0, field: 0, line: 1, byte: 3};
+        assert_eq!(flatten(&read_row(DUMP, &mut "ab\\".as_bytes())), flatten(&Err(Error::UnexpectedEof(position))));
+    }
+
+    #[test]
+    fn backslash_escapes_are_written() {
+        let mut writer = Vec::new();
+        let row = vec!("a\tb\\".to_string(), "c\r\nd".to_string(), "\0\"".to_string(), " e ".to_string());
+        write_row(DUMP, &mut writer, row).unwrap();
+        assert_eq!(&writer[..], b"a\\tb\\\\\tc\\r\\nd\t\\0\"\t e \n");
+        let mut writer = Vec::new();
+        assert!(matches!(write_row(DUMP, &mut writer, vec!("".to_string())), Err(Error::MustQuote(_))));
+        let config = ConfigBuilder::new(CSV).escape(Backslash).comment(Some('#')).build();
+        let mut writer = Vec::new();
+        write_row(config, &mut writer, vec!("#a,\"b\"".to_string(), "".to_string())).unwrap();
+        assert_eq!(&writer[..], b"\\#a\\,\\\"b\\\",\r\n");
+    }
+
+    fn read_nullable(config: Config, data: &str) -> Vec<Vec<Option<String>>> {
+        let mut rows = from_str(config, data);
+        let mut record = StringRecord::new();
+        let mut read = Vec::new();
+        while rows.read_record(&mut record).unwrap() {
+            read.push(record.to_nullable_row());
+        }
+        read
+    }
+
+    #[test]
+    fn null_marker_is_read() {
+        let config = ConfigBuilder::new(CSV).null(NullMarker("NULL")).trim(TrimFields).build();
+        assert_eq!(read_nullable(config, "NULL, NULL ,\"NULL\",NULLx,\r\nNULL"), vec!(
+            vec!(None, None, Some("NULL".to_string()), Some("NULLx".to_string()), Some("".to_string())),
+            vec!(None)));
+        assert_eq!(read_nullable(ConfigBuilder::new(DUMP).null(NullMarker("\\N")).build(), "\\N\t\\\\N\tN"),
+            vec!(vec!(None, Some("\\N".to_string()), Some("N".to_string()))));
+        let config = ConfigBuilder::new(DUMP).null(NullMarker("NULL")).build();
+        assert_eq!(read_nullable(config, "\\N\tNULL\tNUL\\L"), vec!(vec!(None, None, Some("NULL".to_string()))));
+        assert_eq!(read_nullable(CSV, "NULL,"), vec!(vec!(Some("NULL".to_string()), Some("".to_string()))));
+    }
+
+    #[test]
+    fn unquoted_empty_is_null() {
+        let config = ConfigBuilder::new(CSV).null(UnquotedEmpty).build();
+        assert_eq!(read_nullable(config, ",\"\"\r\n\r\n\"\""), vec!(
+            vec!(None, Some("".to_string())),
+            vec!(Some("".to_string()))));
+        assert_rowmatch(config, ",a", vec!(Ok(vec!("".to_string(), "a".to_string()))));
+    }
+
+    #[test]
+    fn nulls_are_written() {
+        let row = vec!(None, Some("".to_string()), Some("NULL".to_string()), Some("\\N".to_string()));
+        let written = |config: Config| {
+            let mut writer = Vec::new();
+            write_nullable_row(config, &mut writer, row.clone()).unwrap();
+            String::from_utf8(writer).unwrap()
+        };
+        assert_eq!(written(CSV), ",,NULL,\\N\r\n");
+        assert_eq!(written(ConfigBuilder::new(CSV).null(NullMarker("NULL")).build()), "NULL,,\"NULL\",\\N\r\n");
+        assert_eq!(written(ConfigBuilder::new(CSV).null(UnquotedEmpty).build()), ",\"\",NULL,\\N\r\n");
+        assert_eq!(written(DUMP), "\\N\t\tNULL\t\\\\N\n");
+        assert_eq!(written(ConfigBuilder::new(DUMP).null(NullMarker("\\N")).build()), "\\N\t\tNULL\t\\\\N\n");
+        let mut writer = Vec::new();
+        let res = write_nullable_row(ConfigBuilder::new(DUMP).null(NullMarker("NULL")).build(), &mut writer, row.clone());
+        assert!(matches!(res, Err(Error::MustQuote(_))));
+        let res = write_nullable_row(ConfigBuilder::new(CSV).null(UnquotedEmpty).build(), &mut writer, vec!(None));
+        assert!(matches!(res, Err(Error::MustQuote(_))));
+    }
+
+    /// Columns with whether they are borrowed
+    type Borrowed = Vec<(String, bool)>;
+
+    /// Columns of borrowed records and their positions
+    fn read_borrowed(config: Config, data: &[u8]) -> Vec<Flat<(Borrowed, Position)>> {
+        let mut rows = read_rows(config, data).recovery(Skip);
+        let mut record = BorrowedRecord::new();
+        let mut read = Vec::new();
+        loop {
+            match rows.read_borrowed_record(&mut record) {
+                Ok(true) => {
+                    let cols = record.iter().map(|col| (col.to_string(), matches!(col, Cow::Borrowed(_)))).collect();
+                    read.push(Ok((cols, *record.position())));
+                }
+                Ok(false) => return read,
+                Err(err) => read.push(Err(format!("{:?}", err)))
+            }
+        }
+    }
+
+    #[test]
+    fn borrowed_records_borrow_columns() {
+        let col = |col: &str, borrowed| (col.to_string(), borrowed);
+        let config = ConfigBuilder::new(CSV).flexible(false).build();
+        assert_eq!(read_borrowed(config, b"\xef\xbb\xbfa,\"b\"\"c\",\"d\"\r\n\r\ne,\xc3\xa9,\r\n\"f\"g\r\nh\r\ni,j"), vec!(
+            Ok((vec!(col("a", true), col("b\"c", false), col("d", true)), Position::new())),
+            Ok((vec!(col("e", true), col("\u{e9}", true), col("", true)), Position {record: 1, line: 3, byte: 16, ..Position::new()})),
+            Err("UnexpectedChar('g', Position { record: 2, field: 0, line: 4, byte: 26 })".to_string()),
+            Err("UnequalLengths(3, 1, Position { record: 3, field: 0, line: 5, byte: 29 })".to_string()),
+            Err("UnequalLengths(3, 2, Position { record: 4, field: 0, line: 6, byte: 32 })".to_string())));
+        let config = ConfigBuilder::new(CSV).trim(TrimAll).null(UnquotedEmpty).build();
+        assert_eq!(read_borrowed(config, b" a ,"), vec!(Ok((vec!(col("a", false), col("", true)), Position::new()))));
+        let mut rows = read_rows(ConfigBuilder::new(CSV).null(UnquotedEmpty).build(), &b"a,,\"\""[..]);
+        let mut record = BorrowedRecord::new();
+        rows.read_borrowed_record(&mut record).unwrap();
+        assert!(!record.is_null(0) && record.is_null(1) && !record.is_null(2));
+    }
+
+    static HEADERS: Config = Config {has_headers: true, ..CSV};
+
+    #[test]
+    fn records_are_written_one_at_a_time() {
+        let config = ConfigBuilder::new(HEADERS).quote(Never).bom(true).build();
+        let mut writer = Writer::new(config, Vec::new()).line_terminator(LF);
+        assert_eq!(flatten(&writer.write_record(&["a", "b,c"])), flatten(&Err(Error::MustQuote(Position {field: 1, byte: 2, ..Position::new()}))));
+        writer.write_header(&["h1", "h2"]).unwrap();
+        writer.write_nullable_record(&[Some("a".to_string()), None]).unwrap();
+        writer.flush().unwrap();
+        let mut record = StringRecord::new();
+        record.push_field("b");
+        record.push_null();
+        writer.write_string_record(&record).unwrap();
+        let position = Position {record: 3, line: 4, byte: 12, ..Position::new()};
+        assert_eq!(flatten(&writer.write_header(&["h1"])), flatten(&Err(Error::HeaderNotFirst(position))));
+        assert_eq!(writer.into_inner().unwrap(), b"\xef\xbb\xbfh1,h2\na,\nb,\n");
+        let mut writer = Writer::new(HEADERS, Vec::new());
+        writer.serialize(&[("a", 1)]).unwrap();
+        writer.serialize(&[("b", 2)]).unwrap();
+        assert_eq!(writer.into_inner().unwrap(), b"a,1\r\nb,2\r\n");
+    }
+
+    #[test]
+    fn header_row_is_not_returned_as_row() {
+        assert_rowmatch(HEADERS, "a,b\r\nfoo,bar", vec!(Ok(vec!("foo".to_string(), "bar".to_string()))));
+    }
+
+    #[test]
+    fn headers_are_read_before_first_row() {
+        let mut rows = from_str(HEADERS, "\r\na,b\r\nfoo,bar");
+        assert_eq!(rows.headers().unwrap().unwrap().as_row(), &vec!("a".to_string(), "b".to_string()));
+        assert_eq!(rows.next().unwrap().unwrap(), vec!("foo".to_string(), "bar".to_string()));
+        assert!(rows.next().is_none());
+    }
+
+    #[test]
+    fn headers_of_empty_data() {
+        let mut rows = from_str(HEADERS, "");
+        assert!(rows.headers().unwrap().unwrap().is_empty());
+        assert!(rows.next().is_none());
+        assert!(from_str(CSV, "a,b").headers().unwrap().is_none());
+    }
+
+    #[test]
+    fn record_column_by_name() {
+        let mut records = from_str(HEADERS, "a,b\r\nfoo,bar\r\nbaz").records();
+        let record = records.next().unwrap().unwrap();
+        assert_eq!(record.get_by_name("a"), Some("foo"));
+        assert_eq!(record.get_by_name("b"), Some("bar"));
+        assert_eq!(record.get_by_name("c"), None);
+        let record = records.next().unwrap().unwrap();
+        assert_eq!(record.get_by_name("a"), Some("baz"));
+        assert_eq!(record.get_by_name("b"), None);
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn record_without_headers_has_no_names() {
+        let record = from_str(CSV, "a,b").records().next().unwrap().unwrap();
+        assert_eq!(record.get(1), Some("b"));
+        assert_eq!(record.get_by_name("a"), None);
+    }
+
+    #[test]
+    fn duplicate_headers_keep_first() {
+        let record = from_str(HEADERS, "a,a\r\n1,2").records().next().unwrap().unwrap();
+        assert_eq!(record.get_by_name("a"), Some("1"));
+    }
+
+    #[test]
+    fn duplicate_headers_renamed() {
+        let config = ConfigBuilder::new(HEADERS).duplicate_headers(Rename).build();
+        let mut rows = from_str(config, "a,a,a_2,a\r\n");
+        let names: Vec<&str> = rows.headers().unwrap().unwrap().iter().map(|s| &s[..]).collect();
+        assert_eq!(names, vec!("a", "a_2", "a_2_2", "a_3"));
+    }
+
+    #[test]
+    fn duplicate_headers_rejected() {
+        let config = ConfigBuilder::new(HEADERS).duplicate_headers(RejectDuplicates).build();
+        let position = Position {field: 2, ..Position::new()};
+        assert_rowmatch(config, "a,b,a\r\n1,2,3", vec!(Err(Error::DuplicateHeader("a".to_string(), position))));
+    }
+
+    #[test]
+    fn empty_headers_generated() {
+        let config = ConfigBuilder::new(HEADERS).empty_headers(Generate).build();
+        let record = from_str(config, "a,,c\r\n1,2,3").records().next().unwrap().unwrap();
+        assert_eq!(record.get_by_name("column_2"), Some("2"));
+    }
+
+    #[test]
+    fn empty_headers_rejected() {
+        let config = ConfigBuilder::new(HEADERS).empty_headers(RejectEmpty).build();
+        let position = Position {field: 1, ..Position::new()};
+        assert_rowmatch(config, "a,\r\n1,2", vec!(Err(Error::EmptyHeader(position))));
+    }
+
+    fn assert_column_written(config: Config, col: String, exp: &[u8], exp_res: Result<()>) {
+        let mut writer = Vec::new();
         let res = {
-            write_column(config, &mut writer, col.as_slice())
+            write_column(config, &mut writer, &col, false, &mut Position::new())
         };
-        assert_eq!(res, exp_res);
-        assert_eq!(exp, writer.get_ref());
+        assert_eq!(flatten(&res), flatten(&exp_res));
+        assert_eq!(exp, &writer[..]);
     }
 
     #[test]
@@ -712,9 +2729,54 @@ mod test {
         assert_column_written(Config {quote: Always, ..CSV}, "bar".to_string(), b"\"bar\"", Ok(()));
     }
 
+    #[test]
+    fn written_column_with_quote_char_or_surrounding_whitespace_is_quoted() {
+        assert_column_written(CSV, "5\"".to_string(), b"\"5\"\"\"", Ok(()));
+        assert_column_written(CSV, " a".to_string(), b"\" a\"", Ok(()));
+        assert_column_written(CSV, "a\t".to_string(), b"\"a\t\"", Ok(()));
+        assert_column_written(CSV, "a b".to_string(), b"a b", Ok(()));
+        assert_column_written(TSV, " a".to_string(), b" a", Ok(()));
+    }
+
+    #[test]
+    fn single_empty_column_is_quoted() {
+        let mut writer = Vec::new();
+        write_rows(CSV, &mut writer, vec!(vec!("".to_string()), vec!("".to_string(), "".to_string())).into_iter()).unwrap();
+        assert_eq!(&writer[..], b"\"\"\r\n,\r\n");
+        assert_rowmatch(CSV, "\"\"\r\n,\r\n", vec!(Ok(vec!("".to_string())), Ok(vec!("".to_string(), "".to_string()))));
+        let res = write_row(TSV, &mut Vec::new(), vec!("".to_string()));
+        assert_eq!(flatten(&res), flatten(&Err(Error::MustQuote(Position::new()))));
+    }
+
+    #[test]
+    fn non_numeric_columns_are_quoted() {
+        let config = Config {quote: NonNumeric, ..CSV};
+        for &number in ["1", "-2.5", "+.5", "3.", "1e10", "6.02E-23"].iter() {
+            assert_column_written(config, number.to_string(), number.as_bytes(), Ok(()));
+        }
+        for &text in ["", "a", "1a", ".", "-", "1e", "inf", "NaN", "0x1F"].iter() {
+            assert_column_written(config, text.to_string(), format!("\"{}\"", text).as_bytes(), Ok(()));
+        }
+        assert_column_written(Config {delimiter: '.', ..config}, "1.5".to_string(), b"\"1.5\"", Ok(()));
+    }
+
+    #[test]
+    fn columns_are_quoted_by_index() {
+        static QUOTES: [Quote; 3] = [Always, NonNumeric, Never];
+        let config = Config {quote: PerColumn(&QUOTES), ..CSV};
+        let mut writer = Vec::new();
+        let row = vec!("a".to_string(), "b".to_string(), "c d".to_string(), "e,f".to_string());
+        write_row(config, &mut writer, row).unwrap();
+        assert_eq!(&writer[..], b"\"a\",\"b\",c d,\"e,f\"\r\n");
+        let position = Position {field: 2, byte: 6, ..Position::new()};
+        let res = write_row(config, &mut Vec::new(), vec!("1".to_string(), "2".to_string(), "3,4".to_string()));
+        assert_eq!(flatten(&res), flatten(&Err(Error::MustQuote(position))));
+        assert_rowmatch(config, "\"a\",\"b\",\"c\"", vec!(Ok(vec!("a".to_string(), "b".to_string(), "\"c\"".to_string()))));
+    }
+
     #[test]
     fn error_on_writing_value_that_should_be_quoted() {
-        assert_column_written(Config {quote: Never, ..DELIM_PIPE}, "a|b".to_string(), b"", Err(MUST_QUOTE.clone()))
+        assert_column_written(Config {quote: Never, ..DELIM_PIPE}, "a|b".to_string(), b"", Err(Error::MustQuote(Position::new())))
     }
 
     #[test]
@@ -725,58 +2787,44 @@ mod test {
 
     #[test]
     fn error_when_writing_quoted_column_with_escape_disallowed() {
-        assert_column_written(Config {escape: Disallowed, ..QUOTE_TILDE}, "Hello, ~world~".to_string(), b"~Hello, ", Err(ESCAPE_DISALLOWED.clone()));
+        assert_column_written(Config {escape: Disallowed, ..QUOTE_TILDE}, "Hello, ~world~".to_string(), b"~Hello, ", Err(Error::EscapeDisallowed(Position::new())));
     }
 
     #[test]
     fn writen_quoted_column_can_not_cantain_escape_char() {
-        assert_column_written(Config {escape: Char('?'), quote: Always, ..CSV}, "Hello?".to_string(), b"\"Hello", Err(ESCAPE_CHAR_IN_QUOTE.clone()));
+        assert_column_written(Config {escape: Char('?'), quote: Always, ..CSV}, "Hello?".to_string(), b"\"Hello", Err(Error::EscapeCharInQuote(Position::new())));
+    }
+
+    #[test]
+    fn error_position_when_writing_rows() {
+        let mut writer = Vec::new();
+        let res = {
+            let rows = vec!(vec!("a".to_string(), "b".to_string()), vec!("c".to_string(), "d|e".to_string()));
+            write_rows(Config {quote: Never, ..DELIM_PIPE}, &mut writer, rows.into_iter())
+        };
+        let position = Position {record: 1, field: 1, line: 2, byte: 7};
+        assert_eq!(flatten(&res), flatten(&Err(Error::MustQuote(position))));
     }
 
     #[test]
     fn line_ending_is_written() {
-        let mut writer = io::MemWriter::new();
+        let mut writer = Vec::new();
         let res = {
             let rows = vec!("foo".to_string(), "bar".to_string());
             write_row(CSV, &mut writer, rows)
         };
-        assert_eq!(Ok(()), res);
-        assert_eq!(b"foo,bar\r\n", writer.get_ref());
+        assert!(res.is_ok());
+        assert_eq!(b"foo,bar\r\n", &writer[..]);
     }
 
     #[test]
     fn rows_are_written_correctly() {
-        let mut writer = io::MemWriter::new();
+        let mut writer = Vec::new();
         let res = {
             let rows = vec!(vec!("foo".to_string(), "b|ar".to_string()), vec!("b\r\naz".to_string(), "qux".to_string()));
             write_rows(DELIM_PIPE, &mut writer, rows.into_iter())
         };
-        assert_eq!(Ok(()), res);
-        assert_eq!(b"foo|\"b|ar\"\r\n\"b\r\naz\"|qux\r\n", writer.get_ref());
-    }
-}
-
-#[cfg(test)]
-mod bench {
-    extern crate test;
-
-    use self::test::Bencher;
-
-    use super::{from_file, CSV};
-
-    #[bench]
-    fn read_medium(b: &mut Bencher) {
-        let path = Path::new("data/medium.csv");
-        b.iter(|| {
-            for _ in from_file(CSV, &path) {}
-        })
-    }
-
-    #[bench]
-    fn read_short(b: &mut Bencher) {
-        let path = Path::new("data/short.csv");
-        b.iter(|| {
-            for _ in from_file(CSV, &path) {}
-        })
+        assert!(res.is_ok());
+        assert_eq!(b"foo|\"b|ar\"\r\n\"b\r\naz\"|qux\r\n", &writer[..]);
     }
 }
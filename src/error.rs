@@ -0,0 +1,201 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+/// Location in the data being read or written
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub struct Position {
+    /// Index of the record, starting at 0
+    pub record: u64,
+    /// Index of the field inside of the record, starting at 0
+    pub field: u64,
+    /// Line number, starting at 1
+    pub line: u64,
+    /// Byte offset from the beginning of the data
+    pub byte: u64,
+}
+
+impl Position {
+    /// Position at the very beginning of the data
+    pub fn new() -> Position {
+        Position {
+            record: 0,
+            field: 0,
+            line: 1,
+            byte: 0
+        }
+    }
+}
+
+impl Default for Position {
+    fn default() -> Position {
+        Position::new()
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "record {}, field {} (line {}, byte {})", self.record, self.field, self.line, self.byte)
+    }
+}
+
+/// Error that occurred while reading or writing tabular data
+#[derive(Debug)]
+pub enum Error {
+    /// Error of the underlying reader or writer
+    Io(io::Error),
+    /// Data is not valid UTF-8
+    InvalidUtf8(Position),
+    /// Data ended in the middle of a record
+    UnexpectedEof(Position),
+    /// Quoted column is not closed before the end of data, position is the start of the column
+    UnterminatedQuote(Position),
+    /// Line terminator does not match the configured one
+    InvalidLineEnding(Position),
+    /// Character other than delimiter or line terminator follows the closing quote
+    UnexpectedChar(char, Position),
+    /// Escape character inside of quoted column is not followed by quote character
+    InvalidEscape(Position),
+    /// Column value requires quoting but quoting is disabled, or an empty null column is
+    /// alone in its row
+    MustQuote(Position),
+    /// Column value contains quote character but escaping is disallowed
+    EscapeDisallowed(Position),
+    /// Quoted column value contains the escape character
+    EscapeCharInQuote(Position),
+    /// Column value is longer than the width of column
+    ColumnTooLong(Position),
+    /// Columns of a row are longer than the fixed row width
+    RowTooLong(Position),
+    /// Header name occurs more than once
+    DuplicateHeader(String, Position),
+    /// Header name is empty
+    EmptyHeader(Position),
+    /// Row can not be deserialized into the requested type
+    Deserialize(String, Position),
+    /// Value can not be serialized into a row
+    Serialize(String, Position),
+    /// Character can not be represented in the output encoding
+    Unencodable(char, Position),
+    /// Row has a different number of columns than the header or the first row,
+    /// contains the expected and the actual number of columns
+    UnequalLengths(usize, usize, Position),
+    /// Header is written after other rows
+    HeaderNotFirst(Position),
+    /// Row can not be located by its index because its characters are not one byte long
+    UnlocatableRow(Position),
+}
+
+impl Error {
+    /// Position where the error occurred, `None` for I/O errors
+    pub fn position(&self) -> Option<&Position> {
+        match *self {
+            Error::Io(_) => None,
+            Error::InvalidUtf8(ref pos)
+            | Error::UnexpectedEof(ref pos)
+            | Error::UnterminatedQuote(ref pos)
+            | Error::InvalidLineEnding(ref pos)
+            | Error::UnexpectedChar(_, ref pos)
+            | Error::InvalidEscape(ref pos)
+            | Error::MustQuote(ref pos)
+            | Error::EscapeDisallowed(ref pos)
+            | Error::EscapeCharInQuote(ref pos)
+            | Error::ColumnTooLong(ref pos)
+            | Error::RowTooLong(ref pos)
+            | Error::DuplicateHeader(_, ref pos)
+            | Error::EmptyHeader(ref pos)
+            | Error::Deserialize(_, ref pos)
+            | Error::Serialize(_, ref pos)
+            | Error::Unencodable(_, ref pos)
+            | Error::UnequalLengths(_, _, ref pos)
+            | Error::HeaderNotFirst(ref pos)
+            | Error::UnlocatableRow(ref pos) => Some(pos),
+        }
+    }
+
+    pub(crate) fn position_mut(&mut self) -> Option<&mut Position> {
+        match *self {
+            Error::Io(_) => None,
+            Error::InvalidUtf8(ref mut pos)
+            | Error::UnexpectedEof(ref mut pos)
+            | Error::UnterminatedQuote(ref mut pos)
+            | Error::InvalidLineEnding(ref mut pos)
+            | Error::UnexpectedChar(_, ref mut pos)
+            | Error::InvalidEscape(ref mut pos)
+            | Error::MustQuote(ref mut pos)
+            | Error::EscapeDisallowed(ref mut pos)
+            | Error::EscapeCharInQuote(ref mut pos)
+            | Error::ColumnTooLong(ref mut pos)
+            | Error::RowTooLong(ref mut pos)
+            | Error::DuplicateHeader(_, ref mut pos)
+            | Error::EmptyHeader(ref mut pos)
+            | Error::Deserialize(_, ref mut pos)
+            | Error::Serialize(_, ref mut pos)
+            | Error::Unencodable(_, ref mut pos)
+            | Error::UnequalLengths(_, _, ref mut pos)
+            | Error::HeaderNotFirst(ref mut pos)
+            | Error::UnlocatableRow(ref mut pos) => Some(pos),
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match *self {
+            Error::Io(_) => "I/O error",
+            Error::InvalidUtf8(_) => "Invalid UTF-8",
+            Error::UnexpectedEof(_) => "Unexpected end of data",
+            Error::UnterminatedQuote(_) => "Unterminated quoted column",
+            Error::InvalidLineEnding(_) => "Invalid line ending",
+            Error::UnexpectedChar(..) => "Expecting line terminator or delimiter",
+            Error::InvalidEscape(_) => "Expecting quote char",
+            Error::MustQuote(_) => "Value should be quoted",
+            Error::EscapeDisallowed(_) => "Escaping disallowed",
+            Error::EscapeCharInQuote(_) => "Escape character not allowed in quote",
+            Error::ColumnTooLong(_) => "Column too long",
+            Error::RowTooLong(_) => "Row too long",
+            Error::DuplicateHeader(..) => "Duplicate header name",
+            Error::EmptyHeader(_) => "Empty header name",
+            Error::Deserialize(..) => "Cannot deserialize row",
+            Error::Serialize(..) => "Cannot serialize row",
+            Error::Unencodable(..) => "Character cannot be encoded",
+            Error::UnequalLengths(..) => "Unequal number of columns",
+            Error::HeaderNotFirst(_) => "Header must be the first row",
+            Error::UnlocatableRow(_) => "Row cannot be located by index",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "{}: {}", self.description(), err),
+            Error::UnexpectedChar(ch, ref pos) | Error::Unencodable(ch, ref pos) => write!(f, "{}, found {:?} at {}", self.description(), ch, pos),
+            Error::UnequalLengths(expected, len, ref pos) => {
+                write!(f, "{}, expected {} but found {} at {}", self.description(), expected, len, pos)
+            }
+            Error::DuplicateHeader(ref name, ref pos) => write!(f, "{} {:?} at {}", self.description(), name, pos),
+            Error::Deserialize(ref msg, ref pos) | Error::Serialize(ref msg, ref pos) => {
+                write!(f, "{}, {} at {}", self.description(), msg, pos)
+            }
+            _ => write!(f, "{} at {}", self.description(), self.position().unwrap()),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+/// Result of reading or writing tabular data
+pub type Result<T> = result::Result<T, Error>;
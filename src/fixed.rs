@@ -1,13 +1,29 @@
 //! Reading and writing of data with fixed-width columns and rows
-use std::io;
-use std::io::{IoResult, IoError};
-use std::string::String;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, Write};
+use std::mem;
+use std::path::Path;
+use std::str;
 
-pub use common::{LineTerminator, Row, LF, CR, CRLF, VT, FF, NEL, LS, PS};
-use common::INVALID_LINE_ENDING;
+use encoding_rs::{Encoding, UTF_8};
+use serde::ser::Serialize;
+
+pub use crate::common::{LineTerminator, Row, LF, CR, CRLF, VT, FF, NEL, LS, PS};
+pub use crate::mmap::MappedFile;
+pub use crate::record::BorrowedRecord;
+pub use crate::recovery::{Recovery, Rejected, RejectWriter, Quarantine, Stop, Skip, Collect};
+use crate::common::read_raw_char;
+use crate::error::{Error, Position, Result};
+use crate::recovery::{strip_terminator, Recorder};
+use crate::ser::serialize_row;
+use crate::transcode::{self, DecodeReader, EncodeWriter};
+
+pub use self::Justification::*;
+pub use self::LineEnding::*;
 
 /// Text justification
-#[deriving(Eq, PartialEq, Clone)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum Justification {
     /// Justify left, pad right
     Left,
@@ -16,21 +32,21 @@ pub enum Justification {
 }
 
 /// Line ending rule
-#[deriving(Eq, PartialEq, Clone)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub enum LineEnding {
     /// No row separation, columns of adjacent rows are next to another
     Nothing,
     /// Row is always of set length, unused characters are ignored
-    FixedWidth(uint),
+    FixedWidth(usize),
     /// Rows are separated by newline line terminator
     Newline(LineTerminator),
 }
 
 /// Contains configuration parameters for reading and writing columns
-#[deriving(Eq, PartialEq, Clone)]
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
 pub struct ColumnConfig {
     /// Width of column
-    pub width: uint,
+    pub width: usize,
     /// Character used for padding when data in column < width of column
     pub pad_with: char,
     /// Justification of column data
@@ -38,57 +54,96 @@ pub struct ColumnConfig {
 }
 
 /// Contains configuration parameters for reading and writing
-#[deriving(Eq, PartialEq, Clone)]
+#[derive(Eq, PartialEq, Clone, Debug)]
 pub struct Config {
     /// Column configurations
     pub columns: Vec<ColumnConfig>,
     /// Line ending rule
     pub line_end: LineEnding,
+    /// Character encoding of the data, a byte order mark found when reading overrides it
+    ///
+    /// Column widths are counted in characters, byte offsets in positions count the data as UTF-8.
+    pub encoding: &'static Encoding,
+    /// Write a byte order mark at the start of data
+    pub bom: bool,
+}
+
+impl Config {
+    /// Configuration for UTF-8 data without a byte order mark
+    pub fn new(columns: Vec<ColumnConfig>, line_end: LineEnding) -> Config {
+        Config {
+            columns,
+            line_end,
+            encoding: UTF_8,
+            bom: false
+        }
+    }
 }
 
 struct Columns<'a, R: 'a> {
     reader: &'a mut R,
     config: Config,
-    column: uint,
-    pos: uint,
+    column: usize,
+    pos: usize,
+    position: Position,
     done: bool,
 }
 
-impl<'a, R: Buffer> Columns<'a, R> {
+fn is_eof(err: &Error) -> bool {
+    matches!(*err, Error::UnexpectedEof(_))
+}
+
+impl<'a, R: BufRead> Columns<'a, R> {
+    fn new(reader: &'a mut R, config: Config, position: Position) -> Columns<'a, R> {
+        Columns {
+            reader,
+            config,
+            column: 0,
+            pos: 0,
+            position,
+            done: false
+        }
+    }
+
     #[inline(always)]
-    fn read_char(&mut self) -> IoResult<char> {
+    fn read_char(&mut self) -> Result<char> {
         self.pos += 1;
-        self.reader.read_char()
+        let start = self.position;
+        let raw = match read_raw_char(self.reader)? {
+            Some(raw) => raw,
+            None => return Err(Error::UnexpectedEof(start))
+        };
+        self.position.byte += raw.as_bytes().len() as u64;
+        let ch = raw.ch.ok_or(Error::InvalidUtf8(start))?;
+        if let Newline(lt) = self.config.line_end {
+            if lt.is_end(ch) {
+                self.position.line += 1;
+            }
+        }
+        Ok(ch)
     }
 
     #[inline(always)]
-    fn read_str(&mut self, len: uint) -> IoResult<String> {
+    fn read_str(&mut self, len: usize) -> Result<String> {
         let mut s = String::new();
-        for _ in range(0, len) {
-            match self.read_char() {
-                Ok(ch) => s.push_char(ch),
-                Err(err) => return Err(err)
-            }
+        for _ in 0..len {
+            s.push(self.read_char()?);
         }
-        Ok(s.into_string())
+        Ok(s)
     }
 
     #[inline(always)]
-    fn read_column(&mut self, config: ColumnConfig) -> IoResult<String> {
-        match self.read_str(config.width) {
-            Ok(col) => {
-                let trimmed = if config.justification == Left {
-                    col.as_slice().trim_right_chars(config.pad_with)
-                } else {
-                    col.as_slice().trim_left_chars(config.pad_with)
-                };
-                Ok(trimmed.to_string())
-            }
-            Err(err) => Err(err)
-        }
+    fn read_column(&mut self, config: ColumnConfig) -> Result<String> {
+        let col = self.read_str(config.width)?;
+        let trimmed = if config.justification == Left {
+            col.trim_end_matches(config.pad_with)
+        } else {
+            col.trim_start_matches(config.pad_with)
+        };
+        Ok(trimmed.to_string())
     }
 
-    fn read_line_ending(&mut self) -> IoResult<()> {
+    fn read_line_ending(&mut self) -> Result<()> {
         match self.config.line_end {
             Nothing => Ok(()),
             FixedWidth(w) => self.read_fixed_width(w),
@@ -96,14 +151,15 @@ impl<'a, R: Buffer> Columns<'a, R> {
         }
     }
 
-    fn read_newline(&mut self, lt: LineTerminator) -> IoResult<()> {
-        let mut lt = lt.as_str().chars();
+    fn read_newline(&mut self, lt: LineTerminator) -> Result<()> {
+        let lt = lt.as_str().chars();
         let curr_pos = self.pos;
+        let start = self.position;
         for c in lt {
             match self.read_char() {
                 Ok(ch) if ch == c => (),
-                Ok(_) => return Err(INVALID_LINE_ENDING.clone()),
-                Err(ref err) if err.kind == io::EndOfFile && curr_pos + 1 == self.pos => {
+                Ok(_) => return Err(Error::InvalidLineEnding(start)),
+                Err(ref err) if is_eof(err) && curr_pos + 1 == self.pos => {
                     return Ok(())
                 }
                 Err(err) => return Err(err)
@@ -112,25 +168,60 @@ impl<'a, R: Buffer> Columns<'a, R> {
         Ok(())
     }
 
-    fn read_fixed_width(&mut self, width: uint) -> IoResult<()> {
+    fn read_fixed_width(&mut self, width: usize) -> Result<()> {
         let current_pos = self.pos;
-        try!(self.read_str(width - current_pos));
+        self.read_str(width - current_pos)?;
+        Ok(())
+    }
+
+    /// Skip the rest of the row after an error
+    fn skip_row(&mut self) -> Result<()> {
+        let width = match self.config.line_end {
+            Nothing => self.config.columns.iter().map(|col| col.width).sum(),
+            FixedWidth(w) => w,
+            Newline(lt) => return self.skip_line(lt)
+        };
+        while self.pos < width {
+            match self.read_char() {
+                Ok(_) | Err(Error::InvalidUtf8(_)) => (),
+                Err(ref err) if is_eof(err) => return Ok(()),
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_line(&mut self, lt: LineTerminator) -> Result<()> {
+        let lt: Vec<char> = lt.as_str().chars().collect();
+        let mut matched = 0;
+        while matched < lt.len() {
+            match self.read_char() {
+                Ok(ch) if ch == lt[matched] => matched += 1,
+                Ok(ch) => matched = if ch == lt[0] { 1 } else { 0 },
+                Err(Error::InvalidUtf8(_)) => matched = 0,
+                Err(ref err) if is_eof(err) => return Ok(()),
+                Err(err) => return Err(err)
+            }
+        }
         Ok(())
     }
 }
 
-impl<'a, R: Buffer> Iterator<IoResult<String>> for Columns<'a, R> {
-    fn next(&mut self) -> Option<IoResult<String>> {
+impl<'a, R: BufRead> Iterator for Columns<'a, R> {
+    type Item = Result<String>;
+
+    fn next(&mut self) -> Option<Result<String>> {
         if self.done {
             return None
         }
-        let cfg = self.config.columns[self.column].clone();
+        self.position.field = self.column as u64;
+        let cfg = self.config.columns[self.column];
         self.column += 1;
         let col = match self.read_column(cfg) {
             Ok(col) => Ok(col),
             Err(err) => {
                 self.done = true;
-                if err.kind == io::EndOfFile && self.pos == 1 {
+                if is_eof(&err) && self.pos == 1 {
                     return None
                 } else {
                     Err(err)
@@ -151,235 +242,677 @@ impl<'a, R: Buffer> Iterator<IoResult<String>> for Columns<'a, R> {
     }
 }
 
-/// Read a single row
-pub fn read_row<R: Buffer>(config: Config, reader: &mut R) -> IoResult<Row> {
-    let mut cols = Columns {
-        reader: reader,
-        config: config,
-        column: 0,
-        pos: 0,
-        done: false
-    };
+/// Read a row, the rest of the row is skipped on errors if `recover` is set
+fn read_row_at<R: BufRead>(config: Config, reader: &mut R, position: &mut Position, recover: bool) -> Result<Row> {
+    let mut cols = Columns::new(reader, config, *position);
     let mut row = Vec::new();
-    for col in cols {
+    for col in &mut cols {
         match col {
-            Ok(c) => row.push(c),
-            Err(err) => return Err(err)
+            Ok(col) => row.push(col),
+            Err(err) => {
+                if recover {
+                    let res = cols.skip_row();
+                    *position = cols.position;
+                    position.field = 0;
+                    res?;
+                }
+                return Err(err)
+            }
         }
     }
+    *position = cols.position;
+    position.field = 0;
     Ok(row)
 }
 
+/// Read a single row, data is read as UTF-8 regardless of the configured encoding
+pub fn read_row<R: BufRead>(config: Config, reader: &mut R) -> Result<Row> {
+    read_row_at(config, reader, &mut Position::new(), false)
+}
+
 /// Iterator over rows
 pub struct Rows<R> {
-    reader: R,
+    reader: Recorder<DecodeReader<R>>,
     config: Config,
+    position: Position,
     done: bool,
+    recovery: Recovery,
+    rejected: Vec<Rejected>,
+    /// Length in bytes of the next row if it was located by its index in UTF-8 data
+    located: Option<u64>,
 }
 
-impl<R: Buffer> Iterator<IoResult<Row>> for Rows<R> {
-    fn next(&mut self) -> Option<IoResult<Row>> {
+impl<R> Rows<R> {
+    /// Position of the next row to be read
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Set the handling of rows that can not be read, `Stop` by default
+    ///
+    /// ```rust
+    /// # use tabular::fixed::{Config, ColumnConfig, Left, Newline, LF, Collect, from_str};
+    /// let config = Config::new(vec!(ColumnConfig {width: 3, pad_with: ' ', justification: Left}), Newline(LF));
+    /// let mut rows = from_str(config, "abc\ndefg\nhij\n").recovery(Collect);
+    /// assert_eq!(rows.by_ref().filter_map(|row| row.ok()).count(), 2);
+    /// assert_eq!(rows.take_rejected()[0].raw, b"defg");
+    /// ```
+    pub fn recovery(mut self, recovery: Recovery) -> Rows<R> {
+        self.recovery = recovery;
+        self.reader.set_enabled(recovery == Collect);
+        self
+    }
+
+    /// Rows rejected so far by the `Collect` recovery
+    pub fn rejected(&self) -> &[Rejected] {
+        &self.rejected
+    }
+
+    /// Remove and return the rows rejected so far by the `Collect` recovery
+    pub fn take_rejected(&mut self) -> Vec<Rejected> {
+        mem::take(&mut self.rejected)
+    }
+
+    /// Convert into an iterator over rows that can be read, rows that can not be read
+    /// are written to rejects and reading continues
+    pub fn quarantine<W: Write>(self, rejects: RejectWriter<W>) -> Quarantine<Rows<R>, W> {
+        Quarantine::new(self.recovery(Collect), rejects)
+    }
+}
+
+impl<R: BufRead> Rows<R> {
+    /// Read the next row, also returning the position of its start
+    fn next_at(&mut self) -> Option<(Result<Row>, Position)> {
+        loop {
+            if self.done {
+                return None
+            }
+            let start = self.position;
+            self.reader.clear(start.byte);
+            let recover = self.recovery != Stop;
+            let located = self.located.take();
+            let err = match read_row_at(self.config.clone(), &mut self.reader, &mut self.position, recover) {
+                Ok(row) => {
+                    if row.is_empty() {
+                        self.done = true;
+                        return None
+                    }
+                    // Offsets of rows in UTF-8 are right only for ASCII rows, which are one byte per character
+                    if located.is_some_and(|len| self.position.byte - start.byte > len) {
+                        self.done = true;
+                        return Some((Err(Error::UnlocatableRow(start)), start))
+                    }
+                    self.position.record += 1;
+                    return Some((Ok(row), start))
+                }
+                Err(err) => err
+            };
+            if !recover || matches!(err, Error::Io(_)) {
+                self.done = true;
+                return Some((Err(err), start))
+            }
+            self.position.record += 1;
+            if self.recovery == Skip {
+                return Some((Err(err), start))
+            }
+            let mut raw = self.reader.recorded(start.byte).to_vec();
+            if let Newline(lt) = self.config.line_end {
+                strip_terminator(&mut raw, &[lt.as_str()]);
+            }
+            self.rejected.push(Rejected {error: err, raw});
+        }
+    }
+}
+
+impl<R: BufRead + Seek> Rows<R> {
+    /// Length of a row in bytes of the data, in UTF-8 of a row of ASCII characters
+    ///
+    /// Characters of other multi-byte encodings are never one byte long, their rows can not be
+    /// located without reading all rows before them.
+    fn row_len(&mut self) -> Result<u64> {
+        // Byte order mark is checked by filling the buffer
+        self.reader.fill_buf()?;
+        let encoding = self.reader.get_ref().encoding();
+        if encoding != UTF_8 && !encoding.is_single_byte() {
+            return Err(Error::UnlocatableRow(self.position))
+        }
+        let width = self.config.columns.iter().map(|col| col.width as u64).sum();
+        Ok(match self.config.line_end {
+            Nothing => width,
+            FixedWidth(w) => w as u64,
+            Newline(lt) if encoding == UTF_8 => width + lt.as_str().len() as u64,
+            Newline(lt) => width + lt.as_str().chars().count() as u64
+        })
+    }
+
+    /// Number of rows computed from the length of the data, a shorter last row is counted
+    ///
+    /// Like `seek_record` it requires one byte per character, UTF-8 data is counted as if it was
+    /// ASCII. The next row read is not changed.
+    pub fn len(&mut self) -> Result<u64> {
+        let row_len = self.row_len()?;
+        let data_len = self.reader.get_mut().data_len()?;
+        Ok(if row_len == 0 { 0 } else { data_len.div_ceil(row_len) })
+    }
+
+    /// Returns true if there are no rows
+    pub fn is_empty(&mut self) -> Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Continue reading at the row with index n, counted from 0
+    ///
+    /// The offset of the row is computed from the column widths, so every character must be one
+    /// byte long: ASCII in UTF-8 or any character of a single-byte encoding. In UTF-8 reading the
+    /// located row is an `UnlocatableRow` error if it is not ASCII, in other multi-byte encodings
+    /// seeking is. The position continues as if every row before was one line.
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use tabular::fixed::{Config, ColumnConfig, Left, Newline, LF, read_rows};
+    /// let config = Config::new(vec!(ColumnConfig {width: 3, pad_with: ' ', justification: Left}), Newline(LF));
+    /// let mut rows = read_rows(config, Cursor::new(b"abc\ndef\nghi\n"));
+    /// rows.seek_record(2).unwrap();
+    /// assert_eq!(rows.next().unwrap().unwrap(), vec!("ghi"));
+    /// ```
+    pub fn seek_record(&mut self, n: u64) -> Result<()> {
+        let row_len = self.row_len()?;
+        self.reader.get_mut().seek_data(n * row_len)?;
+        let line = match self.config.line_end {
+            Newline(_) => n + 1,
+            _ => 1
+        };
+        self.position = Position {record: n, field: 0, line, byte: n * row_len};
+        self.reader.clear(self.position.byte);
+        self.done = false;
+        if self.reader.get_ref().encoding() == UTF_8 {
+            self.located = Some(row_len);
+        }
+        Ok(())
+    }
+
+    /// Read the row with index n, `None` if it is after the last row
+    ///
+    /// Reading continues after the row. Sorted data can be searched without reading all rows:
+    ///
+    /// ```rust
+    /// # use std::io::Cursor;
+    /// # use tabular::fixed::{Config, ColumnConfig, Left, Newline, LF, read_rows};
+    /// let config = Config::new(vec!(ColumnConfig {width: 4, pad_with: ' ', justification: Left}), Newline(LF));
+    /// let mut rows = read_rows(config, Cursor::new(b"ant \nbee \ncat \ndog \nelk \n"));
+    /// let (mut low, mut high) = (0, rows.len().unwrap());
+    /// while low < high {
+    ///     let mid = (low + high) / 2;
+    ///     if rows.get(mid).unwrap().unwrap()[0].as_str() < "dog" { low = mid + 1 } else { high = mid }
+    /// }
+    /// assert_eq!(low, 3);
+    /// ```
+    pub fn get(&mut self, n: u64) -> Result<Option<Row>> {
+        self.seek_record(n)?;
+        self.next().transpose()
+    }
+}
+
+impl<'a> Rows<&'a [u8]> {
+    /// Read the next row into a record with columns borrowed from the data
+    ///
+    /// Rows that are incomplete or not valid UTF-8, data in other encodings than UTF-8 and all rows
+    /// with the `Collect` recovery are read into owned columns.
+    ///
+    /// ```rust
+    /// # use tabular::fixed::{read_rows, BorrowedRecord, Config, ColumnConfig, Left, Newline, LF};
+    /// let config = Config::new(vec!(ColumnConfig {width: 3, pad_with: ' ', justification: Left}), Newline(LF));
+    /// let mut rows = read_rows(config, &b"ab \ncde\n"[..]);
+    /// let mut record = BorrowedRecord::new();
+    /// assert!(rows.read_borrowed_record(&mut record).unwrap());
+    /// assert_eq!(&record[0], "ab");
+    /// ```
+    pub fn read_borrowed_record(&mut self, record: &mut BorrowedRecord<'a>) -> Result<bool> {
+        record.clear();
         if self.done {
-            return None
+            return Ok(false)
         }
-        match read_row(self.config.clone(), &mut self.reader) {
-            Ok(row) => {
-                if row.len() == 0 {
-                    self.done = true;
-                    return None
+        if self.recovery != Collect {
+            // Byte order mark is removed by filling the buffer
+            self.reader.fill_buf()?;
+            if let Some(&data) = self.reader.get_ref().passthrough() {
+                record.set_position(self.position);
+                if let Some(len) = read_borrowed_row(&self.config, data, record, &mut self.position) {
+                    self.reader.consume(len);
+                    self.position.record += 1;
+                    return Ok(true)
                 }
-                Some(Ok(row))
             }
-            Err(err) => {
-                self.done = true;
-                Some(Err(err))
+        }
+        match self.next_at() {
+            Some((Ok(row), position)) => {
+                record.set_position(position);
+                for col in row {
+                    record.push_field(Cow::Owned(col));
+                }
+                Ok(true)
+            }
+            Some((Err(err), _)) => Err(err),
+            None => Ok(false)
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for Rows<R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        self.next_at().map(|(row, _)| row)
+    }
+}
+
+/// Split the first `chars` characters off data if they are valid UTF-8
+fn split_chars(data: &[u8], chars: usize) -> Option<(&str, &[u8])> {
+    let mut len = 0;
+    for _ in 0..chars {
+        len += match *data.get(len)? {
+            b if b < 0x80 => 1,
+            b if b >= 0xf0 => 4,
+            b if b >= 0xe0 => 3,
+            _ => 2
+        };
+    }
+    let text = str::from_utf8(data.get(..len)?).ok()?;
+    Some((text, &data[len..]))
+}
+
+/// Read the next row from data into the empty record if it is complete and valid UTF-8
+///
+/// Returns the length of the row, `None` if it has to be read by the character reader.
+fn read_borrowed_row<'a>(config: &Config, data: &'a [u8], record: &mut BorrowedRecord<'a>, position: &mut Position) -> Option<usize> {
+    let split = |record: &mut BorrowedRecord<'a>| {
+        let mut rest = data;
+        for col in config.columns.iter() {
+            let (text, after) = split_chars(rest, col.width)?;
+            let trimmed = if col.justification == Left {
+                text.trim_end_matches(col.pad_with)
+            } else {
+                text.trim_start_matches(col.pad_with)
+            };
+            record.push_field(Cow::Borrowed(trimmed));
+            rest = after;
+        }
+        match config.line_end {
+            Nothing => (),
+            FixedWidth(width) => {
+                let used = config.columns.iter().map(|col| col.width).sum();
+                rest = split_chars(rest, width.checked_sub(used)?)?.1;
+            }
+            Newline(lt) => {
+                rest = rest.strip_prefix(lt.as_str().as_bytes()).or(if rest.is_empty() { Some(rest) } else { None })?;
             }
         }
+        Some(data.len() - rest.len())
+    };
+    let len = match split(record) {
+        Some(len) if len > 0 => len,
+        _ => {
+            record.clear();
+            return None
+        }
+    };
+    if let Newline(lt) = config.line_end {
+        // Data of the row was validated while splitting it
+        let text = str::from_utf8(&data[..len]).ok()?;
+        position.line += text.chars().filter(|&ch| lt.is_end(ch)).count() as u64;
     }
+    position.byte += len as u64;
+    position.field = 0;
+    Some(len)
 }
 
-static COLUMN_TOO_LONG: IoError = IoError {
-    kind: io::InvalidInput,
-    desc: "Column too long",
-    detail: None
-};
+impl<R: BufRead, W: Write> Iterator for Quarantine<Rows<R>, W> {
+    type Item = Result<Row>;
 
-static ROW_TOO_LONG: IoError = IoError {
-    kind: io::InvalidInput,
-    desc: "Column too long",
-    detail: None
-};
+    fn next(&mut self) -> Option<Result<Row>> {
+        let next = self.rows.next();
+        let rejected = self.rows.take_rejected();
+        match self.write_rejected(rejected) {
+            Ok(()) => next,
+            Err(err) => Some(Err(err))
+        }
+    }
+}
 
 /// Create an iterator that reads a line on each iteration until EOF
 ///
-/// ```rust
-/// # use std::io::BufferedReader;
-/// # use std::io::File;
+/// ```rust,no_run
+/// # use std::io::BufReader;
+/// # use std::fs::File;
 /// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, read_rows};
-/// let path = Path::new("file.csv");
-/// let mut file = BufferedReader::new(File::open(&path));
+/// let file = BufReader::new(File::open("file.csv").unwrap());
 ///
-/// let config = Config {
-///     columns: vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
-///                   ColumnConfig {width: 9, pad_with: '-', justification: Right}),
-///     line_end: Newline(LF)
-/// };
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 9, pad_with: '-', justification: Right}),
+///                          Newline(LF));
 ///
 /// let rows = read_rows(config, file);
 /// ```
-pub fn read_rows<R: Buffer>(config: Config, reader: R) -> Rows<R> {
+pub fn read_rows<R: BufRead>(config: Config, reader: R) -> Rows<R> {
     Rows {
-        reader: reader,
-        config: config,
-        done: false
+        reader: Recorder::new(DecodeReader::new(reader, config.encoding)),
+        config,
+        position: Position::new(),
+        done: false,
+        recovery: Stop,
+        rejected: Vec::new(),
+        located: None
     }
 }
 
-pub type RowsMem = Rows<io::MemReader>;
+pub type RowsMem = Rows<io::Cursor<Vec<u8>>>;
 
-/// Helper method for reading rows from a string
+/// Helper method for reading rows from a string, the configured encoding is ignored
 ///
 /// ```rust
 /// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, from_str};
-/// let config = Config {
-///     columns: vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
-///                   ColumnConfig {width: 9, pad_with: '-', justification: Right}),
-///     line_end: Newline(LF)
-/// };
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 9, pad_with: '-', justification: Right}),
+///                          Newline(LF));
 ///
 /// let rows = from_str(config, "aa,bb\r\ncc,dd");
 /// ```
 pub fn from_str(config: Config, s: &str) -> RowsMem {
-    let buf = io::MemReader::new(Vec::from_slice(s.as_bytes()));
-    read_rows(config, buf)
+    let buf = io::Cursor::new(s.as_bytes().to_vec());
+    read_rows(Config {encoding: UTF_8, ..config}, buf)
 }
 
-pub type RowsFile = Rows<io::BufferedReader<IoResult<io::File>>>;
+pub type RowsFile = Rows<BufReader<File>>;
 
 /// Helper method for reading rows from a file
 ///
-/// ```rust
+/// ```rust,no_run
 /// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, from_file};
-/// let config = Config {
-///     columns: vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
-///                   ColumnConfig {width: 9, pad_with: '-', justification: Right}),
-///     line_end: Newline(LF)
-/// };
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 9, pad_with: '-', justification: Right}),
+///                          Newline(LF));
 ///
-/// let path = Path::new("path/file.csv");
-/// let rows = from_file(config, &path);
+/// let rows = from_file(config, "path/file.csv").unwrap();
 /// ```
-pub fn from_file(config: Config, path: &Path) -> RowsFile {
-    let file = io::BufferedReader::new(io::File::open(path));
-    read_rows(config, file)
+pub fn from_file<P: AsRef<Path>>(config: Config, path: P) -> Result<RowsFile> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(read_rows(config, file))
 }
 
+pub type RowsMapped<'a> = Rows<&'a [u8]>;
 
-fn write_column(config: &ColumnConfig, writer: &mut Writer, col: &str) -> IoResult<()> {
-    if col.len() > config.width {
-        return Err(COLUMN_TOO_LONG.clone())
+/// Helper method for reading rows from a file mapped into memory
+///
+/// ```rust,no_run
+/// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, BorrowedRecord, MappedFile, from_mapped};
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left}), Newline(LF));
+/// let file = MappedFile::open("path/file.txt").unwrap();
+/// let mut rows = from_mapped(config, &file);
+/// let mut record = BorrowedRecord::new();
+/// while rows.read_borrowed_record(&mut record).unwrap() {
+///     println!("{}", &record[0]);
+/// }
+/// ```
+pub fn from_mapped(config: Config, file: &MappedFile) -> RowsMapped<'_> {
+    read_rows(config, file.as_bytes())
+}
+
+
+fn write_column<W: Write + ?Sized>(config: &ColumnConfig, writer: &mut W, col: &str, position: &mut Position) -> Result<()> {
+    let len = col.chars().count();
+    if len > config.width {
+        return Err(Error::ColumnTooLong(*position))
     }
-    let padding = config.pad_with.to_string().repeat(config.width - col.len());
+    let padding = config.pad_with.to_string().repeat(config.width - len);
     if config.justification == Left {
-        try!(writer.write_str(col));
-        writer.write_str(padding.as_slice())
+        writer.write_all(col.as_bytes())?;
+        writer.write_all(padding.as_bytes())?;
     } else {
-        try!(writer.write_str(padding.as_slice()));
-        writer.write_str(col)
+        writer.write_all(padding.as_bytes())?;
+        writer.write_all(col.as_bytes())?;
+    }
+    position.byte += (col.len() + padding.len()) as u64;
+    Ok(())
+}
+
+fn write_row_at<W: Write + ?Sized, S: AsRef<str>>(config: &Config, writer: &mut W, row: &[S], position: &mut Position) -> Result<()> {
+    if config.encoding == UTF_8 {
+        return write_utf8_row(config, writer, row, position)
     }
+    let mut encoder = EncodeWriter::new(writer, config.encoding);
+    write_utf8_row(config, &mut encoder, row, position).map_err(|err| encoder.map_error(err, *position))
 }
 
-/// Write a single row
-pub fn write_row(config: &Config, writer: &mut Writer, row: Row) -> IoResult<()> {
+fn write_utf8_row<W: Write + ?Sized, S: AsRef<str>>(config: &Config, writer: &mut W, row: &[S], position: &mut Position) -> Result<()> {
     let mut written = 0;
-    for (col, cfg) in row.iter().zip(config.columns.iter()) {
-        try!(write_column(cfg, writer, col.as_slice()));
+    for (i, (col, cfg)) in row.iter().zip(config.columns.iter()).enumerate() {
+        position.field = i as u64;
+        write_column(cfg, writer, col.as_ref(), position)?;
         written += cfg.width;
     }
     match config.line_end {
         Nothing => (),
         FixedWidth(w) => {
             if written > w {
-                return Err(ROW_TOO_LONG.clone())
+                return Err(Error::RowTooLong(*position))
             } else {
                 let padding = " ".repeat(w - written);
-                try!(writer.write_str(padding.as_slice()));
+                writer.write_all(padding.as_bytes())?;
+                position.byte += padding.len() as u64;
             }
         }
         Newline(lt) => {
-            try!(writer.write_str(lt.as_str()));
+            writer.write_all(lt.as_str().as_bytes())?;
+            position.byte += lt.as_str().len() as u64;
+            position.line += 1;
         }
     }
+    position.record += 1;
+    position.field = 0;
     Ok(())
 }
 
+fn write_bom<W: Write + ?Sized>(config: &Config, writer: &mut W) -> Result<()> {
+    if config.bom {
+        writer.write_all(transcode::bom(config.encoding))?;
+    }
+    Ok(())
+}
+
+/// Write a single row, a byte order mark is never written
+pub fn write_row<W: Write + ?Sized>(config: &Config, writer: &mut W, row: Row) -> Result<()> {
+    write_row_at(config, writer, &row, &mut Position::new())
+}
+
 /// Write rows from iterator into writer with settings from config
 ///
-/// ```rust
-/// # #![allow(unused_must_use)]
-/// # use std::io::BufferedWriter;
-/// # use std::io::File;
+/// ```rust,no_run
+/// # use std::io::BufWriter;
+/// # use std::fs::File;
 /// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, write_rows};
-/// let path = Path::new("path/file.csv");
-/// let mut file = BufferedWriter::new(File::open(&path));
+/// let mut file = BufWriter::new(File::create("path/file.csv").unwrap());
 ///
-/// let config = Config {
-///     columns: vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
-///                   ColumnConfig {width: 9, pad_with: '-', justification: Right}),
-///     line_end: Newline(LF)
-/// };
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 9, pad_with: '-', justification: Right}),
+///                          Newline(LF));
 ///
 /// let rows = vec!(vec!("a".to_string(), "bb".to_string()), vec!("ccc".to_string(), "dddd".to_string()));
-/// write_rows(config, &mut file, rows.into_iter());
+/// write_rows(config, &mut file, rows.into_iter()).unwrap();
 /// ```
-pub fn write_rows<R: Iterator<Row>>(config: Config, writer: &mut Writer, mut rows: R) -> IoResult<()> {
+pub fn write_rows<W, R>(config: Config, writer: &mut W, rows: R) -> Result<()>
+    where W: Write + ?Sized, R: Iterator<Item = Row> {
+    let mut position = Position::new();
+    write_bom(&config, writer)?;
     for row in rows {
-        try!(write_row(&config, writer, row));
+        write_row_at(&config, writer, &row, &mut position)?;
     }
     Ok(())
 }
 
-/// Helper method for writing rows to a file
+/// Serialize values from iterator into writer with settings from config
+///
+/// Structs, tuples and sequences are flattened into columns in order of their fields, `None` is
+/// written as an empty column.
 ///
 /// ```rust
-/// # #![allow(unused_must_use)]
-/// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, write_file};
-/// let path = Path::new("path/file.csv");
+/// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, serialize_rows};
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 4, pad_with: '0', justification: Right}),
+///                          Newline(LF));
+///
+/// let mut buf = Vec::new();
+/// serialize_rows(config, &mut buf, vec!(("DAL", 17), ("NYG", 24))).unwrap();
+/// assert_eq!(&buf[..], b"DAL  0017\nNYG  0024\n");
+/// ```
+pub fn serialize_rows<W, S, I>(config: Config, writer: &mut W, values: I) -> Result<()>
+    where W: Write + ?Sized, S: Serialize, I: IntoIterator<Item = S> {
+    let mut position = Position::new();
+    write_bom(&config, writer)?;
+    for value in values {
+        let serialized = serialize_row(&value, position)?;
+        write_row_at(&config, writer, &serialized.into_row(), &mut position)?;
+    }
+    Ok(())
+}
+
+/// Helper method for writing rows to a file
 ///
-/// let config = Config {
-///     columns: vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
-///                   ColumnConfig {width: 9, pad_with: '-', justification: Right}),
-///     line_end: Newline(LF)
-/// };
+/// ```rust,no_run
+/// # use tabular::fixed::{Config, Newline, LF, ColumnConfig, Left, Right, write_file};
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 9, pad_with: '-', justification: Right}),
+///                          Newline(LF));
 ///
 /// let rows = vec!(vec!("a".to_string(), "bb".to_string()), vec!("ccc".to_string(), "dddd".to_string()));
-/// write_file(config, &path, rows.into_iter());
+/// write_file(config, "path/file.csv", rows.into_iter()).unwrap();
+/// ```
+pub fn write_file<P: AsRef<Path>, R: Iterator<Item = Row>>(config: Config, path: P, rows: R) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_rows(config, &mut file, rows)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Writer of rows one at a time with buffered output
+///
+/// A byte order mark is written before the first row when the configuration has one. Each row
+/// is buffered until it is complete, a row that fails to be written leaves no partial output.
+/// Data reaches the underlying writer when the buffer is full, on `flush` and by `into_inner`.
+///
+/// ```rust
+/// # use tabular::fixed::{Config, ColumnConfig, Writer, Newline, Nothing, Left, Right, LF};
+/// let config = Config::new(vec!(ColumnConfig {width: 5, pad_with: ' ', justification: Left},
+///                               ColumnConfig {width: 4, pad_with: ' ', justification: Right}),
+///                          Newline(LF));
+/// let mut writer = Writer::new(config, Vec::new()).line_end(Nothing);
+/// writer.write_header(&["off", "pts"]).unwrap();
+/// writer.write_record(&["DAL", "17"]).unwrap();
+/// assert_eq!(writer.into_inner().unwrap(), b"off   ptsDAL    17");
 /// ```
-pub fn write_file<R: Iterator<Row>>(config: Config, path: &Path, rows: R) -> IoResult<()> {
-    let mut file = io::BufferedWriter::new(io::File::open_mode(path, io::Open, io::Write));
-    write_rows(config, &mut file, rows)
+pub struct Writer<W: Write> {
+    writer: BufWriter<W>,
+    config: Config,
+    position: Position,
+    row: Vec<u8>,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer in the format of config
+    pub fn new(config: Config, writer: W) -> Writer<W> {
+        Writer {
+            writer: BufWriter::new(writer),
+            config,
+            position: Position::new(),
+            row: Vec::new()
+        }
+    }
+
+    /// End rows with line ending instead of the configured one
+    pub fn line_end(mut self, line_end: LineEnding) -> Writer<W> {
+        self.config.line_end = line_end;
+        self
+    }
+
+    /// Position of the next row to be written
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Write a header row of column names, error if other rows were written before
+    pub fn write_header<S: AsRef<str>>(&mut self, names: &[S]) -> Result<()> {
+        if self.position.record > 0 {
+            return Err(Error::HeaderNotFirst(self.position))
+        }
+        self.write_record(names)
+    }
+
+    /// Write a row
+    pub fn write_record<S: AsRef<str>>(&mut self, record: &[S]) -> Result<()> {
+        self.row.clear();
+        if self.position.record == 0 && self.position.byte == 0 {
+            write_bom(&self.config, &mut self.row)?;
+        }
+        let mut position = self.position;
+        write_row_at(&self.config, &mut self.row, record, &mut position)?;
+        self.writer.write_all(&self.row)?;
+        self.position = position;
+        Ok(())
+    }
+
+    /// Serialize value into a row like `serialize_rows`
+    pub fn serialize<S: Serialize + ?Sized>(&mut self, value: &S) -> Result<()> {
+        let serialized = serialize_row(value, self.position)?;
+        self.write_record(&serialized.into_row())
+    }
+
+    /// Write buffered data to the underlying writer and flush it
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Flush buffered data and return the underlying writer
+    pub fn into_inner(self) -> Result<W> {
+        self.writer.into_inner().map_err(|err| Error::Io(err.into_error()))
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use std::io;
-    use std::io::{IoResult, IoError};
+    use std::io::Cursor;
 
-    use common::INVALID_LINE_ENDING;
+    use crate::error::{Error, Position, Result};
 
     use super::{Config, ColumnConfig, Left, Right, Row, CRLF, Newline, FixedWidth, LF, Nothing, FF, LS};
-    use super::{read_row, read_rows, write_column, COLUMN_TOO_LONG, write_rows, ROW_TOO_LONG, write_row};
+    use super::{read_row, read_rows, write_column, write_rows, write_row, Skip, Collect, RejectWriter, Writer, BorrowedRecord};
+    use crate::dsv::CSV;
+    use crate::encoding::{UTF_16LE, WINDOWS_1252};
+
+    type Flat<T> = ::std::result::Result<T, String>;
 
-    fn assert_colmatch(cfg: Config, row: &str, cols: IoResult<Row>) {
-        let mut reader = io::BufReader::new(row.as_bytes());
+    fn flatten<T: Clone>(res: &Result<T>) -> Flat<T> {
+        match *res {
+            Ok(ref v) => Ok(v.clone()),
+            Err(ref err) => Err(format!("{:?}", err))
+        }
+    }
+
+    fn flatten_all<T: Clone>(res: &[Result<T>]) -> Vec<Flat<T>> {
+        res.iter().map(flatten).collect()
+    }
+
+    fn pos(field: u64, byte: u64) -> Position {
+        Position {field, byte, ..Position::new()}
+    }
+
+    fn assert_colmatch(cfg: Config, row: &str, cols: Result<Row>) {
+        let mut reader = row.as_bytes();
         let result = {
             read_row(cfg, &mut reader)
         };
-        match reader.read_char() {
-            Ok(_) => fail!("Should consume all input"),
-            _ => ()
+        if !reader.is_empty() {
+            panic!("Should consume all input")
         }
-        assert_eq!(cols, result)
+        assert_eq!(flatten(&cols), flatten(&result))
     }
 
     static COLUMN_1: ColumnConfig = ColumnConfig {
@@ -408,160 +941,148 @@ mod test {
 
     #[test]
     fn read_fixed_empty() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Newline(CRLF));
         assert_colmatch(cfg, "", Ok(vec!()));
     }
 
     #[test]
     fn read_fixed_column_no_padding() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1), Newline(CRLF));
         assert_colmatch(cfg, "aaa", Ok(vec!("aaa".to_string())));
     }
 
     #[test]
     fn read_fixed_columns_no_padding() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Newline(CRLF));
         assert_colmatch(cfg, "aaabccccc", Ok(vec!("aaa".to_string(), "b".to_string(), "ccccc".to_string())));
     }
 
     #[test]
     fn read_fixed_with_zero_length_column() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_ZERO, COLUMN_3),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_ZERO, COLUMN_3), Newline(CRLF));
         assert_colmatch(cfg, "aaaccccc", Ok(vec!("aaa".to_string(), "".to_string(), "ccccc".to_string())));
     }
 
     #[test]
     fn read_fixed_columns_with_padding() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Newline(CRLF));
         assert_colmatch(cfg, "  a#cccc-", Ok(vec!("a".to_string(), "".to_string(), "cccc".to_string())));
     }
 
     #[test]
     fn read_fixed_columns_with_newline_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(CRLF));
         assert_colmatch(Config {line_end: Newline(LF), ..cfg.clone()}, "aaab\n", Ok(vec!("aaa".to_string(), "b".to_string())));
         assert_colmatch(cfg, "aaab\r\n", Ok(vec!("aaa".to_string(), "b".to_string())));
     }
 
     #[test]
     fn read_fixed_columns_with_invalid_newline_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: Newline(CRLF)
-        };
-        assert_colmatch(cfg.clone(), "aaab\r\r", Err(INVALID_LINE_ENDING.clone()));
-        assert_colmatch(cfg, "aaab\r", Err(IoError {
-            kind: io::EndOfFile,
-            desc: "end of file",
-            detail: None
-        }));
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(CRLF));
+        assert_colmatch(cfg.clone(), "aaab\r\r", Err(Error::InvalidLineEnding(pos(1, 4))));
+        assert_colmatch(cfg, "aaab\r", Err(Error::UnexpectedEof(pos(1, 5))));
     }
 
     #[test]
     fn read_fixed_columns_with_fixed_width_length() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: FixedWidth(10)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), FixedWidth(10));
         assert_colmatch(cfg, "aaab      ", Ok(vec!("aaa".to_string(), "b".to_string())));
     }
 
     #[test]
     fn read_fixed_columns_error_on_not_enough_data() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: Newline(LF)
-        };
-        assert_colmatch(cfg, "aab", Err(IoError {
-            kind: io::EndOfFile,
-            desc: "end of file",
-            detail: None
-        }));
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF));
+        assert_colmatch(cfg, "aab", Err(Error::UnexpectedEof(pos(1, 3))));
     }
 
-    fn assert_rowmatch(config: Config, s: &str, ex: Vec<IoResult<Row>>) {
-        let reader = io::BufReader::new(s.as_bytes());
-        let rows: Vec<IoResult<Row>> = read_rows(config, reader).collect();
+    fn assert_rowmatch(config: Config, s: &str, ex: Vec<Result<Row>>) {
+        let reader = s.as_bytes();
+        let rows: Vec<Flat<Row>> = read_rows(config, reader).map(|r| flatten(&r)).collect();
+        let ex: Vec<Flat<Row>> = ex.iter().map(flatten).collect();
         for (row, exrow) in rows.iter().zip(ex.iter()) {
             assert_eq!(row, exrow);
         }
         if rows.len() < ex.len() {
-            fail!("Missing rows: {}", ex.slice_from(rows.len()))
+            panic!("Missing rows: {:?}", &ex[rows.len()..])
         } else if rows.len() > ex.len() {
-            fail!("Unexpected rows: {}", rows.slice_from(ex.len()))
+            panic!("Unexpected rows: {:?}", &rows[ex.len()..])
         }
     }
 
     #[test]
     fn read_lines_with_fixed_columns_and_newline_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Newline(CRLF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Newline(CRLF));
         assert_rowmatch(cfg, " aabccc--\r\n  a#-----", vec!(Ok(vec!("aa".to_string(), "b".to_string(), "ccc".to_string())), Ok(vec!("a".to_string(), "".to_string(), "".to_string()))));
     }
 
     #[test]
     fn read_lines_with_fixed_columns_and_feedforward_line_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Newline(FF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Newline(FF));
         assert_rowmatch(cfg, " aabccc--\x0c  a#-----", vec!(Ok(vec!("aa".to_string(), "b".to_string(), "ccc".to_string())), Ok(vec!("a".to_string(), "".to_string(), "".to_string()))));
     }
 
     #[test]
     fn read_lines_with_fixed_columns_and_line_separator_line_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Newline(LS)
-        };
-        assert_rowmatch(cfg, " aabccc--\u2028  a#-----", vec!(Ok(vec!("aa".to_string(), "b".to_string(), "ccc".to_string())), Ok(vec!("a".to_string(), "".to_string(), "".to_string()))));
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Newline(LS));
+        assert_rowmatch(cfg, " aabccc--\u{2028}  a#-----", vec!(Ok(vec!("aa".to_string(), "b".to_string(), "ccc".to_string())), Ok(vec!("a".to_string(), "".to_string(), "".to_string()))));
     }
 
     #[test]
     fn read_lines_with_fixed_columns_and_fixed_width_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: FixedWidth(10)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), FixedWidth(10));
         assert_rowmatch(cfg, " aabccc--   a#----- ", vec!(Ok(vec!("aa".to_string(), "b".to_string(), "ccc".to_string())), Ok(vec!("a".to_string(), "".to_string(), "".to_string()))));
     }
 
     #[test]
     fn read_lines_with_fixed_columns_and_no_line_end() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2, COLUMN_3),
-            line_end: Nothing
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2, COLUMN_3), Nothing);
         assert_rowmatch(cfg, " aabccc--  a#-----", vec!(Ok(vec!("aa".to_string(), "b".to_string(), "ccc".to_string())), Ok(vec!("a".to_string(), "".to_string(), "".to_string()))));
     }
 
-    fn assert_column_written(config: ColumnConfig, col: String, exp: &[u8], exp_res: IoResult<()>) {
-        let mut writer = io::MemWriter::new();
+    #[test]
+    fn error_position_in_later_row() {
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF));
+        let position = Position {record: 1, field: 1, line: 2, byte: 8};
+        assert_rowmatch(cfg, "aaab\nccc", vec!(Ok(vec!("aaa".to_string(), "b".to_string())), Err(Error::UnexpectedEof(position))));
+    }
+
+    #[test]
+    fn bad_rows_are_skipped() {
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF));
+        let rows: Vec<_> = read_rows(cfg, "aaab\naaabX\nccc\u{e9}\n".as_bytes()).recovery(Skip).collect();
+        let position = Position {record: 1, field: 1, line: 2, byte: 9};
+        assert_eq!(flatten_all(&rows), flatten_all(&[Ok(vec!("aaa".to_string(), "b".to_string())), Err(Error::InvalidLineEnding(position)), Ok(vec!("ccc".to_string(), "\u{e9}".to_string()))]));
+    }
+
+    #[test]
+    fn bad_rows_are_collected() {
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), FixedWidth(5));
+        let mut rows = read_rows(cfg, &b"aaab \xffbbb cccd "[..]).recovery(Collect);
+        let read: Vec<_> = rows.by_ref().collect();
+        assert_eq!(flatten_all(&read), flatten_all(&[Ok(vec!("aaa".to_string(), "b".to_string())), Ok(vec!("ccc".to_string(), "d".to_string()))]));
+        let rejected = rows.take_rejected();
+        assert_eq!(rejected[0].raw, b"\xffbbb ");
+        let position = Position {record: 1, field: 0, line: 1, byte: 5};
+        assert_eq!(format!("{:?}", rejected[0].error), format!("{:?}", Error::InvalidUtf8(position)));
+        assert_eq!(rows.position().record, 3);
+    }
+
+    #[test]
+    fn bad_rows_are_quarantined() {
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF));
+        let mut rows = read_rows(cfg, "aaab\naaabc\ncc\n".as_bytes()).quarantine(RejectWriter::new(CSV, Vec::new()));
+        assert_eq!(flatten_all(&rows.by_ref().collect::<Vec<_>>()), flatten_all(&[Ok(vec!("aaa".to_string(), "b".to_string()))]));
+        let rejects = String::from_utf8(rows.into_rejects().into_inner()).unwrap();
+        assert_eq!(rejects, "aaabc,\"Invalid line ending at record 1, field 1 (line 2, byte 9)\"\r\ncc,\"Unexpected end of data at record 2, field 1 (line 4, byte 14)\"\r\n");
+    }
+
+    fn assert_column_written(config: ColumnConfig, col: String, exp: &[u8], exp_res: Result<()>) {
+        let mut writer = Vec::new();
         let res = {
-            write_column(&config, &mut writer, col.as_slice())
+            write_column(&config, &mut writer, &col, &mut Position::new())
         };
-        assert_eq!(res, exp_res);
-        assert_eq!(exp, writer.get_ref());
+        assert_eq!(flatten(&res), flatten(&exp_res));
+        assert_eq!(exp, &writer[..]);
     }
 
     #[test]
@@ -586,75 +1107,139 @@ mod test {
 
     #[test]
     fn write_error_on_column_data_too_long() {
-        assert_column_written(COLUMN_3, "cccccc".to_string(), b"", Err(COLUMN_TOO_LONG.clone()));
+        assert_column_written(COLUMN_3, "cccccc".to_string(), b"", Err(Error::ColumnTooLong(Position::new())));
     }
 
     #[test]
     fn line_ending_is_written() {
-        let config = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: Newline(CRLF)
-        };
-        let mut writer = io::MemWriter::new();
+        let config = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(CRLF));
+        let mut writer = Vec::new();
         let res = {
             let row = vec!("aaa".to_string(), "b".to_string());
             write_row(&config, &mut writer, row)
         };
-        assert_eq!(res, Ok(()));
-        assert_eq!(writer.get_ref(), b"aaab\r\n");
+        assert!(res.is_ok());
+        assert_eq!(&writer[..], b"aaab\r\n");
     }
 
     #[test]
     fn write_error_on_fixed_row_columns_too_long() {
-        let config = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: FixedWidth(3)
-        };
-        let mut writer = io::MemWriter::new();
+        let config = Config::new(vec!(COLUMN_1, COLUMN_2), FixedWidth(3));
+        let mut writer = Vec::new();
         let res = {
             let row = vec!("aaa".to_string(), "b".to_string());
             write_row(&config, &mut writer, row)
         };
-        assert_eq!(res, Err(ROW_TOO_LONG.clone()));
-        assert_eq!(writer.get_ref(), b"aaab");
+        assert_eq!(flatten(&res), flatten(&Err(Error::RowTooLong(pos(1, 4)))));
+        assert_eq!(&writer[..], b"aaab");
     }
 
-    fn assert_lines_written(config: Config, rows: Vec<Row>, exp: &[u8], exp_res: IoResult<()>) {
-        let mut writer = io::MemWriter::new();
+    fn assert_lines_written(config: Config, rows: Vec<Row>, exp: &[u8], exp_res: Result<()>) {
+        let mut writer = Vec::new();
         let res = {
             write_rows(config, &mut writer, rows.into_iter())
         };
-        assert_eq!(res, exp_res);
-        assert_eq!(writer.get_ref(), exp);
+        assert_eq!(flatten(&res), flatten(&exp_res));
+        assert_eq!(&writer[..], exp);
     }
 
     #[test]
     fn fixed_width_rows_are_written_correctly() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: FixedWidth(6)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), FixedWidth(6));
         let rows = vec!(vec!("a".to_string(), "".to_string()), vec!("aaa".to_string(), "b".to_string()));
         assert_lines_written(cfg, rows, b"  a#  aaab  ", Ok(()));
     }
 
     #[test]
     fn newline_terminated_rows_are_written_correctly() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: Newline(LF)
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF));
         let rows = vec!(vec!("a".to_string(), "".to_string()), vec!("aaa".to_string(), "b".to_string()));
         assert_lines_written(cfg, rows, b"  a#\naaab\n", Ok(()));
     }
 
     #[test]
     fn rows_without_terminator_are_written_correctly() {
-        let cfg = Config {
-            columns: vec!(COLUMN_1, COLUMN_2),
-            line_end: Nothing
-        };
+        let cfg = Config::new(vec!(COLUMN_1, COLUMN_2), Nothing);
         let rows = vec!(vec!("a".to_string(), "".to_string()), vec!("aaa".to_string(), "b".to_string()));
         assert_lines_written(cfg, rows, b"  a#aaab", Ok(()));
     }
+
+    #[test]
+    fn encoded_rows_are_written_and_read() {
+        let config = Config {encoding: WINDOWS_1252, ..Config::new(vec!(COLUMN_1, COLUMN_3), Newline(LF))};
+        let mut writer = Vec::new();
+        write_rows(config.clone(), &mut writer, vec!(vec!("\u{e9}".to_string(), "\u{20ac}5".to_string())).into_iter()).unwrap();
+        assert_eq!(&writer[..], b"  \xe9\x805---\n");
+        let rows: Vec<Result<Row>> = read_rows(config, &writer[..]).collect();
+        assert_eq!(rows.iter().map(flatten).collect::<Vec<_>>(), vec!(Ok(vec!("\u{e9}".to_string(), "\u{20ac}5".to_string()))));
+    }
+
+    #[test]
+    fn borrowed_records_borrow_columns() {
+        let read = |config: Config, data: &[u8]| {
+            let mut rows = read_rows(config, data).recovery(Skip);
+            let mut record = BorrowedRecord::new();
+            let mut read = Vec::new();
+            loop {
+                match rows.read_borrowed_record(&mut record) {
+                    Ok(true) => read.push(Ok((record.to_row(), *record.position()))),
+                    Ok(false) => return read,
+                    Err(err) => read.push(Err(format!("{:?}", err)))
+                }
+            }
+        };
+        let row = |cols: &[&str], record, line, byte| Ok((cols.iter().map(|col| col.to_string()).collect::<Row>(), Position {record, line, byte, field: 0}));
+        let config = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(CRLF));
+        assert_eq!(read(config.clone(), b"\xef\xbb\xbf  a#\r\n \xc3\xa9\nb\r\n\xffaa \r\nccc#"), vec!(
+            row(&["a", ""], 0, 1, 0),
+            row(&["\u{e9}\n", "b"], 1, 2, 6),
+            Err("InvalidUtf8(Position { record: 2, field: 0, line: 4, byte: 13 })".to_string()),
+            row(&["ccc", ""], 3, 5, 19)));
+        let config = Config::new(vec!(COLUMN_1, COLUMN_2), FixedWidth(6));
+        assert_eq!(read(config, b"  a#xxaaab\xc3\xa9x"), vec!(row(&["a", ""], 0, 1, 0), row(&["aaa", "b"], 1, 1, 6)));
+    }
+
+    #[test]
+    fn records_are_read_by_index() {
+        let config = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(CRLF));
+        let mut rows = read_rows(config, Cursor::new(b"\xef\xbb\xbf  a#\r\naaab\r\n  c#".to_vec()));
+        assert_eq!(rows.len().unwrap(), 3);
+        assert_eq!(flatten(&rows.get(2)), Ok(Some(vec!("c".to_string(), "".to_string()))));
+        assert_eq!(*rows.position(), Position {record: 3, line: 3, byte: 16, field: 0});
+        assert_eq!(flatten(&rows.get(0)), Ok(Some(vec!("a".to_string(), "".to_string()))));
+        assert_eq!(flatten(&rows.next().unwrap()), Ok(vec!("aaa".to_string(), "b".to_string())));
+        assert_eq!(flatten(&rows.get(3)), Ok(None));
+        assert!(rows.next().is_none());
+        let config = Config {encoding: WINDOWS_1252, ..Config::new(vec!(COLUMN_1, COLUMN_2), FixedWidth(6))};
+        let mut rows = read_rows(config.clone(), Cursor::new(b"  \xe9#xx\x80aab-".to_vec()));
+        assert_eq!(rows.len().unwrap(), 2);
+        assert_eq!(flatten(&rows.get(1)), Err(format!("{:?}", Error::UnexpectedEof(Position {record: 1, line: 1, byte: 13, field: 1}))));
+        assert_eq!(flatten(&rows.get(0)), Ok(Some(vec!("\u{e9}".to_string(), "".to_string()))));
+        let mut rows = read_rows(Config {encoding: UTF_16LE, ..config}, Cursor::new(b"a\x00".to_vec()));
+        assert!(matches!(rows.seek_record(1), Err(Error::UnlocatableRow(_))));
+    }
+
+    #[test]
+    fn located_rows_must_be_ascii_in_utf8() {
+        let config = Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF));
+        let mut rows = read_rows(config, Cursor::new("  a#\n\u{e9}aab\n  c#\n".as_bytes().to_vec()));
+        assert_eq!(flatten(&rows.get(0)), Ok(Some(vec!("a".to_string(), "".to_string()))));
+        assert_eq!(flatten(&rows.get(1)), Err(format!("{:?}", Error::UnlocatableRow(Position {record: 1, line: 2, byte: 5, field: 0}))));
+        assert!(rows.next().is_none());
+        rows.seek_record(0).unwrap();
+        assert_eq!(flatten(&rows.next().unwrap()), Ok(vec!("a".to_string(), "".to_string())));
+        assert_eq!(flatten(&rows.next().unwrap()), Ok(vec!("\u{e9}aa".to_string(), "b".to_string())));
+    }
+
+    #[test]
+    fn records_are_written_one_at_a_time() {
+        let config = Config {bom: true, ..Config::new(vec!(COLUMN_1, COLUMN_2), Newline(LF))};
+        let mut writer = Writer::new(config, Vec::new()).line_end(FixedWidth(5));
+        writer.write_header(&["h", "i"]).unwrap();
+        assert_eq!(flatten(&writer.write_record(&["aaaa", "b"])), flatten(&Err(Error::ColumnTooLong(Position {record: 1, byte: 5, ..Position::new()}))));
+        writer.serialize(&("aaa", "b")).unwrap();
+        assert_eq!(flatten(&writer.write_header(&["h", "i"])), flatten(&Err(Error::HeaderNotFirst(Position {record: 2, byte: 10, ..Position::new()}))));
+        assert_eq!(*writer.position(), Position {record: 2, byte: 10, ..Position::new()});
+        assert_eq!(writer.into_inner().unwrap(), b"\xef\xbb\xbf  hi aaab ");
+    }
 }
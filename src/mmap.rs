@@ -0,0 +1,74 @@
+//! Files mapped into memory, read without copying by `from_mapped` of both formats
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::error::Result;
+
+/// Contents of a file mapped into memory, or read into memory if it can not be mapped
+///
+/// Regular files are mapped, other files like pipes and terminals and files on file systems
+/// without support for mapping are read to the end. The file must not be modified while it is
+/// mapped, data changing under the reader is undefined behavior.
+pub struct MappedFile {
+    data: Data,
+}
+
+enum Data {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl MappedFile {
+    /// Map the file at path, or read it if it is not a regular file
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MappedFile> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        // Empty files can not be mapped
+        if metadata.is_file() && metadata.len() > 0 {
+            // SAFETY: the mapping is read only and the file must not be modified while it is mapped
+            if let Ok(map) = unsafe { Mmap::map(&file) } {
+                return Ok(MappedFile {data: Data::Mapped(map)})
+            }
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(MappedFile {data: Data::Read(data)})
+    }
+
+    /// Returns true if the file is mapped, false if it was read into memory
+    pub fn is_mapped(&self) -> bool {
+        matches!(self.data, Data::Mapped(_))
+    }
+
+    /// Contents of the file
+    pub fn as_bytes(&self) -> &[u8] {
+        match self.data {
+            Data::Mapped(ref map) => map,
+            Data::Read(ref data) => data
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::MappedFile;
+
+    #[test]
+    fn regular_files_are_mapped() {
+        let file = MappedFile::open("data/short.csv").unwrap();
+        assert!(file.is_mapped());
+        assert_eq!(file.as_bytes(), &fs::read("data/short.csv").unwrap()[..]);
+    }
+
+    #[test]
+    fn other_files_are_read() {
+        let file = MappedFile::open("/dev/null").unwrap();
+        assert!(!file.is_mapped());
+        assert!(file.as_bytes().is_empty());
+    }
+}
@@ -0,0 +1,532 @@
+//! Parallel reading of large DSV files in chunks
+//!
+//! The file is split at nominal offsets into chunks. In the first pass each chunk is scanned in parallel
+//! with the DFA from every state it can start in, then the states at the offsets are resolved in order
+//! and each chunk is moved to the first row start after its offset. In the second pass a fixed pool of
+//! workers reads the chunks with the regular reader, so rows and errors are the same as reading the file
+//! at once, and streams their rows in batches.
+use std::fs::{self, File};
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::vec;
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::common::Row;
+use crate::dfa::{Dfa, State, ROW_START, STATES};
+use crate::dsv::{from_file, read_rows, Config, RowsFile, FILE_BUFFER_SIZE};
+use crate::error::{Error, Position, Result};
+
+/// Size of the chunks files are split into
+const CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Size of the blocks read while looking for the start of a chunk
+const ALIGN_SIZE: usize = 64 * 1024;
+
+/// Number of rows sent at once by a worker reading a chunk
+const BATCH_SIZE: usize = 1024;
+
+/// Chunk of the file starting at a row start
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Chunk {
+    start: u64,
+    end: u64,
+    /// Position of the first row, the record is counted from the start of the chunk
+    position: Position,
+}
+
+/// Result of scanning the data between two nominal offsets in the first pass
+struct Scan {
+    states: [Option<State>; STATES],
+    lines: u64,
+    first: u8,
+    last: u8,
+}
+
+/// Rows of a chunk sent by a worker in the second pass
+struct Batch {
+    rows: Vec<Result<Row>>,
+    /// Number of records read in the chunk including the header, set on its last batch
+    records: Option<u64>,
+}
+
+type Message = (usize, thread::Result<Batch>);
+
+/// Chunk to be read by a worker, with the sender of its batches
+struct Task {
+    index: usize,
+    chunk: Chunk,
+    sender: SyncSender<Message>,
+}
+
+/// Iterator over the rows of a file read by several threads, created by `par_read_file`
+pub struct ParRows {
+    source: Source,
+}
+
+enum Source {
+    /// File that can not be split is read by the calling thread
+    Rows(Box<RowsFile>),
+    Chunks(Box<Chunks>),
+}
+
+struct Chunks {
+    config: Config,
+    path: PathBuf,
+    chunks: Vec<Chunk>,
+    columns: Option<usize>,
+    threads: usize,
+    ordered: bool,
+    /// Receivers of batches, one per chunk in order or one for all chunks, empty until workers are started
+    receivers: Vec<Receiver<Message>>,
+    started: bool,
+    /// Number of chunks whose last batch was returned
+    returned: usize,
+    /// Records in the chunks returned in order
+    records: u64,
+    current: vec::IntoIter<Result<Row>>,
+    done: bool,
+}
+
+impl ParRows {
+    /// Return rows in the order of the file, true by default
+    ///
+    /// Unordered rows are returned as soon as a batch of their chunk is read. The record numbers in
+    /// the positions of their errors are counted from the start of the chunk, lines and bytes are exact.
+    pub fn ordered(mut self, ordered: bool) -> ParRows {
+        if let Source::Chunks(ref mut chunks) = self.source {
+            chunks.ordered = ordered;
+        }
+        self
+    }
+}
+
+impl Iterator for ParRows {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        match self.source {
+            Source::Rows(ref mut rows) => rows.next(),
+            Source::Chunks(ref mut chunks) => chunks.next()
+        }
+    }
+}
+
+impl Chunks {
+    /// Start a fixed number of workers taking chunks in order from a shared queue
+    ///
+    /// Channels hold few batches, so workers wait while their rows are not received. In order each
+    /// chunk has its own channel and the chunk being returned is always read by a running worker.
+    fn start(&mut self) {
+        self.started = true;
+        let workers = self.threads.min(self.chunks.len());
+        let mut tasks = Vec::with_capacity(self.chunks.len());
+        if self.ordered {
+            for (index, &chunk) in self.chunks.iter().enumerate() {
+                let (sender, receiver) = sync_channel(1);
+                self.receivers.push(receiver);
+                tasks.push(Task {index, chunk, sender});
+            }
+        } else {
+            let (sender, receiver) = sync_channel(workers);
+            self.receivers.push(receiver);
+            for (index, &chunk) in self.chunks.iter().enumerate() {
+                tasks.push(Task {index, chunk, sender: sender.clone()});
+            }
+        }
+        let queue = Arc::new(Mutex::new(tasks.into_iter()));
+        for _ in 0..workers {
+            let (config, path, columns, queue) = (self.config, self.path.clone(), self.columns, queue.clone());
+            thread::spawn(move || work(config, &path, columns, &queue));
+        }
+    }
+
+    /// Stop reading, workers end when sending their next batch fails
+    fn finish(&mut self) {
+        self.done = true;
+        self.receivers.clear();
+        self.current = Vec::new().into_iter();
+    }
+}
+
+impl Iterator for Chunks {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Result<Row>> {
+        loop {
+            if let Some(row) = self.current.next() {
+                if ends_reading(&row) {
+                    self.finish();
+                }
+                return Some(row)
+            }
+            if self.done || self.returned == self.chunks.len() {
+                return None
+            }
+            if !self.started {
+                self.start();
+            }
+            let receiver = &self.receivers[if self.ordered { self.returned } else { 0 }];
+            // Senders of chunks not returned yet are held by the queue or a worker, which sends before it ends
+            let (_, batch) = receiver.recv().unwrap();
+            let mut batch = batch.unwrap_or_else(|payload| panic::resume_unwind(payload));
+            if self.ordered {
+                let records = self.records;
+                for row in batch.rows.iter_mut() {
+                    if let Some(position) = row.as_mut().err().and_then(|err| err.position_mut()) {
+                        position.record += records;
+                    }
+                }
+                self.records += batch.records.unwrap_or(0);
+            }
+            if batch.records.is_some() {
+                self.returned += 1;
+            }
+            self.current = batch.rows.into_iter();
+        }
+    }
+}
+
+/// Read chunks from the queue until it is empty or rows are no longer received
+fn work(config: Config, path: &Path, columns: Option<usize>, queue: &Mutex<vec::IntoIter<Task>>) {
+    loop {
+        // Queue is only locked to take the next task, so it can not be poisoned
+        let task = match queue.lock().unwrap().next() {
+            Some(task) => task,
+            None => return
+        };
+        let (index, sender) = (task.index, &task.sender);
+        let mut send = |batch| sender.send((index, Ok(batch))).is_ok();
+        match panic::catch_unwind(AssertUnwindSafe(|| read_chunk(config, path, index, task.chunk, columns, &mut send))) {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(payload) => {
+                let _ = sender.send((index, Err(payload)));
+                return
+            }
+        }
+    }
+}
+
+/// Returns true if the row is an error that ends reading with the `Stop` recovery
+fn ends_reading(row: &Result<Row>) -> bool {
+    matches!(*row, Err(ref err) if !matches!(*err, Error::UnequalLengths(..)))
+}
+
+/// Read rows of a chunk until the first error that ends reading and send them in batches
+///
+/// Returns false if a batch could not be sent because rows are no longer received.
+fn read_chunk(config: Config, path: &Path, index: usize, chunk: Chunk, columns: Option<usize>, send: &mut dyn FnMut(Batch) -> bool) -> bool {
+    let mut file = match File::open(path).and_then(|mut file| file.seek(SeekFrom::Start(chunk.start)).map(|_| file)) {
+        Ok(file) => file,
+        Err(err) => return send(Batch {rows: vec!(Err(err.into())), records: Some(0)})
+    };
+    let reader = BufReader::with_capacity(FILE_BUFFER_SIZE, Read::by_ref(&mut file).take(chunk.end - chunk.start));
+    let mut reader = read_rows(config, reader);
+    if index > 0 {
+        reader = reader.resume_at(chunk.position, columns);
+    }
+    let mut rows = Vec::with_capacity(BATCH_SIZE);
+    for row in reader.by_ref() {
+        let end = ends_reading(&row);
+        rows.push(row);
+        if end {
+            break
+        }
+        if rows.len() == BATCH_SIZE && !send(Batch {rows: mem::replace(&mut rows, Vec::with_capacity(BATCH_SIZE)), records: None}) {
+            return false
+        }
+    }
+    send(Batch {rows, records: Some(reader.position().record)})
+}
+
+/// Read the rows of a DSV file with several threads
+///
+/// The file is split into chunks of several megabytes at row starts found by scanning the file in
+/// parallel, then the chunks are read in parallel. Rows are returned in the order of the file unless
+/// `ordered(false)` is set. Errors end reading like with the `Stop` recovery.
+///
+/// The number of threads is limited to the available parallelism. Rows are passed from the reading
+/// threads in small batches, which wait until the rows before them are returned.
+///
+/// Files are read by the calling thread if they are small, only one thread is available, the configuration has comments, uses
+/// features only the character reader supports, or an encoding other than UTF-8.
+///
+/// ```rust,no_run
+/// # use tabular::dsv::{par_read_file, CSV};
+/// let rows = par_read_file(CSV, "path/large.csv", 8).unwrap();
+/// println!("{}", rows.filter(|row| row.is_ok()).count());
+/// ```
+pub fn par_read_file<P: AsRef<Path>>(config: Config, path: P, threads: usize) -> Result<ParRows> {
+    let available = thread::available_parallelism().map_or(1, usize::from);
+    par_read_chunks(config, path.as_ref(), threads.min(available), CHUNK_SIZE)
+}
+
+fn par_read_chunks(config: Config, path: &Path, threads: usize, chunk_size: u64) -> Result<ParRows> {
+    let sequential = || Ok(ParRows {source: Source::Rows(Box::new(from_file(config, path)?))});
+    let len = fs::metadata(path)?.len();
+    let dfa = match Dfa::new(&config) {
+        Some(dfa) if config.comment.is_none() && config.encoding == UTF_8 => dfa,
+        _ => return sequential()
+    };
+    if threads < 2 || len <= chunk_size {
+        return sequential()
+    }
+    let mut head = Vec::new();
+    File::open(path)?.take(3).read_to_end(&mut head)?;
+    let bom = match Encoding::for_bom(&head) {
+        Some((encoding, len)) if encoding == UTF_8 => len as u64,
+        Some(_) => return sequential(),
+        None => 0
+    };
+    let offsets: Vec<u64> = (bom..len).step_by(chunk_size as usize).collect();
+    let scans = scan(&dfa, path, &offsets, len, threads)?;
+    let chunks = split(&dfa, path, &offsets, &scans, len, bom)?;
+    if chunks.len() < 2 {
+        return sequential()
+    }
+    let columns = if config.flexible {
+        None
+    } else {
+        let mut rows = from_file(config, path)?;
+        rows.next();
+        rows.columns()
+    };
+    let chunks = Chunks {
+        config,
+        path: path.to_path_buf(),
+        chunks,
+        columns,
+        threads,
+        ordered: true,
+        receivers: Vec::new(),
+        started: false,
+        returned: 0,
+        records: 0,
+        current: Vec::new().into_iter(),
+        done: false
+    };
+    Ok(ParRows {source: Source::Chunks(Box::new(chunks))})
+}
+
+/// First pass scanning the data between offsets in parallel
+fn scan(dfa: &Dfa, path: &Path, offsets: &[u64], len: u64, threads: usize) -> Result<Vec<Scan>> {
+    let next = AtomicUsize::new(0);
+    let scan_next = || -> Result<Vec<(usize, Scan)>> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::new();
+        let mut scans = Vec::new();
+        loop {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let start = match offsets.get(index) {
+                Some(&start) => start,
+                None => return Ok(scans)
+            };
+            let end = offsets.get(index + 1).copied().unwrap_or(len);
+            data.clear();
+            file.seek(SeekFrom::Start(start))?;
+            Read::by_ref(&mut file).take(end - start).read_to_end(&mut data)?;
+            let (first, last) = (data.first().copied().unwrap_or(0), data.last().copied().unwrap_or(0));
+            scans.push((index, Scan {states: dfa.run_all(&data), lines: dfa.count_lines(&data), first, last}));
+        }
+    };
+    let mut scans = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(offsets.len())).map(|_| scope.spawn(scan_next)).collect();
+        workers.into_iter()
+            .map(|worker| worker.join().unwrap_or_else(|payload| panic::resume_unwind(payload)))
+            .collect::<Result<Vec<_>>>()
+    })?.into_iter().flatten().collect::<Vec<_>>();
+    scans.sort_by_key(|&(index, _)| index);
+    Ok(scans.into_iter().map(|(_, scan)| scan).collect())
+}
+
+/// Resolve the states at the offsets in order and split the file at the first row start after each
+///
+/// Splitting ends where the data is not valid, the rest of the file is read as one chunk.
+fn split(dfa: &Dfa, path: &Path, offsets: &[u64], scans: &[Scan], len: u64, bom: u64) -> Result<Vec<Chunk>> {
+    let mut file = File::open(path)?;
+    let mut chunks = vec!(Chunk {start: 0, end: len, position: Position::new()});
+    let mut state = Some(ROW_START);
+    let mut line = 1;
+    for i in 1..offsets.len() {
+        let (before, scan) = (&scans[i - 1], &scans[i]);
+        state = state.and_then(|state| before.states[state as usize]);
+        line += before.lines;
+        if i > 1 {
+            line -= joined_lines(dfa, scans[i - 2].last, before.first);
+        }
+        let state = match state {
+            Some(state) => state,
+            None => break
+        };
+        let (start, lines) = match row_start(dfa, &mut file, offsets[i], state)? {
+            Some((start, segment)) if start < len && start > chunks.last().unwrap().start => {
+                let joined = if segment.is_empty() { 0 } else { joined_lines(dfa, before.last, scan.first) };
+                (start, dfa.count_lines(&segment) - joined)
+            }
+            _ => continue
+        };
+        // Byte order mark at the start of a chunk would be removed by its reader
+        let mut head = Vec::new();
+        file.seek(SeekFrom::Start(start))?;
+        Read::by_ref(&mut file).take(3).read_to_end(&mut head)?;
+        if Encoding::for_bom(&head).is_some() {
+            continue
+        }
+        chunks.last_mut().unwrap().end = start;
+        let position = Position {record: 0, field: 0, line: line + lines, byte: start - bom};
+        chunks.push(Chunk {start, end: len, position});
+    }
+    Ok(chunks)
+}
+
+/// Lines counted twice when data ending with byte `last` and data starting with `first` are counted separately
+fn joined_lines(dfa: &Dfa, last: u8, first: u8) -> u64 {
+    dfa.count_lines(&[last]) + dfa.count_lines(&[first]) - dfa.count_lines(&[last, first])
+}
+
+/// First row start at or after offset in state, with the data before it
+fn row_start(dfa: &Dfa, file: &mut File, offset: u64, state: State) -> Result<Option<(u64, Vec<u8>)>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut state = Some(state);
+    let mut segment = Vec::new();
+    let mut block = vec![0; ALIGN_SIZE];
+    loop {
+        let n = file.read(&mut block)?;
+        if n == 0 || state.is_none() {
+            return Ok(None)
+        }
+        if let Some(end) = dfa.row_start(&mut state, &block[..n]) {
+            segment.extend_from_slice(&block[..end]);
+            return Ok(Some((offset + segment.len() as u64, segment)))
+        }
+        segment.extend_from_slice(&block[..n]);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    use crate::dsv::{from_file, Config, ConfigBuilder, CSV, TSV, LF, Char, Universal};
+
+    use super::{par_read_chunks, ParRows, Source};
+
+    /// Rows and errors read in parallel in order and as read at once
+    fn read_both(config: Config, path: &str, chunk_size: u64) -> (Vec<String>, Vec<String>, usize) {
+        let rows = par_read_chunks(config, path.as_ref(), 4, chunk_size).unwrap();
+        let chunks = match rows.source {
+            Source::Chunks(ref chunks) => chunks.chunks.len(),
+            Source::Rows(_) => 1
+        };
+        let parallel = rows.map(|row| format!("{:?}", row)).collect();
+        let expected = from_file(config, path).unwrap().map(|row| format!("{:?}", row)).collect();
+        (parallel, expected, chunks)
+    }
+
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &str) -> TempFile {
+            let path = env::temp_dir().join(format!("tabular-par-{}-{}", process::id(), name));
+            fs::write(&path, data).unwrap();
+            TempFile(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn chunks_are_read_in_order() {
+        let (parallel, expected, chunks) = read_both(CSV, "data/medium.csv", 64 * 1024);
+        assert_eq!(chunks, 21);
+        assert_eq!(parallel, expected);
+        let config = ConfigBuilder::new(TSV).has_headers(true).flexible(false).build();
+        let (parallel, expected, _) = read_both(config, "data/short.tsv", 1000);
+        assert_eq!(parallel, expected);
+    }
+
+    #[test]
+    fn chunks_start_after_quoted_line_terminators() {
+        let data = "a,\"b\r\nc,\"\"d\"\"\r\n\",e\r\n\"f\r\n\"\r\n".repeat(50);
+        let file = TempFile::new("quoted.csv", &data);
+        let (parallel, expected, chunks) = read_both(CSV, file.path(), 7);
+        assert!(chunks > 40, "{}", chunks);
+        assert_eq!(parallel, expected);
+
+        let config = ConfigBuilder::new(CSV).escape(Char('\\')).newlines(Universal).build();
+        let data = "\u{feff}a,\"\\\"b\r\n\r\"\rc\n\r\n\"d\\\"\",\u{e9}\r".repeat(50) + "\"e\"f";
+        let file = TempFile::new("universal.csv", &data);
+        for chunk_size in 3..12 {
+            let (parallel, expected, chunks) = read_both(config, file.path(), chunk_size);
+            assert!(chunks > 40, "{}", chunks);
+            assert_eq!(parallel, expected);
+        }
+    }
+
+    #[test]
+    fn errors_have_positions_of_whole_file() {
+        let config = ConfigBuilder::new(CSV).line_terminator(LF).flexible(false).build();
+        let data = format!("{}a,b,c\n{}", "a,b\n\"c\nd\",e\n".repeat(20), "f,g\n".repeat(10));
+        let file = TempFile::new("lengths.csv", &data);
+        let (parallel, expected, _) = read_both(config, file.path(), 10);
+        assert!(parallel[40].contains("UnequalLengths(2, 3, Position { record: 40"), "{:?}", parallel[40]);
+        assert_eq!(parallel.len(), 51);
+        assert_eq!(parallel, expected);
+
+        let data = format!("{}\"g\"h\n{}", "a,b\n".repeat(20), "i,j\n".repeat(10));
+        let file = TempFile::new("quote.csv", &data);
+        let (parallel, expected, _) = read_both(config, file.path(), 10);
+        assert_eq!(parallel, expected);
+    }
+
+    #[test]
+    fn unordered_rows_are_all_read() {
+        let rows: ParRows = par_read_chunks(CSV, "data/short.csv".as_ref(), 3, 10_000).unwrap();
+        let mut parallel: Vec<_> = rows.ordered(false).map(|row| row.unwrap()).collect();
+        let mut expected: Vec<_> = from_file(CSV, "data/short.csv").unwrap().map(|row| row.unwrap()).collect();
+        parallel.sort();
+        expected.sort();
+        assert_eq!(parallel, expected);
+    }
+
+    #[test]
+    fn chunks_are_streamed_in_batches() {
+        let config = ConfigBuilder::new(CSV).line_terminator(LF).build();
+        let file = TempFile::new("batches.csv", &"a,b\n".repeat(10_000));
+        let (parallel, expected, chunks) = read_both(config, file.path(), 8192);
+        assert_eq!(chunks, 5);
+        assert_eq!(parallel, expected);
+        let rows = par_read_chunks(config, file.path().as_ref(), 2, 8192).unwrap();
+        assert_eq!(rows.ordered(false).count(), 10_000);
+        // Workers waiting to send the rest of their chunks end when the rows are dropped
+        let mut rows = par_read_chunks(config, file.path().as_ref(), 2, 8192).unwrap();
+        assert_eq!(rows.by_ref().take(3000).count(), 3000);
+    }
+
+    #[test]
+    fn small_files_are_read_at_once() {
+        let rows = par_read_chunks(CSV, "data/short.csv".as_ref(), 4, 1 << 20).unwrap();
+        assert!(matches!(rows.source, Source::Rows(_)));
+        let config = ConfigBuilder::new(CSV).comment(Some('#')).build();
+        let rows = par_read_chunks(config, "data/short.csv".as_ref(), 4, 1000).unwrap();
+        assert!(matches!(rows.source, Source::Rows(_)));
+    }
+}
@@ -0,0 +1,642 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::slice;
+use std::str;
+
+use crate::common::{NullableRow, Row};
+use crate::dsv::{Utf8, Strict, Lossy};
+use crate::error::{Error, Position, Result};
+
+/// Row with all columns stored in a single buffer
+///
+/// Null columns are empty and marked as null. Reading into the same record again reuses its allocations.
+#[derive(Clone, Default, Eq, PartialEq)]
+pub struct StringRecord {
+    buf: String,
+    ends: Vec<usize>,
+    nulls: Vec<usize>,
+}
+
+impl StringRecord {
+    /// Create an empty record
+    pub fn new() -> StringRecord {
+        StringRecord::default()
+    }
+
+    /// Create an empty record with capacity for `buffer` bytes of data in `columns` columns
+    pub fn with_capacity(buffer: usize, columns: usize) -> StringRecord {
+        StringRecord {
+            buf: String::with_capacity(buffer),
+            ends: Vec::with_capacity(columns),
+            nulls: Vec::new()
+        }
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Returns true if there are no columns
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Column at index
+    pub fn get(&self, i: usize) -> Option<&str> {
+        let end = *self.ends.get(i)?;
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        Some(&self.buf[start..end])
+    }
+
+    /// Column at index, `Some(None)` if the column is null
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{from_str, ConfigBuilder, StringRecord, CSV, UnquotedEmpty};
+    /// let config = ConfigBuilder::new(CSV).null(UnquotedEmpty).build();
+    /// let mut record = StringRecord::new();
+    /// from_str(config, "a,,\"\"").read_record(&mut record).unwrap();
+    /// assert_eq!(record.get_nullable(1), Some(None));
+    /// assert_eq!(record.get_nullable(2), Some(Some("")));
+    /// ```
+    pub fn get_nullable(&self, i: usize) -> Option<Option<&str>> {
+        let col = self.get(i)?;
+        Some(if self.is_null(i) { None } else { Some(col) })
+    }
+
+    /// Returns true if the column at index is null
+    pub fn is_null(&self, i: usize) -> bool {
+        self.nulls.binary_search(&i).is_ok()
+    }
+
+    /// Iterator over columns
+    pub fn iter(&self) -> StringRecordIter<'_> {
+        StringRecordIter {
+            record: self,
+            column: 0
+        }
+    }
+
+    /// Iterator over columns with `None` for null columns
+    pub fn iter_nullable(&self) -> NullableIter<'_> {
+        NullableIter {
+            record: self,
+            column: 0
+        }
+    }
+
+    /// Data of all columns without separators
+    pub fn as_str(&self) -> &str {
+        &self.buf
+    }
+
+    /// Remove all columns, keeping the allocated memory
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.ends.clear();
+        self.nulls.clear();
+    }
+
+    /// Append a column
+    pub fn push_field(&mut self, col: &str) {
+        self.buf.push_str(col);
+        self.ends.push(self.buf.len());
+    }
+
+    /// Append a null column
+    pub fn push_null(&mut self) {
+        self.nulls.push(self.ends.len());
+        self.ends.push(self.buf.len());
+    }
+
+    /// Copy columns into a row, null columns are empty
+    pub fn to_row(&self) -> Row {
+        self.iter().map(|col| col.to_string()).collect()
+    }
+
+    /// Copy columns into a row with `None` for null columns
+    pub fn to_nullable_row(&self) -> NullableRow {
+        self.iter_nullable().map(|col| col.map(str::to_string)).collect()
+    }
+
+    /// Convert into a byte record without copying the data
+    pub fn into_byte_record(self) -> ByteRecord {
+        ByteRecord {
+            buf: self.buf.into_bytes(),
+            ends: self.ends,
+            nulls: self.nulls,
+            position: Position::new()
+        }
+    }
+}
+
+impl fmt::Debug for StringRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl Index<usize> for StringRecord {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        self.get(i).expect("column index out of bounds")
+    }
+}
+
+impl<'a> FromIterator<&'a str> for StringRecord {
+    fn from_iter<I: IntoIterator<Item = &'a str>>(iter: I) -> StringRecord {
+        let mut record = StringRecord::new();
+        for col in iter {
+            record.push_field(col);
+        }
+        record
+    }
+}
+
+impl<'a> From<&'a [String]> for StringRecord {
+    fn from(row: &'a [String]) -> StringRecord {
+        row.iter().map(|col| &col[..]).collect()
+    }
+}
+
+impl<'a> IntoIterator for &'a StringRecord {
+    type Item = &'a str;
+    type IntoIter = StringRecordIter<'a>;
+
+    fn into_iter(self) -> StringRecordIter<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over columns of a record
+pub struct StringRecordIter<'a> {
+    record: &'a StringRecord,
+    column: usize,
+}
+
+impl<'a> Iterator for StringRecordIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let col = self.record.get(self.column)?;
+        self.column += 1;
+        Some(col)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.record.len() - self.column;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for StringRecordIter<'a> {}
+
+/// Iterator over columns of a record with `None` for null columns
+pub struct NullableIter<'a> {
+    record: &'a StringRecord,
+    column: usize,
+}
+
+impl<'a> Iterator for NullableIter<'a> {
+    type Item = Option<&'a str>;
+
+    fn next(&mut self) -> Option<Option<&'a str>> {
+        let col = self.record.get_nullable(self.column)?;
+        self.column += 1;
+        Some(col)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.record.len() - self.column;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for NullableIter<'a> {}
+
+/// Row of raw byte columns stored in a single buffer, the data does not have to be valid UTF-8
+///
+/// Null columns are empty and marked as null. Reading into the same record again reuses its allocations.
+#[derive(Clone, Default)]
+pub struct ByteRecord {
+    buf: Vec<u8>,
+    ends: Vec<usize>,
+    nulls: Vec<usize>,
+    position: Position,
+}
+
+impl ByteRecord {
+    /// Create an empty record
+    pub fn new() -> ByteRecord {
+        ByteRecord::default()
+    }
+
+    /// Create an empty record with capacity for `buffer` bytes of data in `columns` columns
+    pub fn with_capacity(buffer: usize, columns: usize) -> ByteRecord {
+        ByteRecord {
+            buf: Vec::with_capacity(buffer),
+            ends: Vec::with_capacity(columns),
+            nulls: Vec::new(),
+            position: Position::new()
+        }
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    /// Returns true if there are no columns
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+
+    /// Column at index
+    pub fn get(&self, i: usize) -> Option<&[u8]> {
+        let end = *self.ends.get(i)?;
+        let start = if i == 0 { 0 } else { self.ends[i - 1] };
+        Some(&self.buf[start..end])
+    }
+
+    /// Returns true if the column at index is null
+    pub fn is_null(&self, i: usize) -> bool {
+        self.nulls.binary_search(&i).is_ok()
+    }
+
+    /// Iterator over columns
+    pub fn iter(&self) -> ByteRecordIter<'_> {
+        ByteRecordIter {
+            record: self,
+            column: 0
+        }
+    }
+
+    /// Data of all columns without separators
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Position of the start of the record in the data it was read from
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Remove all columns, keeping the allocated memory
+    pub fn clear(&mut self) {
+        self.buf.clear();
+        self.ends.clear();
+        self.nulls.clear();
+    }
+
+    /// Append a column
+    pub fn push_field(&mut self, col: &[u8]) {
+        self.buf.extend_from_slice(col);
+        self.end_field();
+    }
+
+    /// Append a null column
+    pub fn push_null(&mut self) {
+        self.end_null_field();
+    }
+
+    /// Validate columns as UTF-8 and convert into a string record
+    ///
+    /// With `Strict` the first invalid column is reported as `InvalidUtf8` at the position
+    /// of the record, with `Lossy` invalid sequences are replaced with U+FFFD.
+    ///
+    /// ```rust
+    /// # use tabular::dsv::{ByteRecord, Lossy};
+    /// let mut record = ByteRecord::new();
+    /// record.push_field(b"caf\xe9");
+    /// assert_eq!(record.into_string_record(Lossy).unwrap().get(0), Some("caf\u{FFFD}"));
+    /// ```
+    pub fn into_string_record(self, utf8: Utf8) -> Result<StringRecord> {
+        let ByteRecord {buf, ends, nulls, position} = self;
+        match String::from_utf8(buf) {
+            Ok(buf) => Ok(StringRecord {buf, ends, nulls}),
+            Err(err) => {
+                let invalid = ByteRecord {buf: err.into_bytes(), ends, nulls, position};
+                let mut record = StringRecord::with_capacity(invalid.buf.len(), invalid.len());
+                for (i, col) in invalid.iter().enumerate() {
+                    match (str::from_utf8(col), utf8) {
+                        (Ok(col), _) => record.push_field(col),
+                        (Err(_), Lossy) => record.push_field(&String::from_utf8_lossy(col)),
+                        (Err(_), Strict) => {
+                            return Err(Error::InvalidUtf8(Position {field: i as u64, ..position}))
+                        }
+                    }
+                }
+                record.nulls = invalid.nulls;
+                Ok(record)
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub(crate) fn push_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    #[inline(always)]
+    pub(crate) fn end_field(&mut self) {
+        self.ends.push(self.buf.len());
+    }
+
+    /// Discard data of current unfinished column and end it as null
+    pub(crate) fn end_null_field(&mut self) {
+        self.discard_field();
+        self.nulls.push(self.ends.len());
+        self.end_field();
+    }
+
+    /// Data of current unfinished column
+    pub(crate) fn field(&self) -> &[u8] {
+        &self.buf[self.ends.last().cloned().unwrap_or(0)..]
+    }
+
+    /// Length of the data of current unfinished column
+    #[inline(always)]
+    pub(crate) fn field_len(&self) -> usize {
+        self.buf.len() - self.ends.last().cloned().unwrap_or(0)
+    }
+
+    /// Remove trailing bytes matching predicate from current unfinished column
+    pub(crate) fn trim_field_end<F: Fn(u8) -> bool>(&mut self, trim: F) {
+        while self.field_len() > 0 && trim(self.buf[self.buf.len() - 1]) {
+            self.buf.pop();
+        }
+    }
+
+    /// Discard data of current unfinished column
+    pub(crate) fn discard_field(&mut self) {
+        let end = self.ends.last().cloned().unwrap_or(0);
+        self.buf.truncate(end);
+    }
+
+    pub(crate) fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+
+    /// Convert into a string record, the data must have been validated while reading
+    pub(crate) fn into_valid_string_record(self) -> Result<StringRecord> {
+        let position = self.position;
+        self.into_string_record(Strict).map_err(|_| Error::InvalidUtf8(position))
+    }
+}
+
+impl PartialEq for ByteRecord {
+    fn eq(&self, other: &ByteRecord) -> bool {
+        self.buf == other.buf && self.ends == other.ends && self.nulls == other.nulls
+    }
+}
+
+impl Eq for ByteRecord {}
+
+impl fmt::Debug for ByteRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter().map(String::from_utf8_lossy)).finish()
+    }
+}
+
+impl Index<usize> for ByteRecord {
+    type Output = [u8];
+
+    fn index(&self, i: usize) -> &[u8] {
+        self.get(i).expect("column index out of bounds")
+    }
+}
+
+impl<'a> FromIterator<&'a [u8]> for ByteRecord {
+    fn from_iter<I: IntoIterator<Item = &'a [u8]>>(iter: I) -> ByteRecord {
+        let mut record = ByteRecord::new();
+        for col in iter {
+            record.push_field(col);
+        }
+        record
+    }
+}
+
+impl From<StringRecord> for ByteRecord {
+    fn from(record: StringRecord) -> ByteRecord {
+        record.into_byte_record()
+    }
+}
+
+impl<'a> IntoIterator for &'a ByteRecord {
+    type Item = &'a [u8];
+    type IntoIter = ByteRecordIter<'a>;
+
+    fn into_iter(self) -> ByteRecordIter<'a> {
+        self.iter()
+    }
+}
+
+/// Iterator over columns of a byte record
+pub struct ByteRecordIter<'a> {
+    record: &'a ByteRecord,
+    column: usize,
+}
+
+impl<'a> Iterator for ByteRecordIter<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        let col = self.record.get(self.column)?;
+        self.column += 1;
+        Some(col)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.record.len() - self.column;
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for ByteRecordIter<'a> {}
+
+/// Row of columns borrowed from the data they were read from, columns that had to be decoded are owned
+///
+/// Null columns are empty and marked as null.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct BorrowedRecord<'a> {
+    cols: Vec<Cow<'a, str>>,
+    nulls: Vec<usize>,
+    position: Position,
+}
+
+impl<'a> BorrowedRecord<'a> {
+    /// Create an empty record
+    pub fn new() -> BorrowedRecord<'a> {
+        BorrowedRecord::default()
+    }
+
+    /// Number of columns
+    pub fn len(&self) -> usize {
+        self.cols.len()
+    }
+
+    /// Returns true if there are no columns
+    pub fn is_empty(&self) -> bool {
+        self.cols.is_empty()
+    }
+
+    /// Column at index
+    pub fn get(&self, i: usize) -> Option<&str> {
+        self.cols.get(i).map(|col| &col[..])
+    }
+
+    /// Returns true if the column at index is null
+    pub fn is_null(&self, i: usize) -> bool {
+        self.nulls.binary_search(&i).is_ok()
+    }
+
+    /// Iterator over columns, borrowed columns live as long as the data
+    pub fn iter(&self) -> slice::Iter<'_, Cow<'a, str>> {
+        self.cols.iter()
+    }
+
+    /// Position of the start of the record in the data it was read from
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// Remove all columns
+    pub fn clear(&mut self) {
+        self.cols.clear();
+        self.nulls.clear();
+    }
+
+    /// Append a column
+    pub fn push_field(&mut self, col: Cow<'a, str>) {
+        self.cols.push(col);
+    }
+
+    /// Append a null column
+    pub fn push_null(&mut self) {
+        self.nulls.push(self.cols.len());
+        self.cols.push(Cow::Borrowed(""));
+    }
+
+    /// Copy columns into a row, null columns are empty
+    pub fn to_row(&self) -> Row {
+        self.iter().map(|col| col.to_string()).collect()
+    }
+
+    /// Convert into the columns
+    pub fn into_columns(self) -> Vec<Cow<'a, str>> {
+        self.cols
+    }
+
+    /// Append copies of the columns of record
+    pub(crate) fn extend_owned(&mut self, record: &StringRecord) {
+        for (i, col) in record.iter().enumerate() {
+            if record.is_null(i) {
+                self.push_null();
+            } else {
+                self.push_field(Cow::Owned(col.to_string()));
+            }
+        }
+    }
+
+    pub(crate) fn set_position(&mut self, position: Position) {
+        self.position = position;
+    }
+}
+
+impl<'a> Index<usize> for BorrowedRecord<'a> {
+    type Output = str;
+
+    fn index(&self, i: usize) -> &str {
+        self.get(i).expect("column index out of bounds")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dsv::{Strict, Lossy};
+    use crate::error::{Error, Position};
+
+    use super::{ByteRecord, StringRecord};
+
+    #[test]
+    fn columns_are_stored_in_order() {
+        let record: StringRecord = vec!("foo", "", "bar").into_iter().collect();
+        assert_eq!(record.len(), 3);
+        assert_eq!(record.get(0), Some("foo"));
+        assert_eq!(record.get(1), Some(""));
+        assert_eq!(&record[2], "bar");
+        assert_eq!(record.get(3), None);
+        assert_eq!(record.as_str(), "foobar");
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!("foo", "", "bar"));
+    }
+
+    #[test]
+    fn clear_keeps_capacity() {
+        let mut record = StringRecord::with_capacity(16, 4);
+        record.push_field("foo");
+        record.clear();
+        assert!(record.is_empty());
+        assert_eq!(record.as_str(), "");
+        assert!(record.buf.capacity() >= 16 && record.ends.capacity() >= 4);
+    }
+
+    #[test]
+    fn unfinished_field_is_discarded() {
+        let mut record = ByteRecord::new();
+        record.push_field(b"a");
+        record.push_bytes(b"b");
+        assert_eq!(record.field_len(), 1);
+        record.discard_field();
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!(&b"a"[..]));
+    }
+
+    #[test]
+    fn unfinished_field_end_is_trimmed() {
+        let mut record = ByteRecord::new();
+        record.push_field(b"a ");
+        record.push_bytes(b"b \t");
+        record.trim_field_end(|b| b == b' ' || b == b'\t');
+        assert_eq!(record.as_bytes(), b"a b");
+        record.push_bytes(b"  ");
+        record.trim_field_end(|b| b == b' ');
+        assert_eq!(record.field_len(), 1);
+    }
+
+    #[test]
+    fn null_columns_are_marked() {
+        let mut record = StringRecord::new();
+        record.push_field("a");
+        record.push_null();
+        record.push_field("");
+        assert_eq!(record.iter().collect::<Vec<_>>(), vec!("a", "", ""));
+        assert_eq!(record.iter_nullable().collect::<Vec<_>>(), vec!(Some("a"), None, Some("")));
+        assert_eq!(record.to_nullable_row(), vec!(Some("a".to_string()), None, Some("".to_string())));
+        let mut bytes = record.into_byte_record();
+        assert!(bytes.is_null(1) && !bytes.is_null(2));
+        bytes.push_field(b"\xff");
+        bytes.push_bytes(b"x");
+        bytes.end_null_field();
+        let record = bytes.into_string_record(Lossy).unwrap();
+        assert_eq!(record.iter_nullable().skip(3).collect::<Vec<_>>(), vec!(Some("\u{FFFD}"), None));
+    }
+
+    #[test]
+    fn string_record_converts_without_validation_errors() {
+        let record: StringRecord = vec!("a", "ü").into_iter().collect();
+        let bytes = record.clone().into_byte_record();
+        assert_eq!(bytes.get(1), Some("ü".as_bytes()));
+        assert_eq!(bytes.into_string_record(Strict).unwrap(), record);
+    }
+
+    #[test]
+    fn invalid_column_is_validated() {
+        let record: ByteRecord = vec!(&b"ok"[..], &b"\xff\xfe"[..]).into_iter().collect();
+        let lossy = record.clone().into_string_record(Lossy).unwrap();
+        assert_eq!(lossy.iter().collect::<Vec<_>>(), vec!("ok", "\u{FFFD}\u{FFFD}"));
+        let err = record.into_string_record(Strict).unwrap_err();
+        assert_eq!(format!("{:?}", err), format!("{:?}", Error::InvalidUtf8(Position {field: 1, ..Position::new()})));
+    }
+}
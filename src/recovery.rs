@@ -0,0 +1,243 @@
+//! Recovery from errors in records while reading
+use std::io::{self, BufRead, Read, Write};
+
+use crate::dsv::{self, write_bom, write_byte_row_at};
+use crate::error::{Error, Position, Result};
+
+pub use self::Recovery::*;
+
+/// Handling of records that can not be read
+///
+/// Recovering readers skip the rest of a bad record up to the next line terminator and
+/// continue with the record after it. I/O errors and errors in the header always stop reading.
+#[derive(Eq, PartialEq, Clone, Copy, Debug)]
+pub enum Recovery {
+    /// The first error is returned and ends reading, except rows with unequal lengths that
+    /// were read completely
+    Stop,
+    /// Each error is returned and reading continues with the next record
+    Skip,
+    /// Bad records are not returned, they are kept with their errors and raw data as `Rejected`
+    Collect,
+}
+
+/// Record that could not be read, kept by the `Collect` recovery
+#[derive(Debug)]
+pub struct Rejected {
+    /// Reason the record was rejected, with its position
+    pub error: Error,
+    /// Data of the record as it was read, decoded to UTF-8, without the line terminator
+    pub raw: Vec<u8>,
+}
+
+/// Writer of rejected records as DSV rows with two columns, the raw record and the reason
+///
+/// Raw data is written unchanged, invalid UTF-8 sequences included, so rejects are fixed and
+/// replayed by reading the first column of each row, with `read_byte_record` if it is not valid.
+/// Raw data of other encodings is decoded to UTF-8, writing rejects in the encoding it was read
+/// with encodes it back. Invalid sequences can not be written in encodings other than UTF-8,
+/// writing such raw data is an `InvalidUtf8` error.
+pub struct RejectWriter<W> {
+    writer: W,
+    config: dsv::Config,
+    position: Position,
+}
+
+impl<W: Write> RejectWriter<W> {
+    /// Create a writer of rejects in the format of config
+    pub fn new(config: dsv::Config, writer: W) -> RejectWriter<W> {
+        RejectWriter {
+            writer,
+            config,
+            position: Position::new()
+        }
+    }
+
+    /// Write a rejected record
+    pub fn write(&mut self, rejected: &Rejected) -> Result<()> {
+        if self.position.record == 0 && self.position.byte == 0 {
+            write_bom(self.config, &mut self.writer)?;
+        }
+        let error = rejected.error.to_string();
+        write_byte_row_at(self.config, &mut self.writer, &[&rejected.raw, error.as_bytes()], &mut self.position)
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.writer.flush()?)
+    }
+
+    /// Return the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Iterator over the good rows of a reader, rejected records are written to a `RejectWriter`
+///
+/// Created by `quarantine` of the readers, errors writing rejects are returned by the iterator.
+pub struct Quarantine<I, W> {
+    pub(crate) rows: I,
+    rejects: RejectWriter<W>,
+}
+
+impl<I, W: Write> Quarantine<I, W> {
+    pub(crate) fn new(rows: I, rejects: RejectWriter<W>) -> Quarantine<I, W> {
+        Quarantine {rows, rejects}
+    }
+
+    pub(crate) fn write_rejected(&mut self, rejected: Vec<Rejected>) -> Result<()> {
+        for rejected in rejected.iter() {
+            self.rejects.write(rejected)?;
+        }
+        Ok(())
+    }
+
+    /// Return the writer of rejects
+    pub fn into_rejects(self) -> RejectWriter<W> {
+        self.rejects
+    }
+}
+
+/// Reader keeping a copy of the data consumed since the last `clear` while recording is enabled
+pub struct Recorder<R> {
+    inner: R,
+    enabled: bool,
+    buf: Vec<u8>,
+    offset: u64,
+}
+
+impl<R> Recorder<R> {
+    pub fn new(inner: R) -> Recorder<R> {
+        Recorder {
+            inner,
+            enabled: false,
+            buf: Vec::new(),
+            offset: 0
+        }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.inner
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    /// Discard recorded data, recording continues at byte offset
+    pub fn clear(&mut self, offset: u64) {
+        self.buf.clear();
+        self.offset = offset;
+    }
+
+    /// Data consumed from byte offset `from` on, empty if recording is disabled
+    pub fn recorded(&self, from: u64) -> &[u8] {
+        let start = from.saturating_sub(self.offset) as usize;
+        self.buf.get(start..).unwrap_or(&[])
+    }
+}
+
+impl<R: BufRead> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let data = self.fill_buf()?;
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for Recorder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.enabled {
+            // Data being consumed is always already buffered, filling does no I/O
+            if let Ok(data) = self.inner.fill_buf() {
+                self.buf.extend_from_slice(&data[..amt.min(data.len())]);
+            }
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Remove the first of terminators found at the end of data
+pub fn strip_terminator(raw: &mut Vec<u8>, terminators: &[&str]) {
+    if let Some(t) = terminators.iter().find(|t| raw.ends_with(t.as_bytes())) {
+        raw.truncate(raw.len() - t.len());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{BufRead, Read};
+
+    use encoding_rs::UTF_16LE;
+
+    use crate::dsv::{read_rows, ByteRecord, ConfigBuilder, CSV};
+    use crate::error::{Error, Position};
+
+    use super::{strip_terminator, Recorder, Rejected, RejectWriter};
+
+    #[test]
+    fn consumed_data_is_recorded_when_enabled() {
+        let mut reader = Recorder::new(&b"abcdef"[..]);
+        reader.read_exact(&mut [0; 2]).unwrap();
+        assert_eq!(reader.recorded(0), b"");
+        reader.set_enabled(true);
+        reader.clear(2);
+        reader.consume(1);
+        reader.read_exact(&mut [0; 2]).unwrap();
+        assert_eq!(reader.recorded(2), b"cde");
+        assert_eq!(reader.recorded(4), b"e");
+        reader.clear(5);
+        assert_eq!(reader.recorded(5), b"");
+    }
+
+    #[test]
+    fn longest_terminator_is_stripped() {
+        let mut raw = b"a,b\r\n".to_vec();
+        strip_terminator(&mut raw, &["\r\n", "\n", "\r"]);
+        assert_eq!(raw, b"a,b");
+        strip_terminator(&mut raw, &["\n"]);
+        assert_eq!(raw, b"a,b");
+    }
+
+    #[test]
+    fn invalid_raw_data_is_written_unchanged() {
+        let raw = b"\"c\xff\"\"d\"x,\xef\xbf\xbd\xfe\xc3".to_vec();
+        let data = [&b"a,b\r\n"[..], &raw, b"\r\ne,f"].concat();
+        let mut rows = read_rows(CSV, &data[..]).quarantine(RejectWriter::new(CSV, Vec::new()));
+        assert_eq!(rows.by_ref().filter(|row| row.is_ok()).count(), 2);
+        let rejects = rows.into_rejects().into_inner();
+        let mut record = ByteRecord::new();
+        assert!(read_rows(CSV, &rejects[..]).read_byte_record(&mut record).unwrap());
+        assert_eq!(record.get(0), Some(&raw[..]));
+
+    }
+
+    #[test]
+    fn invalid_raw_data_is_an_error_in_other_encodings() {
+        let config = ConfigBuilder::new(CSV).encoding(UTF_16LE).build();
+        let mut rejects = RejectWriter::new(config, Vec::new());
+        rejects.write(&Rejected {error: Error::InvalidUtf8(Position::new()), raw: b"\"\xc3\xa9,".to_vec()}).unwrap();
+        let res = rejects.write(&Rejected {error: Error::InvalidUtf8(Position::new()), raw: b"a\xff".to_vec()});
+        assert!(matches!(res, Err(Error::InvalidUtf8(Position {record: 1, field: 0, ..}))), "{:?}", res);
+        rejects.write(&Rejected {error: Error::InvalidUtf8(Position::new()), raw: b"b".to_vec()}).unwrap();
+        let rejects = rejects.into_inner();
+        let rows: Vec<_> = read_rows(config, &rejects[..]).map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0][0], "\"\u{e9},");
+        assert_eq!(rows[1][0], "b");
+    }
+}
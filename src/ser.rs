@@ -0,0 +1,418 @@
+//! Serialization of user types into rows with serde
+use std::error;
+use std::fmt;
+use std::str;
+
+use serde::ser::{self, Serialize};
+
+use crate::common::{NullableRow, Row};
+use crate::error::{Error, Position, Result};
+
+#[derive(Debug)]
+struct SerError(String);
+
+impl fmt::Display for SerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl error::Error for SerError {}
+
+impl ser::Error for SerError {
+    fn custom<T: fmt::Display>(msg: T) -> SerError {
+        SerError(msg.to_string())
+    }
+}
+
+type SerResult<T> = ::std::result::Result<T, SerError>;
+
+/// Serialized row with column names, names are only known when the value is a struct or a map
+pub struct Serialized {
+    /// Columns with `None` for null columns serialized from `None`
+    pub row: NullableRow,
+    pub names: Option<Row>,
+}
+
+impl Serialized {
+    /// Columns with null columns as empty columns
+    pub fn into_row(self) -> Row {
+        self.row.into_iter().map(Option::unwrap_or_default).collect()
+    }
+}
+
+/// Serialize value into a row, nested sequences and structs are flattened into columns
+pub fn serialize_row<S: Serialize + ?Sized>(value: &S, position: Position) -> Result<Serialized> {
+    let mut ser = RowSerializer {
+        row: Vec::new(),
+        names: Vec::new(),
+        name: None,
+        named: false
+    };
+    match value.serialize(&mut ser) {
+        Ok(()) => Ok(Serialized {
+            names: if ser.named { Some(ser.names) } else { None },
+            row: ser.row
+        }),
+        Err(SerError(msg)) => Err(Error::Serialize(msg, Position {field: ser.row.len() as u64, ..position}))
+    }
+}
+
+struct RowSerializer {
+    row: NullableRow,
+    names: Row,
+    name: Option<String>,
+    named: bool,
+}
+
+impl RowSerializer {
+    fn push(&mut self, col: String) -> SerResult<()> {
+        self.push_nullable(Some(col))
+    }
+
+    fn push_nullable(&mut self, col: Option<String>) -> SerResult<()> {
+        self.row.push(col);
+        self.names.push(self.name.clone().unwrap_or_default());
+        Ok(())
+    }
+
+    fn named<T: Serialize + ?Sized>(&mut self, name: String, value: &T) -> SerResult<()> {
+        self.named = true;
+        let outer = self.name.replace(name);
+        value.serialize(&mut *self)?;
+        self.name = outer;
+        Ok(())
+    }
+}
+
+macro_rules! serialize_display {
+    ($($method:ident $ty:ty,)*) => {
+        $(
+            fn $method(self, v: $ty) -> SerResult<()> {
+                self.push(v.to_string())
+            }
+        )*
+    }
+}
+
+impl ser::Serializer for &mut RowSerializer {
+    type Ok = ();
+    type Error = SerError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = ser::Impossible<(), SerError>;
+    type SerializeMap = Self;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = ser::Impossible<(), SerError>;
+
+    serialize_display! {
+        serialize_bool bool,
+        serialize_i8 i8,
+        serialize_i16 i16,
+        serialize_i32 i32,
+        serialize_i64 i64,
+        serialize_i128 i128,
+        serialize_u8 u8,
+        serialize_u16 u16,
+        serialize_u32 u32,
+        serialize_u64 u64,
+        serialize_u128 u128,
+        serialize_f32 f32,
+        serialize_f64 f64,
+        serialize_char char,
+    }
+
+    fn serialize_str(self, v: &str) -> SerResult<()> {
+        self.push(v.to_string())
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> SerResult<()> {
+        match str::from_utf8(v) {
+            Ok(s) => self.push(s.to_string()),
+            Err(_) => Err(ser::Error::custom("bytes are not valid UTF-8"))
+        }
+    }
+
+    fn serialize_none(self) -> SerResult<()> {
+        self.push_nullable(None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> SerResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> SerResult<()> {
+        self.push(String::new())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> SerResult<()> {
+        self.push(String::new())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str) -> SerResult<()> {
+        self.push(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> SerResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32, _variant: &'static str, value: &T) -> SerResult<()> {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> SerResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> SerResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> SerResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(self, name: &'static str, _index: u32, variant: &'static str, _len: usize) -> SerResult<Self::SerializeTupleVariant> {
+        Err(ser::Error::custom(format!("cannot serialize tuple variant {}::{}", name, variant)))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> SerResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> SerResult<Self> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(self, name: &'static str, _index: u32, variant: &'static str, _len: usize) -> SerResult<Self::SerializeStructVariant> {
+        Err(ser::Error::custom(format!("cannot serialize struct variant {}::{}", name, variant)))
+    }
+}
+
+impl ser::SerializeSeq for &mut RowSerializer {
+    type Ok = ();
+    type Error = SerError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> SerResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> SerResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut RowSerializer {
+    type Ok = ();
+    type Error = SerError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> SerResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> SerResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = SerError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> SerResult<()> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> SerResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for &mut RowSerializer {
+    type Ok = ();
+    type Error = SerError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> SerResult<()> {
+        let mut key_ser = RowSerializer {
+            row: Vec::new(),
+            names: Vec::new(),
+            name: None,
+            named: false
+        };
+        key.serialize(&mut key_ser)?;
+        self.name = Some(key_ser.row.into_iter().flatten().collect());
+        self.named = true;
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> SerResult<()> {
+        value.serialize(&mut **self)?;
+        self.name = None;
+        Ok(())
+    }
+
+    fn end(self) -> SerResult<()> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = SerError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> SerResult<()> {
+        self.named(key.to_string(), value)
+    }
+
+    fn end(self) -> SerResult<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Serialize, Serializer};
+
+    use crate::dsv::{self, from_str, Config, ConfigBuilder, StringRecord, CSV, NullMarker, UnquotedEmpty};
+    use crate::error::{Error, Position};
+    use crate::fixed::{self, ColumnConfig, Left, Right, Newline, LF};
+
+    static HEADERS: Config = Config {has_headers: true, ..CSV};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    enum Team {
+        #[serde(rename = "DAL")]
+        Dallas,
+        #[serde(rename = "NYG")]
+        Giants,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Play {
+        qtr: u8,
+        off: Team,
+        down: Option<u8>,
+        description: String,
+    }
+
+    fn serialized<S: Serialize>(config: Config, values: Vec<S>) -> String {
+        let mut buf = Vec::new();
+        dsv::serialize_rows(config, &mut buf, values).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn struct_with_header_row() {
+        let plays = vec!(Play {qtr: 1, off: Team::Dallas, down: None, description: "Kick, 69 yards".to_string()},
+                         Play {qtr: 4, off: Team::Giants, down: Some(3), description: "Pass".to_string()});
+        assert_eq!(serialized(HEADERS, plays), "qtr,off,down,description\r\n1,DAL,,\"Kick, 69 yards\"\r\n4,NYG,3,Pass\r\n");
+    }
+
+    #[test]
+    fn no_header_row_without_headers_config() {
+        assert_eq!(serialized(CSV, vec!(Play {qtr: 1, off: Team::Dallas, down: Some(1), description: "".to_string()})), "1,DAL,1,\r\n");
+    }
+
+    #[test]
+    fn no_header_row_for_tuples() {
+        assert_eq!(serialized(HEADERS, vec!(("a", 1.5, true), ("b", -2.0, false))), "a,1.5,true\r\nb,-2,false\r\n");
+    }
+
+    #[test]
+    fn nested_values_are_flattened() {
+        #[derive(Serialize)]
+        struct Game {
+            id: &'static str,
+            score: (u32, u32),
+            plays: Vec<u8>,
+        }
+        let game = Game {id: "20120905_DAL@NYG", score: (24, 17), plays: vec!(1, 2)};
+        assert_eq!(serialized(HEADERS, vec!(game)), "id,score,score,plays,plays\r\n20120905_DAL@NYG,24,17,1,2\r\n");
+    }
+
+    #[test]
+    fn map_keys_are_headers() {
+        let mut map = BTreeMap::new();
+        map.insert("a", 1);
+        map.insert("b", 2);
+        assert_eq!(serialized(HEADERS, vec!(map)), "a,b\r\n1,2\r\n");
+    }
+
+    fn two_decimals<S: Serializer>(value: &f64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:.2}", value))
+    }
+
+    fn date<S: Serializer>(value: &(u16, u8, u8), serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:04}-{:02}-{:02}", value.0, value.1, value.2))
+    }
+
+    #[test]
+    fn field_formatting_hooks() {
+        #[derive(Serialize)]
+        struct Game {
+            #[serde(serialize_with = "date")]
+            played: (u16, u8, u8),
+            #[serde(serialize_with = "two_decimals")]
+            yards_per_play: f64,
+        }
+        let game = Game {played: (2012, 9, 5), yards_per_play: 5.0 / 3.0};
+        assert_eq!(serialized(HEADERS, vec!(game)), "played,yards_per_play\r\n2012-09-05,1.67\r\n");
+    }
+
+    #[test]
+    fn serialized_rows_are_deserialized() {
+        let plays = vec!(Play {qtr: 1, off: Team::Dallas, down: None, description: "Kick\r\n\"69\" yards".to_string()});
+        let data = serialized(HEADERS, plays);
+        let read: Vec<Play> = from_str(HEADERS, &data).deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(read, vec!(Play {qtr: 1, off: Team::Dallas, down: None, description: "Kick\r\n\"69\" yards".to_string()}));
+    }
+
+    #[test]
+    fn none_is_written_as_null() {
+        let config = ConfigBuilder::new(HEADERS).null(NullMarker("NULL")).build();
+        let plays = vec!(Play {qtr: 2, off: Team::Giants, down: None, description: "NULL".to_string()});
+        let data = serialized(config, plays);
+        assert_eq!(data, "qtr,off,down,description\r\n2,NYG,NULL,\"NULL\"\r\n");
+        let mut rows = from_str(config, &data);
+        let mut record = StringRecord::new();
+        assert!(rows.read_record(&mut record).unwrap());
+        assert!(record.is_null(2) && !record.is_null(3));
+        let read: Vec<Play> = from_str(config, &data).deserialize().map(|r| r.unwrap()).collect();
+        assert_eq!(read, vec!(Play {qtr: 2, off: Team::Giants, down: None, description: "NULL".to_string()}));
+        let config = ConfigBuilder::new(CSV).null(UnquotedEmpty).build();
+        assert_eq!(serialized(config, vec!((None::<u8>, Some(""), 1))), ",\"\",1\r\n");
+        let mut writer = dsv::Writer::new(ConfigBuilder::new(CSV).null(NullMarker("\\N")).build(), Vec::new());
+        writer.serialize(&(None::<u8>, 1)).unwrap();
+        assert_eq!(writer.into_inner().unwrap(), b"\\N,1\r\n");
+    }
+
+    #[test]
+    fn error_on_struct_variant() {
+        #[derive(Serialize)]
+        enum Value {
+            Plain,
+            Pair {a: u8},
+        }
+        let mut buf = Vec::new();
+        let res = dsv::serialize_rows(CSV, &mut buf, vec!((1, Value::Plain), (3, Value::Pair {a: 4})));
+        match res {
+            Err(Error::Serialize(_, position)) => assert_eq!(position, Position {record: 1, field: 1, line: 2, byte: 9}),
+            res => panic!("Unexpected result: {:?}", res)
+        }
+        assert_eq!(&buf[..], b"1,Plain\r\n");
+    }
+
+    #[test]
+    fn fixed_width_values() {
+        let config = fixed::Config::new(vec!(ColumnConfig {width: 3, pad_with: ' ', justification: Left},
+                                             ColumnConfig {width: 2, pad_with: '0', justification: Right}),
+                                        Newline(LF));
+        let mut buf = Vec::new();
+        fixed::serialize_rows(config, &mut buf, vec!((Team::Dallas, 7), (Team::Giants, 24))).unwrap();
+        assert_eq!(&buf[..], b"DAL07\nNYG24\n");
+    }
+}
@@ -0,0 +1,288 @@
+//! Inference of DSV dialect from a sample of data
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::io::Read;
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::common::{LineTerminator, Row, CR, CRLF, LF};
+use crate::dsv::{read_rows, Config, ConfigBuilder, Escape, CSV, Double, Char, Lossy, Exact, Universal};
+use crate::error::Result;
+
+const DELIMITERS: [char; 4] = [',', ';', '\t', '|'];
+
+const QUOTES: [char; 2] = ['"', '\''];
+
+const ESCAPES: [Escape; 2] = [Double, Char('\\')];
+
+/// Number of rows needed for full confidence
+const CONFIDENT_ROWS: usize = 10;
+
+/// Configuration inferred by `sniff`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sniffed {
+    /// Inferred configuration, settings that can not be inferred are the same as in `CSV`
+    pub config: Config,
+    /// Confidence between 0 and 1, the share of sampled rows with the most common number of
+    /// columns, reduced when there are few rows
+    pub confidence: f64,
+}
+
+/// Infer the configuration of DSV data from its first `sample_bytes` bytes
+///
+/// Delimiter is one of `,`, `;`, tab or `|`, quote character `"` or `'`, escaping either
+/// doubled quotes or a backslash. Data with mixed line terminators is read with `Universal`
+/// newlines. The header is detected by comparing the first row with
+/// the types and lengths of the columns in the rest of the sample. The sample is consumed
+/// from the reader.
+///
+/// ```rust
+/// # use tabular::dsv::{sniff, LF};
+/// let data = "name;score\n\"Doe; John\";17\nJane;24\n";
+/// let sniffed = sniff(data.as_bytes(), 1024).unwrap();
+/// assert_eq!(sniffed.config.delimiter, ';');
+/// assert_eq!(sniffed.config.line_terminator, LF);
+/// assert!(sniffed.config.has_headers);
+/// ```
+pub fn sniff<R: Read>(reader: R, sample_bytes: usize) -> Result<Sniffed> {
+    let mut sample = Vec::new();
+    reader.take(sample_bytes as u64).read_to_end(&mut sample)?;
+    let truncated = sample.len() == sample_bytes;
+    let (encoding, text) = decode(&sample);
+    let mut text = &text[..];
+    if truncated {
+        if let Some(end) = text.rfind(['\n', '\r']) {
+            text = &text[..end + 1];
+        }
+    }
+    let (line_terminator, mixed) = line_terminator(text);
+    let newlines = if mixed { Universal } else { Exact };
+
+    let mut best: Option<(Candidate, Config)> = None;
+    for &delimiter in DELIMITERS.iter() {
+        for &quote_char in QUOTES.iter() {
+            for &escape in ESCAPES.iter() {
+                let config = ConfigBuilder::new(CSV)
+                    .delimiter(delimiter)
+                    .quote_char(quote_char)
+                    .escape(escape)
+                    .line_terminator(line_terminator)
+                    .newlines(newlines)
+                    .utf8(Lossy)
+                    .build();
+                let mut candidate = Candidate::parse(config, text, truncated);
+                candidate.quotes = text.matches(quote_char).count();
+                let better = match best {
+                    Some((ref b, _)) => candidate.is_better(b),
+                    None => true
+                };
+                if better {
+                    best = Some((candidate, config));
+                }
+            }
+        }
+    }
+    let (candidate, config) = best.unwrap();
+    if candidate.score() == 0.0 {
+        let config = ConfigBuilder::new(CSV)
+            .line_terminator(line_terminator)
+            .newlines(newlines)
+            .encoding(encoding)
+            .build();
+        return Ok(Sniffed {config, confidence: 0.0})
+    }
+    let config = ConfigBuilder::new(config)
+        .utf8(CSV.utf8)
+        .has_headers(has_headers(&candidate.rows))
+        .encoding(encoding)
+        .build();
+    let confidence = candidate.score() * candidate.rows.len().min(CONFIDENT_ROWS) as f64 / CONFIDENT_ROWS as f64;
+    Ok(Sniffed {config, confidence})
+}
+
+fn decode(sample: &[u8]) -> (&'static Encoding, String) {
+    match Encoding::for_bom(sample) {
+        Some((encoding, len)) => (encoding, encoding.decode_without_bom_handling(&sample[len..]).0.into_owned()),
+        None => (UTF_8, String::from_utf8_lossy(sample).into_owned())
+    }
+}
+
+/// Most common line terminator, CRLF if there is none, and whether different terminators are mixed
+fn line_terminator(text: &str) -> (LineTerminator, bool) {
+    let (mut crlf, mut lf, mut cr) = (0, 0, 0);
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\r' if chars.peek() == Some(&'\n') => {
+                chars.next();
+                crlf += 1;
+            }
+            '\r' => cr += 1,
+            '\n' => lf += 1,
+            _ => ()
+        }
+    }
+    let mixed = [crlf, lf, cr].iter().filter(|&&n| n > 0).count() > 1;
+    if crlf >= lf && crlf >= cr {
+        (CRLF, mixed)
+    } else if lf >= cr {
+        (LF, mixed)
+    } else {
+        (CR, mixed)
+    }
+}
+
+/// Rows of the sample parsed with a candidate configuration
+struct Candidate {
+    rows: Vec<Row>,
+    columns: usize,
+    consistency: f64,
+    failed: bool,
+    quotes: usize,
+}
+
+impl Candidate {
+    fn parse(config: Config, text: &str, truncated: bool) -> Candidate {
+        let mut rows = Vec::new();
+        let mut failed = false;
+        for row in read_rows(config, text.as_bytes()) {
+            match row {
+                Ok(row) => rows.push(row),
+                Err(_) => {
+                    failed = !truncated;
+                    break
+                }
+            }
+        }
+        let mut counts = HashMap::new();
+        for row in rows.iter() {
+            *counts.entry(row.len()).or_insert(0) += 1;
+        }
+        let (columns, count) = counts.into_iter()
+            .max_by_key(|&(columns, count)| (count, columns))
+            .unwrap_or((0, 0));
+        let consistency = if rows.is_empty() { 0.0 } else { count as f64 / rows.len() as f64 };
+        Candidate {rows, columns, consistency, failed, quotes: 0}
+    }
+
+    fn score(&self) -> f64 {
+        if self.columns < 2 {
+            0.0
+        } else if self.failed {
+            self.consistency / 2.0
+        } else {
+            self.consistency
+        }
+    }
+
+    /// Compare by score, then by number of columns and occurrences of the quote character
+    fn is_better(&self, other: &Candidate) -> bool {
+        match self.score().partial_cmp(&other.score()) {
+            Some(Ordering::Equal) => (self.columns, self.quotes) > (other.columns, other.quotes),
+            ordering => ordering == Some(Ordering::Greater)
+        }
+    }
+}
+
+fn is_numeric(col: &str) -> bool {
+    col.trim().parse::<f64>().is_ok()
+}
+
+/// Header is present if its columns differ from the rest in type or length in most columns
+fn has_headers(rows: &[Row]) -> bool {
+    let (header, data) = match rows.split_first() {
+        Some((header, data)) if !data.is_empty() => (header, data),
+        _ => return false
+    };
+    let mut votes = 0i32;
+    for (i, name) in header.iter().enumerate() {
+        let values: Vec<&str> = data.iter().filter_map(|row| row.get(i)).map(|col| &col[..]).collect();
+        if values.is_empty() {
+            continue
+        }
+        if values.iter().all(|col| is_numeric(col)) {
+            votes += if is_numeric(name) { -1 } else { 1 };
+        } else {
+            let len = values[0].chars().count();
+            if values.iter().all(|col| col.chars().count() == len) {
+                votes += if name.chars().count() == len { -1 } else { 1 };
+            }
+        }
+    }
+    votes > 0
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::{CR, CRLF, LF};
+    use crate::dsv::{Double, Char, Universal};
+    use crate::encoding::UTF_16LE;
+
+    use super::sniff;
+
+    #[test]
+    fn delimiter_is_inferred() {
+        for &delimiter in [',', ';', '\t', '|'].iter() {
+            let data = format!("a{0}b{0}c\r\n1{0}2{0}3\r\n4{0}5{0}6\r\n", delimiter);
+            let sniffed = sniff(data.as_bytes(), 1024).unwrap();
+            assert_eq!(sniffed.config.delimiter, delimiter);
+            assert_eq!(sniffed.config.line_terminator, CRLF);
+        }
+    }
+
+    #[test]
+    fn quoted_delimiter_does_not_count() {
+        let data = "'a;b',c\n'd;e',f\n'g;h',i\n";
+        let sniffed = sniff(data.as_bytes(), 1024).unwrap();
+        assert_eq!(sniffed.config.delimiter, ',');
+        assert_eq!(sniffed.config.quote_char, '\'');
+        assert_eq!(sniffed.config.line_terminator, LF);
+    }
+
+    #[test]
+    fn escape_is_inferred() {
+        let data = "\"say \\\"hi\\\"\",1\r\n\"x\",2\r\n";
+        assert_eq!(sniff(data.as_bytes(), 1024).unwrap().config.escape, Char('\\'));
+        let data = "\"say \"\"hi\"\"\",1\r\n\"x\",2\r\n";
+        assert_eq!(sniff(data.as_bytes(), 1024).unwrap().config.escape, Double);
+    }
+
+    #[test]
+    fn mixed_line_terminators_are_universal() {
+        let sniffed = sniff("a,b\n1,2\r\n3,4\n".as_bytes(), 1024).unwrap();
+        assert_eq!(sniffed.config.line_terminator, LF);
+        assert_eq!(sniffed.config.newlines, Universal);
+        assert_eq!(sniffed.confidence, 0.3);
+    }
+
+    #[test]
+    fn header_is_detected() {
+        let sniffed = sniff("id|name\r1|ab\r2|cd\r".as_bytes(), 1024).unwrap();
+        assert_eq!(sniffed.config.line_terminator, CR);
+        assert!(sniffed.config.has_headers);
+        let sniffed = sniff("0|ab\r1|ab\r2|cd\r".as_bytes(), 1024).unwrap();
+        assert!(!sniffed.config.has_headers);
+    }
+
+    #[test]
+    fn truncated_sample_is_cut_at_line_end() {
+        let data = "a,b\r\n1,2\r\n3,\"4\r\n5\"\r\n";
+        let sniffed = sniff(data.as_bytes(), 14).unwrap();
+        assert_eq!(sniffed.config.delimiter, ',');
+        assert!(sniffed.confidence > 0.0 && sniffed.confidence <= 0.3, "{}", sniffed.confidence);
+    }
+
+    #[test]
+    fn bom_sets_encoding() {
+        let sniffed = sniff(&b"\xff\xfea\x00;\x00b\x00\n\x00"[..], 1024).unwrap();
+        assert_eq!(sniffed.config.encoding, UTF_16LE);
+        assert_eq!(sniffed.config.delimiter, ';');
+    }
+
+    #[test]
+    fn no_confidence_without_delimiters() {
+        let sniffed = sniff("abc\ndef\n".as_bytes(), 1024).unwrap();
+        assert_eq!(sniffed.confidence, 0.0);
+        assert_eq!(sniffed.config.delimiter, ',');
+    }
+}
@@ -25,15 +25,56 @@
 //!
 //! Since rows are read as a lazy Iterator you have the flexibility of the Iterator api to control the iteration and row transformation.
 //!
+//! # Errors
+//!
+//! Reading and writing report failures as `Error`, every error except I/O errors carries
+//! the `Position` (record, field, line and byte offset) where it occurred.
+//! Readers stop at the first error by default, except rows with unequal lengths that were read
+//! completely, which are returned as errors and reading continues. With the `recovery` option of
+//! `Rows` bad records are skipped or collected with their raw data and reading continues.
+//!
+//! # Character encodings
+//!
+//! Data is UTF-8 by default, other encodings are set with the `encoding` option of each format
+//! using the encodings re-exported in `encoding`. Byte order marks are detected when reading
+//! and written when the `bom` option is set.
+//!
 //! # Encoder/Decoder api
 //!
-//! There is no support for Encoder and Decoder api currently (or similar) but library is designed for such extension in the future.
-//! Currently best way to achieve similar functionality is mapping a custom decoder over the Rows iterator.
-#![crate_name = "tabular"]
-#![license = "MIT/ASL2"]
-#![crate_type = "lib"]
-
+//! Rows can be decoded into any type implementing serde `Deserialize` with `Rows::deserialize`.
+//! Struct fields are matched by header name when the data has headers, by position otherwise.
+//! Values implementing serde `Serialize` are encoded with `serialize_rows` of each format.
+//!
+//! ```rust
+//! use serde::Deserialize;
+//! use tabular::dsv::{from_str, ConfigBuilder, CSV};
+//!
+//! #[derive(Deserialize)]
+//! struct Play {
+//!     gameid: String,
+//!     qtr: u8,
+//!     down: Option<u8>,
+//! }
+//!
+//! let config = ConfigBuilder::new(CSV).has_headers(true).build();
+//! let data = "gameid,qtr,down\r\n20120905_DAL@NYG,1,\r\n20120905_DAL@NYG,1,2";
+//! let plays: Vec<Play> = from_str(config, data).deserialize().collect::<Result<_, _>>().unwrap();
+//! assert_eq!(plays[1].down, Some(2));
+//! ```
 mod common;
+mod de;
+mod dfa;
+mod error;
+mod mmap;
+mod par;
+mod record;
+mod recovery;
+mod ser;
+mod sniff;
+mod transcode;
+
+pub use error::{Error, Position, Result};
+pub use encoding_rs as encoding;
 
 pub mod dsv;
 pub mod fixed;
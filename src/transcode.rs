@@ -0,0 +1,269 @@
+//! Transcoding between UTF-8 and other character encodings
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::str;
+
+use encoding_rs::{CoderResult, Decoder, Encoding, EncoderResult, UTF_8, UTF_16BE, UTF_16LE};
+
+use crate::error::{Error, Position};
+
+const BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reader decoding data in encoding into UTF-8
+///
+/// A byte order mark at the beginning of data is removed and overrides the encoding.
+/// UTF-8 data is passed through without copying, invalid sequences in other encodings
+/// are replaced with U+FFFD.
+pub struct DecodeReader<R> {
+    inner: R,
+    encoding: &'static Encoding,
+    decoder: Option<Decoder>,
+    bom_checked: bool,
+    /// Length of the byte order mark removed from the data
+    bom_len: u64,
+    buf: Box<[u8]>,
+    start: usize,
+    end: usize,
+    eof: bool,
+}
+
+impl<R: BufRead> DecodeReader<R> {
+    pub fn new(inner: R, encoding: &'static Encoding) -> DecodeReader<R> {
+        DecodeReader {
+            inner,
+            encoding,
+            decoder: None,
+            bom_checked: false,
+            bom_len: 0,
+            buf: Box::new([]),
+            start: 0,
+            end: 0,
+            eof: false
+        }
+    }
+
+    /// Underlying reader if its data is passed through without decoding, known once the byte order mark was checked
+    pub fn passthrough(&self) -> Option<&R> {
+        if self.bom_checked && self.decoder.is_none() {
+            Some(&self.inner)
+        } else {
+            None
+        }
+    }
+
+    /// Encoding of the data, overridden by its byte order mark once it was checked
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    fn check_bom(&mut self) -> io::Result<()> {
+        self.bom_checked = true;
+        let bom = Encoding::for_bom(self.inner.fill_buf()?);
+        if let Some((encoding, len)) = bom {
+            self.inner.consume(len);
+            self.encoding = encoding;
+            self.bom_len = len as u64;
+        }
+        if self.encoding != UTF_8 {
+            self.decoder = Some(self.encoding.new_decoder_without_bom_handling());
+            self.buf = vec![0; BUFFER_SIZE].into_boxed_slice();
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead + Seek> DecodeReader<R> {
+    /// Continue reading at byte offset of the data after the byte order mark
+    pub fn seek_data(&mut self, offset: u64) -> io::Result<()> {
+        if !self.bom_checked {
+            self.check_bom()?;
+        }
+        self.inner.seek(SeekFrom::Start(self.bom_len + offset))?;
+        if self.decoder.is_some() {
+            self.decoder = Some(self.encoding.new_decoder_without_bom_handling());
+            self.start = 0;
+            self.end = 0;
+            self.eof = false;
+        }
+        Ok(())
+    }
+
+    /// Length of the data after the byte order mark, reading continues where it was
+    pub fn data_len(&mut self) -> io::Result<u64> {
+        if !self.bom_checked {
+            self.check_bom()?;
+        }
+        let current = self.inner.stream_position()?;
+        let end = self.inner.seek(SeekFrom::End(0))?;
+        self.inner.seek(SeekFrom::Start(current))?;
+        Ok(end.saturating_sub(self.bom_len))
+    }
+}
+
+impl<R: BufRead> Read for DecodeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = {
+            let data = self.fill_buf()?;
+            let n = data.len().min(buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for DecodeReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if !self.bom_checked {
+            self.check_bom()?;
+        }
+        let decoder = match self.decoder {
+            Some(ref mut decoder) => decoder,
+            None => return self.inner.fill_buf()
+        };
+        while self.start == self.end && !self.eof {
+            let src = self.inner.fill_buf()?;
+            let last = src.is_empty();
+            let (res, read, written, _) = decoder.decode_to_utf8(src, &mut self.buf, last);
+            self.inner.consume(read);
+            self.start = 0;
+            self.end = written;
+            self.eof = last && res == CoderResult::InputEmpty;
+        }
+        Ok(&self.buf[self.start..self.end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        if self.decoder.is_some() {
+            self.start = (self.start + amt).min(self.end);
+        } else {
+            self.inner.consume(amt);
+        }
+    }
+}
+
+/// Writer encoding UTF-8 data written to it into encoding
+///
+/// Every write must contain complete UTF-8 characters.
+pub struct EncodeWriter<'a, W: ?Sized + 'a> {
+    inner: &'a mut W,
+    encoding: &'static Encoding,
+    buf: Vec<u8>,
+    unmappable: Option<char>,
+}
+
+impl<'a, W: Write + ?Sized> EncodeWriter<'a, W> {
+    pub fn new(inner: &'a mut W, encoding: &'static Encoding) -> EncodeWriter<'a, W> {
+        EncodeWriter {
+            inner,
+            encoding,
+            buf: Vec::new(),
+            unmappable: None
+        }
+    }
+
+    /// Replace the I/O error caused by a character that can not be encoded with `Unencodable`
+    pub fn map_error(&self, err: Error, position: Position) -> Error {
+        match self.unmappable {
+            Some(ch) => Error::Unencodable(ch, position),
+            None => err
+        }
+    }
+}
+
+impl<'a, W: Write + ?Sized> Write for EncodeWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let s = str::from_utf8(buf).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        self.buf.clear();
+        if let Err(ch) = encode(self.encoding, s, &mut self.buf) {
+            self.unmappable = Some(ch);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "character can not be encoded"))
+        }
+        self.inner.write_all(&self.buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Encode UTF-8 string into encoding, error contains the first character that can not be encoded
+pub fn encode(encoding: &'static Encoding, s: &str, out: &mut Vec<u8>) -> Result<(), char> {
+    if encoding == UTF_16LE {
+        out.extend(s.encode_utf16().flat_map(|u| u.to_le_bytes()));
+    } else if encoding == UTF_16BE {
+        out.extend(s.encode_utf16().flat_map(|u| u.to_be_bytes()));
+    } else if encoding.output_encoding() == UTF_8 {
+        out.extend_from_slice(s.as_bytes());
+    } else {
+        let mut encoder = encoding.new_encoder();
+        let mut src = s;
+        out.reserve(s.len());
+        loop {
+            let (res, read) = encoder.encode_from_utf8_to_vec_without_replacement(src, out, true);
+            src = &src[read..];
+            match res {
+                EncoderResult::InputEmpty => break,
+                EncoderResult::OutputFull => out.reserve(src.len().max(16)),
+                EncoderResult::Unmappable(ch) => return Err(ch)
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Byte order mark of encoding, empty for encodings without one
+pub fn bom(encoding: &'static Encoding) -> &'static [u8] {
+    if encoding == UTF_8 {
+        b"\xEF\xBB\xBF"
+    } else if encoding == UTF_16LE {
+        b"\xFF\xFE"
+    } else if encoding == UTF_16BE {
+        b"\xFE\xFF"
+    } else {
+        b""
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use encoding_rs::{UTF_8, UTF_16LE, WINDOWS_1252};
+
+    use super::{encode, DecodeReader};
+
+    fn decode(data: &[u8], encoding: &'static encoding_rs::Encoding) -> String {
+        let mut s = String::new();
+        DecodeReader::new(data, encoding).read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn utf8_is_passed_through_without_bom() {
+        assert_eq!(decode(b"\xEF\xBB\xBFa,b", UTF_8), "a,b");
+        assert_eq!(decode(b"a,b", UTF_8), "a,b");
+    }
+
+    #[test]
+    fn bom_overrides_encoding() {
+        assert_eq!(decode(b"\xFF\xFEa\x00,\x00\xe9\x00", WINDOWS_1252), "a,\u{e9}");
+    }
+
+    #[test]
+    fn single_byte_encoding_is_decoded() {
+        assert_eq!(decode(b"caf\xe9 \x80", WINDOWS_1252), "caf\u{e9} \u{20ac}");
+    }
+
+    #[test]
+    fn unmappable_char_is_reported() {
+        let mut out = Vec::new();
+        assert_eq!(encode(WINDOWS_1252, "caf\u{e9}", &mut out), Ok(()));
+        assert_eq!(out, b"caf\xe9");
+        assert_eq!(encode(WINDOWS_1252, "a\u{3b1}", &mut Vec::new()), Err('\u{3b1}'));
+        out.clear();
+        assert_eq!(encode(UTF_16LE, "a\u{e9}", &mut out), Ok(()));
+        assert_eq!(out, b"a\x00\xe9\x00");
+    }
+}
@@ -0,0 +1,52 @@
+use std::fs::File;
+
+use tabular::dsv::{from_file, par_read_file, sniff, ConfigBuilder, CRLF, CSV, TSV};
+
+#[test]
+fn read_short_csv() {
+    let rows: Vec<_> = from_file(CSV, "data/short.csv").unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(rows.len(), 3000);
+    assert_eq!(rows[0][0], "gameid");
+    assert!(rows.iter().all(|r| r.len() == 13));
+}
+
+#[test]
+fn read_short_tsv() {
+    let rows: Vec<_> = from_file(TSV, "data/short.tsv").unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(rows.len(), 3000);
+    assert_eq!(rows[0][12], "season");
+    assert!(rows.iter().all(|r| r.len() == 14));
+}
+
+#[test]
+fn read_medium_csv() {
+    let rows: Vec<_> = from_file(CSV, "data/medium.csv").unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(rows.len(), 10000);
+}
+
+#[test]
+fn read_short_csv_with_headers() {
+    let config = ConfigBuilder::new(CSV).has_headers(true).build();
+    let mut records = from_file(config, "data/short.csv").unwrap().records();
+    assert_eq!(records.headers().unwrap().unwrap().len(), 13);
+    let record = records.next().unwrap().unwrap();
+    assert_eq!(record.get_by_name("gameid"), Some("20120905_DAL@NYG"));
+    assert_eq!(record.get_by_name("season"), Some("2012"));
+    assert_eq!(records.count(), 2998);
+}
+
+#[test]
+fn sniff_short_tsv() {
+    let sniffed = sniff(File::open("data/short.tsv").unwrap(), 64 * 1024).unwrap();
+    assert_eq!(sniffed.config.delimiter, '\t');
+    assert_eq!(sniffed.config.line_terminator, CRLF);
+    assert!(sniffed.config.has_headers);
+    assert!(sniffed.confidence > 0.9, "{}", sniffed.confidence);
+}
+
+#[test]
+fn par_read_medium_csv() {
+    let rows: Vec<_> = par_read_file(CSV, "data/medium.csv", 4).unwrap().map(|r| r.unwrap()).collect();
+    let expected: Vec<_> = from_file(CSV, "data/medium.csv").unwrap().map(|r| r.unwrap()).collect();
+    assert_eq!(rows, expected);
+}
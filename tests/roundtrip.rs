@@ -0,0 +1,180 @@
+use quickcheck::{Arbitrary, Gen, QuickCheck, TestResult};
+
+use std::fmt::Debug;
+use std::io::BufReader;
+
+use tabular::dsv::{read_rows, write_rows, write_nullable_rows, Config, ConfigBuilder, NullableRow, Row, StringRecord, Always, Backslash, Char, Minimal, NonNumeric, Universal, CSV, LF, TSV, NullMarker, UnquotedEmpty, Skip, CR, Disallowed, BorrowedRecord};
+
+/// Column made mostly of characters that are significant to the DSV format
+#[derive(Clone, Debug)]
+struct Column(String);
+
+const SPECIAL: &[char] = &[',', ';', '"', '\'', '\\', '#', '\r', '\n', ' ', '\t', '\u{85}', '\u{feff}', '1', '.', 'e', '-', 'a', 'N', '\u{e9}'];
+
+impl Arbitrary for Column {
+    fn arbitrary(g: &mut Gen) -> Column {
+        let len = usize::arbitrary(g) % 8;
+        let col = (0..len).map(|_| {
+            if bool::arbitrary(g) {
+                *g.choose(SPECIAL).unwrap()
+            } else {
+                char::arbitrary(g)
+            }
+        }).collect();
+        Column(col)
+    }
+
+    fn shrink(&self) -> Box<dyn Iterator<Item = Column>> {
+        Box::new(self.0.shrink().map(Column))
+    }
+}
+
+fn rows_of(columns: Vec<Vec<Column>>) -> Vec<Row> {
+    columns.into_iter()
+        .filter(|row| !row.is_empty())
+        .map(|row| row.into_iter().map(|col| col.0).collect())
+        .collect()
+}
+
+/// Rows written successfully are read back unchanged
+fn round_trips(config: Config, columns: Vec<Vec<Column>>) -> TestResult {
+    let rows = rows_of(columns);
+    let mut data = Vec::new();
+    if write_rows(config, &mut data, rows.clone().into_iter()).is_err() {
+        return TestResult::discard()
+    }
+    match read_rows(config, &data[..]).collect::<Result<Vec<_>, _>>() {
+        Ok(read) => TestResult::from_bool(read == rows),
+        Err(err) => TestResult::error(format!("{} reading {:?}", err, String::from_utf8_lossy(&data)))
+    }
+}
+
+/// Rows with null columns written successfully are read back unchanged
+fn nullable_round_trips(config: Config, columns: Vec<Vec<Option<Column>>>) -> TestResult {
+    let rows: Vec<NullableRow> = columns.into_iter()
+        .filter(|row| !row.is_empty())
+        .map(|row| row.into_iter().map(|col| col.map(|col| col.0)).collect())
+        .collect();
+    let mut data = Vec::new();
+    if write_nullable_rows(config, &mut data, rows.clone().into_iter()).is_err() {
+        return TestResult::discard()
+    }
+    let mut reader = read_rows(config, &data[..]);
+    let mut record = StringRecord::new();
+    let mut read = Vec::new();
+    loop {
+        match reader.read_record(&mut record) {
+            Ok(true) => read.push(record.to_nullable_row()),
+            Ok(false) => break,
+            Err(err) => return TestResult::error(format!("{} reading {:?}", err, String::from_utf8_lossy(&data)))
+        }
+    }
+    TestResult::from_bool(read == rows)
+}
+
+/// Check property over rows of up to 10 columns of up to 8 characters
+fn check<A: Arbitrary + Debug>(property: fn(A) -> TestResult) {
+    QuickCheck::new().rng(Gen::new(10)).tests(500).quickcheck(property);
+}
+
+#[test]
+fn minimal_quoting_round_trips() {
+    check(|columns| round_trips(CSV, columns));
+}
+
+#[test]
+fn always_quoting_round_trips() {
+    check(|columns| round_trips(ConfigBuilder::new(CSV).quote(Always).build(), columns));
+}
+
+#[test]
+fn non_numeric_quoting_round_trips() {
+    check(|columns| round_trips(ConfigBuilder::new(CSV).quote(NonNumeric).build(), columns));
+}
+
+#[test]
+fn dialect_round_trips() {
+    check(|columns| {
+        let config = ConfigBuilder::new(CSV)
+            .delimiter(';')
+            .quote_char('\'')
+            .escape(Char('\\'))
+            .line_terminator(LF)
+            .newlines(Universal)
+            .comment(Some('#'))
+            .quote(Minimal)
+            .build();
+        round_trips(config, columns)
+    });
+}
+
+#[test]
+fn backslash_escape_round_trips() {
+    check(|columns| round_trips(ConfigBuilder::new(TSV).escape(Backslash).line_terminator(LF).build(), columns));
+    check(|columns| round_trips(ConfigBuilder::new(CSV).escape(Backslash).comment(Some('#')).build(), columns));
+}
+
+#[test]
+fn nulls_round_trip() {
+    check(|columns| nullable_round_trips(ConfigBuilder::new(CSV).null(NullMarker("NULL")).build(), columns));
+    check(|columns| nullable_round_trips(ConfigBuilder::new(CSV).null(UnquotedEmpty).build(), columns));
+    check(|columns| nullable_round_trips(ConfigBuilder::new(TSV).escape(Backslash).line_terminator(LF).build(), columns));
+}
+
+/// Rows, errors and positions read from whole buffers are the same as read by the character reader
+/// and as read one byte at a time
+fn reads_like_character_reader(config: Config, columns: Vec<Column>) -> TestResult {
+    let data: String = columns.into_iter().map(|col| col.0).collect();
+    // Byte order mark is only detected when the buffer holds all of it
+    if data.starts_with('\u{feff}') {
+        return TestResult::discard()
+    }
+    let read = |rows: &mut dyn Iterator<Item = String>| rows.collect::<Vec<_>>();
+    let mut buffered = read_rows(config, data.as_bytes()).recovery(Skip);
+    let mut bytewise = read_rows(config, BufReader::with_capacity(1, data.as_bytes())).recovery(Skip);
+    let mut characters = read_rows(config, data.as_bytes()).recovery(Skip).without_dfa();
+    let expected = read(&mut characters.by_ref().map(|row| format!("{:?}", row)));
+    let actual = read(&mut buffered.by_ref().map(|row| format!("{:?}", row)));
+    let bytes = read(&mut bytewise.by_ref().map(|row| format!("{:?}", row)));
+    TestResult::from_bool(actual == expected && bytes == expected
+        && buffered.position() == characters.position() && bytewise.position() == characters.position())
+}
+
+#[test]
+fn buffered_reading_matches_character_reader() {
+    check(|columns| reads_like_character_reader(CSV, columns));
+    check(|columns| reads_like_character_reader(ConfigBuilder::new(CSV).newlines(Universal).null(UnquotedEmpty).build(), columns));
+    check(|columns| reads_like_character_reader(ConfigBuilder::new(CSV).escape(Char('\\')).line_terminator(LF).comment(Some('#')).build(), columns));
+    check(|columns| reads_like_character_reader(ConfigBuilder::new(CSV).escape(Disallowed).line_terminator(CR).flexible(false).build(), columns));
+    check(|columns| reads_like_character_reader(ConfigBuilder::new(TSV).null(NullMarker("a")).build(), columns));
+}
+
+/// Borrowed records have the same columns, nulls and errors as owned records, read to the same positions
+fn borrows_like_owned_reader(config: Config, columns: Vec<Column>) -> TestResult {
+    let data: String = columns.into_iter().map(|col| col.0).collect();
+    let mut owned = read_rows(config, data.as_bytes()).recovery(Skip);
+    let mut borrowed = read_rows(config, data.as_bytes()).recovery(Skip);
+    let (mut record, mut borrowed_record) = (StringRecord::new(), BorrowedRecord::new());
+    loop {
+        let expected = owned.read_record(&mut record).map(|more| more.then(|| record.to_nullable_row()));
+        let actual = borrowed.read_borrowed_record(&mut borrowed_record).map(|more| more.then(|| {
+            (0..borrowed_record.len())
+                .map(|i| (!borrowed_record.is_null(i)).then(|| borrowed_record[i].to_string()))
+                .collect::<Vec<_>>()
+        }));
+        if format!("{:?}", actual) != format!("{:?}", expected) || owned.position() != borrowed.position() {
+            return TestResult::error(format!("{:?} != {:?} reading {:?}", actual, expected, data))
+        }
+        if let Ok(None) = expected {
+            return TestResult::passed()
+        }
+    }
+}
+
+#[test]
+fn borrowed_reading_matches_owned_reader() {
+    check(|columns| borrows_like_owned_reader(CSV, columns));
+    check(|columns| borrows_like_owned_reader(ConfigBuilder::new(CSV).newlines(Universal).null(UnquotedEmpty).build(), columns));
+    check(|columns| borrows_like_owned_reader(ConfigBuilder::new(CSV).escape(Char('\\')).line_terminator(LF).flexible(false).build(), columns));
+    check(|columns| borrows_like_owned_reader(ConfigBuilder::new(TSV).null(NullMarker("a")).build(), columns));
+}